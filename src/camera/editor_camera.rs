@@ -18,6 +18,16 @@ impl EditorCamera {
         }
     }
 
+    /// Fit the camera to the screen, less what panels cover at its right and bottom
+    pub fn resize(
+        &mut self,
+        covered_w: f32,
+        covered_h: f32
+    ) {
+        self.camera_w = screen_width() - covered_w;
+        self.camera_h = screen_height() - covered_h;
+    }
+
     /// Cursor-following camera movement
     pub fn follow_cursor(
         &mut self, cursor_x_px: f32,
@@ -0,0 +1,431 @@
+// Build module, used by the <:mk> directive.
+//
+// <:mk> runs the build command in the background, as <:t> does, see
+// editor_terminal.rs. The command is the one given to <:mk>, or the
+// 'build' option of user.pref, or one picked by the project's type:
+//      Cargo.toml in the directory or above   : cargo build
+//      Makefile in the directory              : make
+//      .java files under the directory        : javac -d out <files>
+// The .java files are looked for BUILD_JAVA_SEARCH_TIME at most, so a
+// large directory doesn't hold up the editor.
+// Once it exits its output is parsed for rustc/cargo, gcc/clang and
+// javac diagnostics, kept as the quickfix list. <:cn>/<:cp> open the
+// next/previous one, <:cl> lists them in the console, and the lines
+// of errors and warnings are underlined in the text.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use ignore::WalkBuilder;
+use regex::Regex;
+
+use crate::console::editor_console_list::EditorConsoleListItem;
+use crate::console::editor_terminal::{EditorTerminal, EditorTerminalStatus};
+
+pub const BUILD_MAX_JAVA_FILES: usize = 1000;
+pub const BUILD_JAVA_SEARCH_TIME: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EditorSeverity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl EditorSeverity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EditorSeverity::Error => "error",
+            EditorSeverity::Warning => "warning",
+            EditorSeverity::Note => "note",
+        }
+    }
+
+    fn parse(
+        name: &str
+    ) -> EditorSeverity {
+        match name {
+            "error" | "fatal error" => EditorSeverity::Error,
+            "warning" => EditorSeverity::Warning,
+            _ => EditorSeverity::Note,
+        }
+    }
+}
+
+/// A compiler message about a place in a file, line and column are 0-based
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorDiagnostic {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub severity: EditorSeverity,
+    pub message: String,
+}
+
+pub struct EditorQuickfix {
+    pub items: Vec<EditorDiagnostic>,
+    /// The item <:cn>/<:cp> went to last
+    pub current: Option<usize>,
+    /// Directory of the running build, its output is parsed once it exits
+    building: Option<PathBuf>,
+}
+
+impl EditorQuickfix {
+    pub fn new() -> EditorQuickfix {
+        EditorQuickfix {
+            items: Vec::new(),
+            current: None,
+            building: None,
+        }
+    }
+
+    /// Wait for the build started in `dir`
+    pub fn start(
+        &mut self,
+        dir: &Path
+    ) {
+        self.building = Some(dir.to_path_buf());
+    }
+
+    /// Parse the build's output once it exits, returns how it went
+    pub fn update(
+        &mut self,
+        terminal: &EditorTerminal
+    ) -> Option<String> {
+        if self.building.is_none() || terminal.is_running() {
+            return None;
+        }
+
+        let dir = self.building.take()?;
+
+        self.items = parse_diagnostics(&terminal.output, &dir);
+        self.current = None;
+
+        let count = |severity: EditorSeverity| self.items.iter().filter(|d| d.severity == severity).count();
+        let (errors, warnings) = (count(EditorSeverity::Error), count(EditorSeverity::Warning));
+
+        Some(match terminal.status {
+            EditorTerminalStatus::Cancelled => "BuildCancelled <:mk>".to_string(),
+            EditorTerminalStatus::Exited(Some(0)) if warnings == 0 => "BuildSucceeded <:mk>".to_string(),
+            EditorTerminalStatus::Exited(Some(0)) => format!("BuildSucceeded with {} warnings <:cn>", warnings),
+            _ if errors + warnings == 0 => "BuildFailed, see the output <:to>".to_string(),
+            _ => format!("BuildFailed with {} errors, {} warnings <:cn>", errors, warnings),
+        })
+    }
+
+    /// The item after (true) or before (false) the current one, wrapping around
+    pub fn next(
+        &mut self,
+        forward: bool
+    ) -> Option<&EditorDiagnostic> {
+        if self.items.is_empty() {
+            return None;
+        }
+
+        let len = self.items.len();
+
+        let next = match self.current {
+            None if forward => 0,
+            None => len - 1,
+            Some(i) if forward => (i + 1) % len,
+            Some(i) => (i + len - 1) % len,
+        };
+
+        self.current = Some(next);
+        self.items.get(next)
+    }
+
+    /// The items as "file:line:column: severity: message" rows
+    pub fn list_items(
+        &self,
+        dir: &Option<PathBuf>
+    ) -> Vec<EditorConsoleListItem> {
+        self.items.iter()
+            .map(|d| {
+                let shown = dir.as_ref()
+                    .and_then(|dir| d.file.strip_prefix(dir).ok())
+                    .unwrap_or(&d.file)
+                    .display();
+
+                EditorConsoleListItem {
+                    label: format!("{}:{}:{}: {}: {}", shown, d.line + 1, d.column + 1, d.severity.name(), d.message),
+                    file: Some(d.file.clone()),
+                    line: d.line,
+                    column: d.column,
+                }
+            })
+            .collect()
+    }
+
+    /// Lines of `file` with errors or warnings, and the worst of each
+    pub fn underlines(
+        &self,
+        file: Option<&Path>
+    ) -> Vec<(usize, EditorSeverity)> {
+        let Some(file) = file.filter(|_| !self.items.is_empty()) else {
+            return Vec::new();
+        };
+
+        // Diagnostics' paths are canonical, see resolve_path
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+
+        let mut lines: Vec<(usize, EditorSeverity)> = self.items.iter()
+            .filter(|d| d.severity != EditorSeverity::Note && d.file == file)
+            .map(|d| (d.line, d.severity))
+            .collect();
+
+        lines.sort();
+        lines.dedup_by_key(|(line, _)| *line);
+        lines
+    }
+}
+
+/// The build command of the project in `dir`, by its type
+pub fn default_build_command(
+    dir: &Path
+) -> Option<String> {
+    if dir.ancestors().any(|d| d.join("Cargo.toml").is_file()) {
+        return Some("cargo build".to_string());
+    }
+
+    if ["Makefile", "makefile", "GNUmakefile"].iter().any(|m| dir.join(m).is_file()) {
+        return Some("make".to_string());
+    }
+
+    let started = Instant::now();
+
+    let sources: Vec<String> = WalkBuilder::new(dir)
+        .require_git(false)
+        .build()
+        .take_while(|_| started.elapsed() < BUILD_JAVA_SEARCH_TIME)
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|e| e == "java"))
+        .filter_map(|entry| entry.path().strip_prefix(dir).ok().map(|p| quote_argument(&p.to_string_lossy())))
+        .take(BUILD_MAX_JAVA_FILES)
+        .collect();
+
+    if sources.is_empty() {
+        return None;
+    }
+
+    Some(format!("javac -d out {}", sources.join(" ")))
+}
+
+/// `argument` quoted for the shell of editor_terminal.rs, sh and PowerShell
+/// both take '' strings, with a ' inside written as '\'' and '' respectively
+fn quote_argument(
+    argument: &str
+) -> String {
+    #[cfg(target_os = "windows")]
+    let escaped = argument.replace('\'', "''");
+
+    #[cfg(not(target_os = "windows"))]
+    let escaped = argument.replace('\'', "'\\''");
+
+    format!("'{}'", escaped)
+}
+
+/// Diagnostics of a build's output, paths are taken from `dir`
+pub fn parse_diagnostics(
+    output: &[String],
+    dir: &Path
+) -> Vec<EditorDiagnostic> {
+    // gcc/clang, javac and cargo's short format: file:line:[column:] severity: message
+    let located = Regex::new(r"^(\S.*?):(\d+):(?:(\d+):)?\s*(fatal error|error|warning|note)(?:\[[\w-]+\])?:\s*(.*)$").unwrap();
+    // rustc: a severity: message header, then --> file:line:column
+    let header = Regex::new(r"^(error|warning|note)(?:\[[\w-]+\])?:\s*(.*)$").unwrap();
+    let arrow = Regex::new(r"^\s*-->\s*(.+?):(\d+):(\d+)\s*$").unwrap();
+
+    let number = |s: Option<regex::Match>| s.and_then(|m| m.as_str().parse::<usize>().ok()).unwrap_or(1).saturating_sub(1);
+
+    let mut diagnostics: Vec<EditorDiagnostic> = Vec::new();
+    let mut pending: Option<(EditorSeverity, String)> = None;
+
+    for row in output {
+        let row = row.trim_end();
+
+        if let Some(c) = located.captures(row) {
+            pending = None;
+
+            diagnostics.push(EditorDiagnostic {
+                file: resolve_path(&c[1], dir),
+                line: number(c.get(2)),
+                column: number(c.get(3)),
+                severity: EditorSeverity::parse(&c[4]),
+                message: c[5].to_string(),
+            });
+        } else if let Some(c) = header.captures(row) {
+            pending = Some((EditorSeverity::parse(&c[1]), c[2].to_string()));
+        } else if let Some(c) = arrow.captures(row) && let Some((severity, message)) = pending.take() {
+            diagnostics.push(EditorDiagnostic {
+                file: resolve_path(&c[1], dir),
+                line: number(c.get(2)),
+                column: number(c.get(3)),
+                severity,
+                message,
+            });
+        }
+    }
+
+    // Cargo repeats diagnostics of files shared by several targets
+    let mut unique: Vec<EditorDiagnostic> = Vec::new();
+
+    for d in diagnostics {
+        if !unique.contains(&d) {
+            unique.push(d);
+        }
+    }
+
+    unique
+}
+
+/// The canonical path of a diagnostic's file, cargo prints
+/// paths from the workspace root, which may be above `dir`
+fn resolve_path(
+    file: &str,
+    dir: &Path
+) -> PathBuf {
+    let path = dir.ancestors()
+        .map(|d| d.join(file))
+        .find(|p| p.is_file())
+        .unwrap_or_else(|| dir.join(file));
+
+    fs::canonicalize(&path).unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::console::editor_test_dir::EditorTestDir;
+
+    /// An empty directory to resolve the fixtures' paths from
+    fn project(
+        name: &str
+    ) -> EditorTestDir {
+        EditorTestDir::new(&format!("build-{}", name))
+    }
+
+    fn output(
+        text: &str
+    ) -> Vec<String> {
+        text.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn rustc_headers_take_the_location_of_their_arrow() {
+        let dir = project("rustc");
+
+        let diagnostics = parse_diagnostics(&output(r#"   Compiling muse v0.1.0 (/home/me/muse)
+error[E0425]: cannot find value `x` in this scope
+ --> src/main.rs:2:13
+  |
+2 |     let y = x + 1;
+  |             ^ not found in this scope
+
+warning: unused variable: `y`
+ --> src/main.rs:2:9
+  |
+2 |     let y = x + 1;
+  |         ^ help: if this is intentional, prefix it with an underscore: `_y`
+  |
+  = note: `#[warn(unused_variables)]` on by default
+
+warning: `muse` (bin "muse") generated 1 warning
+error: could not compile `muse` (bin "muse") due to 1 previous error; 1 warning emitted
+"#), &dir);
+
+        assert_eq!(diagnostics, vec![
+            EditorDiagnostic {
+                file: dir.join("src/main.rs"),
+                line: 1,
+                column: 12,
+                severity: EditorSeverity::Error,
+                message: "cannot find value `x` in this scope".to_string(),
+            },
+            EditorDiagnostic {
+                file: dir.join("src/main.rs"),
+                line: 1,
+                column: 8,
+                severity: EditorSeverity::Warning,
+                message: "unused variable: `y`".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn summaries_without_a_location_are_left_out() {
+        let dir = project("summary");
+
+        let diagnostics = parse_diagnostics(&output(r#"warning: `muse` (bin "muse") generated 3 warnings
+    Finished `dev` profile [unoptimized + debuginfo] target(s) in 0.52s
+"#), &dir);
+
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn gcc_and_javac_lines() {
+        let dir = project("gcc");
+
+        let diagnostics = parse_diagnostics(&output(r#"main.c:1:10: fatal error: stdio.hh: No such file or directory
+    1 | #include <stdio.hh>
+      |          ^~~~~~~~~~
+compilation terminated.
+src/Main.java:3: error: ';' expected
+        int x = 1
+                 ^
+1 error
+"#), &dir);
+
+        assert_eq!(diagnostics, vec![
+            EditorDiagnostic {
+                file: dir.join("main.c"),
+                line: 0,
+                column: 9,
+                severity: EditorSeverity::Error,
+                message: "stdio.hh: No such file or directory".to_string(),
+            },
+            EditorDiagnostic {
+                file: dir.join("src/Main.java"),
+                line: 2,
+                column: 0,
+                severity: EditorSeverity::Error,
+                message: "';' expected".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn java_sources_are_quoted() {
+        let dir = project("java");
+
+        fs::create_dir_all(dir.join("my app")).unwrap();
+        fs::write(dir.join("my app/It's.java"), "class It {}\n").unwrap();
+
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(default_build_command(&dir), Some(r"javac -d out 'my app/It'\''s.java'".to_string()));
+
+        fs::remove_file(dir.join("my app/It's.java")).unwrap();
+        assert_eq!(default_build_command(&dir), None);
+    }
+
+    #[test]
+    fn workspace_paths_resolve_from_a_member_directory() {
+        let root = project("workspace");
+        let member = root.join("crates/app");
+
+        fs::create_dir_all(member.join("src")).unwrap();
+        fs::write(member.join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+
+        // cargo prints paths from the workspace root, the editor is in the member
+        let diagnostics = parse_diagnostics(&output(r#"warning: function `f` is never used
+ --> crates/app/src/lib.rs:1:8
+"#), &member);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, member.join("src/lib.rs"));
+        assert_eq!(diagnostics[0].severity, EditorSeverity::Warning);
+    }
+}
@@ -5,16 +5,17 @@ use macroquad::prelude::*;
 
 use crate::audio::editor_audio::*;
 use crate::options::editor_options::EditorOptions;
-use crate::options::editor_pallete::{
-    CONSOLE_CONTAINER_COLOR,
-    CONSOLE_CURSOR_COLOR,
-    CONSOLE_FRAME_COLOR
-};
+use crate::options::editor_pallete::pallete;
 use crate::console::editor_console_cursor::*;
+use crate::console::editor_console_list::EditorConsoleList;
+use crate::console::editor_file_finder::EditorFileFinder;
 use crate::console::editor_file_system::*;
 use crate::text::editor_cursor::*;
 use crate::text::editor_text_stylizer::*;
 use crate::console::editor_directives::*;
+use crate::text::editor_buffer::EditorBuffers;
+use crate::text::editor_history::EditorHistory;
+use crate::text::editor_search::EditorSearch;
 use crate::text::editor_language_manager::EditorLanguageKeywords;
 
 pub const CONSOLE_INITIAL_WIDTH: f32 = 250.0;
@@ -32,6 +33,10 @@ pub struct EditorConsole {
     pub width: f32,
     pub target_w: f32,
     pub vel_w: f32,
+    /// Results of list directives, e.g. <:grep>
+    pub list: EditorConsoleList,
+    /// Candidates of switch-to-file directives
+    pub finder: EditorFileFinder,
 }
 
 impl EditorConsole {
@@ -47,6 +52,8 @@ impl EditorConsole {
             width: CONSOLE_INITIAL_WIDTH,
             target_w: CONSOLE_INITIAL_WIDTH,
             vel_w: 1.0,
+            list: EditorConsoleList::new(),
+            finder: EditorFileFinder::new(),
         }
     }
 
@@ -86,7 +93,7 @@ impl EditorConsole {
             0.0,
             self.width,
             screen_height(),
-            CONSOLE_FRAME_COLOR
+            pallete().console_frame
         );
 
         // Console foreground
@@ -94,7 +101,7 @@ impl EditorConsole {
             0.0,
             self.width,
             screen_height(),
-            CONSOLE_CONTAINER_COLOR
+            pallete().console_container
         );
 
         draw_line(screen_width() - self.width,
@@ -102,7 +109,7 @@ impl EditorConsole {
             screen_width(),
             CONSOLE_MARGINS + 25.0,
             1.0,
-            CONSOLE_FRAME_COLOR
+            pallete().console_frame
         );
 
         let cursor_idx = char_to_byte(&self.directive, self.cursor.x);
@@ -119,7 +126,7 @@ impl EditorConsole {
             self.cursor.anim_x,
             CONSOLE_MARGINS + 20.0,
             2.0,
-            CONSOLE_CURSOR_COLOR
+            pallete().console_cursor
         );
 
         // Draw the directive written
@@ -162,8 +169,25 @@ impl EditorConsole {
         cursor: &mut EditorCursor,
         ops: &mut EditorOptions,
         elk: &mut EditorLanguageKeywords,
+        history: &mut EditorHistory,
+        buffers: &mut EditorBuffers,
+        search: &mut EditorSearch,
     ) {
+        // The list is navigated while nothing is typed
+        let on_list = self.list.active && self.directive.is_empty();
+
         if cursor.is_combo_active(KeyCode::Backspace, None) {
+            if on_list && let Some(filter) = &self.list.filter {
+                let mut filter = filter.clone();
+
+                if filter.pop().is_some() {
+                    self.list.set_filter(filter);
+                    audio.play_delete();
+                }
+
+                return;
+            }
+
             if self.cursor.x > 0 && !self.directive.is_empty() {
                 let mut byte_idx = char_to_byte(&self.directive, self.cursor.x - 1);
             
@@ -195,10 +219,38 @@ impl EditorConsole {
             self.lshift_shortcuts(audio);
         }
 
+        if on_list {
+            self.list.record_keys(cursor, audio);
+        }
+
+        if on_list && is_key_pressed(KeyCode::Enter) {
+            if let Some(item) = self.list.selected_item().cloned() {
+                if let Some(file) = item.file {
+                    open_file_at(file, item.line, item.column, efs, text, cursor, elk, history, buffers);
+                } else {
+                    // A location of the current buffer, e.g. an <:ol> symbol
+                    place_cursor(item.line, item.column, text, cursor);
+                }
+
+                audio.play_nav();
+            }
+
+            return;
+        }
+
+        // Switch-to-file directives pick among the finder's candidates
+        if !self.directive.starts_with(':') {
+            self.finder.record_keys(cursor, audio);
+        }
+
         if is_key_pressed(KeyCode::Enter) {
+            if !self.directive.starts_with(':') && let Some(selected) = self.finder.selected() {
+                self.directive = selected.to_string();
+            }
+
             // execute whatever is inside the directive string
             // check the directives' source
-            let message_and_manual_toggle = execute_directive(&mut self.directive, efs, text, cursor, ops, elk).clone();
+            let message_and_manual_toggle = execute_directive(&mut self.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut self.list).clone();
 
             // Update for rendering.
             self.message = message_and_manual_toggle.0;
@@ -211,6 +263,60 @@ impl EditorConsole {
         }
     }
 
+    /// Confirm-each substitute input, y/n/a/q, escape also quits
+    fn record_substitute_keys(
+        &mut self,
+        audio: &EditorAudio,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        history: &mut EditorHistory,
+        search: &mut EditorSearch,
+    ) {
+        let Some(substitute) = &mut search.substitute else {
+            return;
+        };
+
+        let mut done = is_key_pressed(KeyCode::Escape);
+
+        if let Some(c) = get_char_pressed() {
+            match c.to_ascii_lowercase() {
+                'y' => {
+                    substitute.replace_current(text);
+                    done = !substitute.next_match(text, cursor);
+                    audio.play_insert();
+                }
+
+                'n' => {
+                    substitute.skip_current(text);
+                    done = !substitute.next_match(text, cursor);
+                    audio.play_nav();
+                }
+
+                'a' => {
+                    substitute.replace_all(text, cursor);
+                    done = true;
+                    audio.play_insert();
+                }
+
+                'q' => done = true,
+
+                _ => {}
+            }
+        }
+
+        if done && let Some(substitute) = search.substitute.take() {
+            let count = substitute.finish(text, cursor, history);
+
+            if count > 0 {
+                efs.unsaved_changes = true;
+            }
+
+            self.message = format!("Replaced {} occurrence(s) <:s>", count);
+            self.showing_message = true;
+        }
+    }
+
     /// Record  heyboard input
     pub fn record_keyboard_to_console_text(
         &mut self,
@@ -220,8 +326,17 @@ impl EditorConsole {
         cursor: &mut EditorCursor,
         ops: &mut EditorOptions,
         elk: &mut EditorLanguageKeywords,
+        history: &mut EditorHistory,
+        buffers: &mut EditorBuffers,
+        search: &mut EditorSearch,
     ) {
-        self.record_special_console_keys(audio, efs, text, cursor, ops, elk);
+        // A confirm-each substitute takes over the console until it is done
+        if search.substitute.is_some() {
+            self.record_substitute_keys(audio, efs, text, cursor, history, search);
+            return;
+        }
+
+        self.record_special_console_keys(audio, efs, text, cursor, ops, elk, history, buffers, search);
 
         // Disable special characters from the console.
         if let Some(c) = get_char_pressed() {
@@ -229,6 +344,14 @@ impl EditorConsole {
                 return;
             }
 
+            // A filtered list takes what is typed, until a directive is started
+            if self.list.active && self.directive.is_empty() && c != ':'
+                && let Some(filter) = &self.list.filter {
+                self.list.set_filter(format!("{}{}", filter, c));
+                audio.play_insert();
+                return;
+            }
+
             match c {
                 _ => {
                     if c != ' ' { 
@@ -248,6 +371,34 @@ impl EditorConsole {
     }
 }
 
+/// Draw the names completing a directive's parameter, e.g. the <:epa> palletes,
+/// returns the completed directive if TAB was pressed
+pub fn draw_name_completions(
+    names: &[String],
+    command: &str,
+    directive: &str,
+    console_width: f32
+) -> Option<String> {
+    let query = directive[command.len()..].trim().to_lowercase();
+    let x = screen_width() - console_width + CONSOLE_MARGINS;
+    let mut y = 50.0 + CONSOLE_MARGINS;
+    let mut best_match = None;
+
+    for name in names.iter().filter(|name| name.to_lowercase().contains(&query)) {
+        let color = if best_match.is_none() { pallete().selected_file } else { pallete().file };
+        best_match.get_or_insert(name);
+
+        draw_text(name, x, y, 24.0, color);
+        y += 20.0;
+    }
+
+    if is_key_pressed(KeyCode::Tab) {
+        return best_match.map(|name| format!("{}{}", command, name));
+    }
+
+    None
+}
+
 /// Draws multi-line text
 pub fn draw_multiline_text_centered(
     text: &str,
@@ -288,7 +439,7 @@ pub fn console_message(
             0.0,
             screen_width(),
             screen_height(),
-            CONSOLE_FRAME_COLOR
+            pallete().console_frame
         );
 
         draw_rectangle(
@@ -296,7 +447,7 @@ pub fn console_message(
             1.0,
             screen_width() - 2.0,
             screen_height() - 2.0,
-            CONSOLE_CONTAINER_COLOR
+            pallete().console_container
         );
     } else {
         draw_rectangle(
@@ -304,7 +455,7 @@ pub fn console_message(
             screen_height() / 2.0 - height / 2.0 - 1.0,
             width + 1.0,
             height + 1.0,
-            CONSOLE_FRAME_COLOR
+            pallete().console_frame
         );
 
         draw_rectangle(
@@ -312,21 +463,21 @@ pub fn console_message(
             screen_height() / 2.0 - height / 2.0,
             width - 1.0,
             height - 1.0,
-            CONSOLE_CONTAINER_COLOR
+            pallete().console_container
         );
     }
 
     // Draw the message
     if is_manual {
         let start_y = 15.0;
-        draw_multiline_text_centered(msg, msg_font_size, CONSOLE_FRAME_COLOR, start_y);
+        draw_multiline_text_centered(msg, msg_font_size, pallete().console_frame, start_y);
     } else {
         draw_text(
             msg,
             screen_width() / 2.0 - text_width / 2.0,
             screen_height() / 2.0,
             msg_font_size as f32,
-            CONSOLE_FRAME_COLOR
+            pallete().console_frame
         );
     }
 
@@ -339,7 +490,7 @@ pub fn console_message(
         screen_width() / 2.0 - esc_width / 2.0,
         screen_height() / 2.0 + height / 2.0 - 20.0,
         msg_font_size as f32,
-        CONSOLE_FRAME_COLOR
+        pallete().console_frame
     );
 }
 
@@ -358,9 +509,45 @@ pub fn console_manual(man_id: u8) -> String {
                 :i          : Current file info display
                 :r <f>      : Remove a file with name 'f'
                 :b <f>      : Change the name of the current open file to 'f'
-                :f <f>      : Go to the line where the first iteration of text 'f' exists
+                :f <f>      : Search for text 'f', flags c/w/r for case, whole word, regex (:fcwr <f>)
+                :fn, :fp    : Go to the next/previous search match
+                :s/<p>/<r>/ : Replace regex 'p' with 'r', :s%/ whole file, :sN,M/ lines N-M
+                              flags g all, i case insensitive, c confirm each (y/n/a/q)
+                :grep <p>   : Search regex 'p' in all files under the directory, Enter opens a result
+                :ol [p]     : Outline the file's functions and types, typing filters, Enter jumps
+                :def [p]    : Go to the definition of 'p' or the identifier at the cursor, asks the server first
+                :back       : Go back to where the last :def jumped from
                 :c <f>      : Create a new file with name 'f'   
-                :t <c>      : Execute a command 'c' terminal
+                :t $ <c>    : Run a command 'c' in the background, output streams into a panel
+                :tk/to      : Kill the running command/show or hide the output panel
+                :pty        : Show/hide an interactive shell, F12 moves the keyboard to/from it
+                :ptk/ptd    : Kill the shell/dock it at the bottom or right
+                :mk [c]     : Build, with 'c' or the project's command, diagnostics are underlined
+                :cn/cp/cl   : Open the next/previous diagnostic of the build, or list them
+                :hv         : Show the line's diagnostics and the docs of the identifier at the cursor
+                :refs       : List the references of the identifier at the cursor, Enter opens one
+                :rn <n>     : Rename the identifier at the cursor to 'n' in every file
+                :u          : Undo the last change
+                :re         : Redo the last undone change
+                :mark       : Mark/unmark the cursor's line
+                :spot       : Go to the next mark, keep pressing enter to keep moving
+                :spot <N>   : Go to the N-th mark
+
+                Buffer specific directives:
+                :ls         : List the open buffers
+                :bn/bp      : Switch to the next/previous open buffer
+                :bs <N|f>   : Switch to the N-th open buffer, or the one of file 'f'
+                :bc/bc!     : Close the current buffer, ! discards unsaved changes
+
+                Git directives:
+                :gst        : List the changed files, Enter opens one
+                :gdi        : List the changes of the current file, Enter goes to the line
+                :gad/gun    : Stage/unstage the current file
+                :gcm <m>    : Commit the staged changes with message 'm'
+                :gn/gp      : Go to the next/previous change since HEAD
+                :grv        : Revert the change under the cursor
+                :glo        : Show the log of the current file
+                :gbr [b]    : Show the branches, or switch to branch 'b'
                     
                 Directory specific directives:
                 :cd         : Change directory                                        
@@ -370,12 +557,15 @@ pub fn console_manual(man_id: u8) -> String {
                 :bd <f>     : Change the name of the current open directory to 'f'
                                     
                 Configuration directives:
-                :epa <p>    : Change to pallete of name 'p'
-                :efn <p>    : Change to a font of name 'p'
+                :epa <p>    : Change to pallete of name 'p', :epa lists them
+                :efn <p>    : Change to a font of name 'p', :efn lists them
                 :esm        : Smart identation on/off switch
                 :eau        : Audio on/off switch
                 :efl        : Editor fullsreen on/off switch
-                :ehi        : Editor text highlighting on/off switch
+                :ehi [c]    : Editor text highlighting on/off switch, or of class 'c' only
+                :ela <l>    : Force the buffer's language to 'l', :ela auto detects it
+                :ebd <c>    : Set the build command of :mk to 'c', :ebd auto picks it by the project
+                :lsp        : Language servers (rust-analyzer, clangd, jdtls) on/off switch
                 :e/q                : Exit, close editor                                           
                     
                 Other directives:
@@ -399,9 +589,45 @@ pub fn console_manual(man_id: u8) -> String {
                         :i          : Current file info display
                         :r <f>      : Remove a file with name 'f'
                         :b <f>      : Change the name of the current open file to 'f'
-                        :f <f>      : Go to the line where the first iteration of text 'f' exists
+                        :f <f>      : Search for text 'f', flags c/w/r for case, whole word, regex (:fcwr <f>)
+                        :fn, :fp    : Go to the next/previous search match
+                        :s/<p>/<r>/ : Replace regex 'p' with 'r', :s%/ whole file, :sN,M/ lines N-M
+                                      flags g all, i case insensitive, c confirm each (y/n/a/q)
+                        :grep <p>   : Search regex 'p' in all files under the directory, Enter opens a result
+                        :ol [p]     : Outline the file's functions and types, typing filters, Enter jumps
+                        :def [p]    : Go to the definition of 'p' or the identifier at the cursor, asks the server first
+                        :back       : Go back to where the last :def jumped from
                         :c <f>      : Create a new file with name 'f'
-                        :t <c>      : Execute a command 'c' terminal
+                        :t $ <c>    : Run a command 'c' in the background, output streams into a panel
+                        :tk/to      : Kill the running command/show or hide the output panel
+                        :pty        : Show/hide an interactive shell, F12 moves the keyboard to/from it
+                        :ptk/ptd    : Kill the shell/dock it at the bottom or right
+                        :mk [c]     : Build, with 'c' or the project's command, diagnostics are underlined
+                        :cn/cp/cl   : Open the next/previous diagnostic of the build, or list them
+                        :hv         : Show the line's diagnostics and the docs of the identifier at the cursor
+                        :refs       : List the references of the identifier at the cursor, Enter opens one
+                        :rn <n>     : Rename the identifier at the cursor to 'n' in every file
+                        :u          : Undo the last change
+                        :re         : Redo the last undone change
+                        :mark       : Mark/unmark the cursor's line
+                        :spot       : Go to the next mark, keep pressing enter to keep moving
+                        :spot <N>   : Go to the N-th mark
+
+                    Buffer specific directives:
+                        :ls         : List the open buffers
+                        :bn/bp      : Switch to the next/previous open buffer
+                        :bs <N|f>   : Switch to the N-th open buffer, or the one of file 'f'
+                        :bc/bc!     : Close the current buffer, ! discards unsaved changes
+
+                    Git directives:
+                        :gst        : List the changed files, Enter opens one
+                        :gdi        : List the changes of the current file, Enter goes to the line
+                        :gad/gun    : Stage/unstage the current file
+                        :gcm <m>    : Commit the staged changes with message 'm'
+                        :gn/gp      : Go to the next/previous change since HEAD
+                        :grv        : Revert the change under the cursor
+                        :glo        : Show the log of the current file
+                        :gbr [b]    : Show the branches, or switch to branch 'b'
                 ".to_string();
             }      
             
@@ -420,12 +646,16 @@ pub fn console_manual(man_id: u8) -> String {
             3 => {
                   text = "
                     Configuration directives:
-                        :epa <p>    : Change to pallete of name 'p'
-                        :efn <p>    : Change to a font of name 'p'
+                        :epa <p>    : Change to pallete of name 'p', :epa lists them
+                        :efn <p>    : Change to a font of name 'p', :efn lists them
                         :eau        : Audio on/off switch
                         :esm        : Smart identation on/off switch
                         :efl        : Editor fullscreen on/off switch
-                        :ehi        : Edito highlighting on/off switch
+                        :ehi [c]    : Editor highlighting on/off switch, 'c' switches one class:
+                                      comments, strings, keywords, punctuation, numbers, macros
+                        :ela <l>    : Force the buffer's language to 'l', :ela auto detects it, :ela lists them
+                        :ebd <c>    : Set the build command of :mk to 'c', :ebd auto picks it by the project
+                        :lsp        : Language servers (rust-analyzer, clangd, jdtls) on/off switch
                 ".to_string();
             }
 
@@ -452,6 +682,7 @@ pub fn console_manual(man_id: u8) -> String {
                      LCtrl + ArrowKeys: Move the cursor index to the next non whitespace character
                                         horizontally, or by 5 vertically.
                      LCtrl + LShift + ArrowKeys: Smoothly slide the cursor vertically.
+                     Typing an identifier shows completions, Up/Down select, Tab/Enter accept, Escape closes.
                      
                      // TODO: Add shortcuts
                ".to_string();
@@ -0,0 +1,191 @@
+// Console list module
+//
+// A selectable list drawn in the console pane, in place of
+// the directory contents, for directives whose results are
+// locations, e.g. <:grep>. Up/Down move the selection,
+// Enter on an empty directive opens the selected location
+// and Escape closes the list. Filtered lists, e.g. <:ol>, take
+// what is typed as their filter until ':' starts a directive.
+
+use std::path::PathBuf;
+
+use macroquad::prelude::*;
+
+use crate::audio::editor_audio::EditorAudio;
+use crate::console::editor_console::CONSOLE_MARGINS;
+use crate::options::editor_pallete::*;
+use crate::text::editor_cursor::EditorCursor;
+
+pub const CONSOLE_LIST_FONT_SIZE: f32 = 20.0;
+pub const CONSOLE_LIST_ROW_HEIGHT: f32 = 20.0;
+pub const CONSOLE_LIST_PAGE: usize = 10;
+
+/// A location of a list, line and column are 0-based,
+/// without a file it is in the current buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorConsoleListItem {
+    pub label: String,
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub struct EditorConsoleList {
+    pub title: String,
+    pub items: Vec<EditorConsoleListItem>,
+    pub selected: usize,
+    pub scroll: usize,
+    pub active: bool,
+    /// Case insensitive filter of the labels, None for unfiltered lists
+    pub filter: Option<String>,
+    unfiltered: Vec<EditorConsoleListItem>,
+}
+
+impl EditorConsoleList {
+    pub fn new() -> EditorConsoleList {
+        EditorConsoleList {
+            title: String::new(),
+            items: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            active: false,
+            filter: None,
+            unfiltered: Vec::new(),
+        }
+    }
+
+    /// Replace the list's contents and show it
+    pub fn show(
+        &mut self,
+        title: String,
+        items: Vec<EditorConsoleListItem>
+    ) {
+        self.title = title;
+        self.items = items;
+        self.selected = 0;
+        self.scroll = 0;
+        self.active = true;
+        self.filter = None;
+        self.unfiltered.clear();
+    }
+
+    /// Show a list filtered by what is typed, starting with `filter`
+    pub fn show_filtered(
+        &mut self,
+        title: String,
+        items: Vec<EditorConsoleListItem>,
+        filter: &str
+    ) {
+        self.show(title, Vec::new());
+        self.unfiltered = items;
+        self.set_filter(filter.to_string());
+    }
+
+    /// Keep the items whose labels contain `filter`
+    pub fn set_filter(
+        &mut self,
+        filter: String
+    ) {
+        let query = filter.to_lowercase();
+
+        self.items = self.unfiltered.iter()
+            .filter(|item| item.label.to_lowercase().contains(&query))
+            .cloned()
+            .collect();
+
+        self.filter = Some(filter);
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    /// Hide the list
+    pub fn close(&mut self) {
+        self.active = false;
+    }
+
+    /// The selected item, if any
+    pub fn selected_item(&self) -> Option<&EditorConsoleListItem> {
+        self.items.get(self.selected)
+    }
+
+    /// Move the selection by `delta` items, clamped to the list
+    pub fn move_selection(
+        &mut self,
+        delta: isize
+    ) {
+        if self.items.is_empty() {
+            return;
+        }
+
+        self.selected = self.selected
+            .saturating_add_signed(delta)
+            .min(self.items.len() - 1);
+    }
+
+    /// Up/Down and PageUp/PageDown navigation, returns true if the selection moved
+    pub fn record_keys(
+        &mut self,
+        cursor: &mut EditorCursor,
+        audio: &EditorAudio
+    ) -> bool {
+        let delta = if cursor.is_combo_active(KeyCode::Up, None) {
+            -1
+        } else if cursor.is_combo_active(KeyCode::Down, None) {
+            1
+        } else if is_key_pressed(KeyCode::PageUp) {
+            -(CONSOLE_LIST_PAGE as isize)
+        } else if is_key_pressed(KeyCode::PageDown) {
+            CONSOLE_LIST_PAGE as isize
+        } else {
+            return false;
+        };
+
+        self.move_selection(delta);
+        audio.play_nav();
+
+        true
+    }
+
+    /// Draw the title and the visible rows in the console pane,
+    /// scrolling to keep the selection visible
+    pub fn draw(
+        &mut self,
+        console_width: f32
+    ) {
+        let x = screen_width() - console_width + CONSOLE_MARGINS;
+        let mut y = 50.0 + CONSOLE_MARGINS;
+
+        draw_text(&self.title, x, y, CONSOLE_LIST_FONT_SIZE, pallete().console_text);
+        y += CONSOLE_LIST_ROW_HEIGHT * 1.5;
+
+        if let Some(filter) = &self.filter {
+            draw_text(&format!("> {}", filter), x, y, CONSOLE_LIST_FONT_SIZE, pallete().console_text);
+            y += CONSOLE_LIST_ROW_HEIGHT * 1.5;
+        }
+
+        let rows = (((screen_height() - y) / CONSOLE_LIST_ROW_HEIGHT) as usize).max(1);
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        for (i, item) in self.items.iter().enumerate().skip(self.scroll).take(rows) {
+            if i == self.selected {
+                draw_rectangle(
+                    x - 5.0,
+                    y - CONSOLE_LIST_ROW_HEIGHT + 5.0,
+                    console_width,
+                    CONSOLE_LIST_ROW_HEIGHT,
+                    pallete().selection
+                );
+            }
+
+            let color = if i == self.selected { pallete().selected_file } else { pallete().file };
+            draw_text(&item.label, x, y, CONSOLE_LIST_FONT_SIZE, color);
+
+            y += CONSOLE_LIST_ROW_HEIGHT;
+        }
+    }
+}
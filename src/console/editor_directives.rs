@@ -17,7 +17,65 @@
 //              :l <N>      : Go to line N inside the file, if possible, else throw an error (C)
 //              :b <f>      : Change the name of the current open file to 'f'                (C)
 //              :i          : Current file info display
-//              :f <f>      : Go to the line where the first iteration of text 'f' exists
+//              :f <f>      : Search for 'f' from the cursor on, highlighting all matches
+//                            :fc case sensitive, :fw whole words, :fr regex, flags combine
+//              :fn/fp      : Go to the next/previous match of the search
+//              :grep <p>   : Search regex 'p' in every file under the current directory, alias :gr,
+//                            results are listed in the console, Enter opens the selected one
+//              :def [p]    : Go to the definition of 'p' or the identifier under the cursor, alias :gd,
+//                            asking the buffer's language server first,
+//                            several definitions are listed in the console, Enter opens one
+//              :back       : Go back to where the last <:def> jumped from
+//              :ol [p]     : Outline the buffer's functions, types and macros, alias :outline,
+//                            typing filters them by 'p', Enter moves the cursor to the selected one
+//              :s/<p>/<r>/ : Replace regex 'p' with 'r' in the selection or the whole file,
+//                            :s%/ whole file, :sN,M/ lines N to M, flags g all, i case, c confirm
+//              :u          : Undo the last change
+//              :re         : Redo the last undone change
+//
+//      Git:
+//              :gst        : List the changed files, Enter opens the selected one
+//              :gdi        : List the changes of the current file against HEAD, Enter goes to the line
+//              :gad/gun    : Stage/unstage the current file
+//              :gcm <m>    : Commit the staged changes with message 'm'
+//              :gn/gp      : Go to the next/previous change since HEAD, marked in the gutter
+//              :grv        : Revert the change under the cursor to its HEAD version
+//              :glo        : Show the log of the current file
+//              :gbr [b]    : Show the branches, or switch to branch 'b', reloading the open files
+//
+//      Terminal, see editor_terminal.rs:
+//              :t $ <c>    : Run command 'c' in the background, its output streams into a panel
+//              :tk         : Kill the running command
+//              :to         : Show/hide the output panel
+//              :pty        : Show/hide an interactive shell, F12 moves the keyboard to/from it,
+//                            see editor_pty.rs
+//              :ptk        : Kill the interactive shell
+//              :ptd        : Dock the shell at the bottom/right
+//
+//      Build, see editor_build.rs:
+//              :mk [c]     : Build with command 'c', the 'build' option or one picked by the project, alias :make,
+//                            its errors and warnings are underlined and kept as the quickfix list
+//              :cn/cp      : Open the next/previous diagnostic of the build
+//              :cl         : List the diagnostics, Enter opens one
+//
+//      Language servers, see editor_lsp.rs:
+//              :hv         : Show the diagnostics of the cursor's line and the docs of the identifier under it,
+//                            alias :hover
+//              :refs       : List the references of the identifier under the cursor, alias :rf
+//              :rn <n>     : Rename the identifier under the cursor to 'n' in every file, alias :rename
+//              :lsp        : Language servers on/off switch
+//
+//      Buffer specific:
+//              :ls         : List the open buffers
+//              :bn         : Switch to the next open buffer
+//              :bp         : Switch to the previous open buffer
+//              :bs <N|f>   : Switch to the N-th open buffer, or the one of file 'f'
+//              :bc         : Close the current buffer, :bc! discards unsaved changes
+//
+//      Markings:
+//              :mark       : Mark the cursor's line, or unmark it if already marked
+//              :spot       : Go to the next mark, the console keeps the directive to keep moving
+//              :spot <N>   : Go to the N-th mark of the file
 //
 //      Directory specific:
 //              :cd         : Change directory                                         (C)
@@ -26,9 +84,12 @@
 //              :rd <f>     : Remove a directory with name 'f' with all its contents   (C)
 //
 //      Conf: <saved in cal.conf file>
-//              :epa <p>    : Change to pallete of name 'p'
-//              :efn <p>    : Change to a font of name 'p'
+//              :epa <p>    : Change to pallete of name 'p', built-in or themes/<p>.pal, :epa lists them
+//              :efn <p>    : Change to a font of name 'p', from assets/font, fonts/ or the system, :efn lists them
 //              :eau        : Audio on/off switch
+//              :ehi [c]    : Highlighting on/off switch, or of token class 'c' only
+//              :ela <l>    : Force the buffer's language to 'l', 'auto' detects it again, :ela lists them
+//              :ebd [c]    : Set the <:mk> build command to 'c', 'auto' picks one by the project
 //              :eav <N>    : Set editor audio volume to N
 //
 //      Other:
@@ -45,38 +106,161 @@
 // When the console is faced with a directive without a ':' prefix
 // it will view it as a switch-to-file command and will try to switch 
 // to a file with that name if found, same with directorys.
-// The console, as long as you are typing, will display files with names close to it.
-// Pressing TAB will select the first seen file closest to the name given and autocomplete it
-// in the console.
+// The console, as long as you are typing, will display files of the whole tree ranked by
+// how close they are to it, see editor_file_finder.rs. Up/Down select one, pressing TAB
+// autocompletes the selected file in the console and Enter opens it.
 
-use std::path::Path;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use macroquad::prelude::rand;
 
 use crate::VERSION;
+use crate::console::editor_build::default_build_command;
 use crate::console::editor_console::console_manual;
+use crate::console::editor_console_list::{EditorConsoleList, EditorConsoleListItem};
+use crate::console::editor_git::*;
+use crate::console::editor_grep::grep_directory;
+use crate::console::editor_pty::EditorPtyDock;
+use crate::console::editor_symbol_index::{EditorJump, identifier_at};
+use crate::options::editor_fonts::*;
 use crate::options::editor_options::*;
+use crate::options::editor_pallete::*;
 use crate::console::editor_file_system::*;
+use crate::text::editor_buffer::{EditorBuffers, load_keywords_for_file};
 use crate::text::editor_cursor::*;
-use crate::text::editor_language_manager::EditorLanguageKeywords;
-use crate::text::editor_language_manager::load_keywords_for_extension;
+use crate::text::editor_history::{EditorEditKind, EditorHistory};
+use crate::text::editor_language_manager::{EditorLanguageKeywords, find_language, list_language_names, refresh_languages};
+use crate::text::editor_marks::*;
+use crate::text::editor_outline::outline_symbols;
 use crate::console::editor_terminal::execute_terminal_command;
-use crate::text::editor_text::find_word_in_text;
-// use crate::text::editor_language_manager::_recognize_identifiers;
-// use crate::text::editor_language_manager::_tokenize_text_file;
+use crate::text::editor_search::*;
+use crate::text::editor_substitute::*;
 
 /// Check if there is a ':', trim it, match it to a directive and execute it
 /// else we will see it as switch-to-file operation
 /// returns a message if there is an error OR a manual to show
 /// as well as boolean to delcare if it's a manual
+///
+/// Any change a directive makes to the text is recorded as a single undo step,
+/// switching files swaps in the history of the new file. Changed options are saved in user.pref.
 pub fn execute_directive(
     directive: &mut String,
     efs: &mut EditorFileSystem, 
     text: &mut Vec<String>, 
     cursor: &mut EditorCursor,
     ops: &mut EditorOptions,
-    elk: &mut EditorLanguageKeywords
+    elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers,
+    search: &mut EditorSearch,
+    list: &mut EditorConsoleList
+) -> (String, bool) {
+    history.begin(text, cursor, 0, text.len(), EditorEditKind::Directive);
+    let options = ops.clone();
+
+    let result = match_directive(directive, efs, text, cursor, ops, elk, history, buffers, search, list);
+
+    end_directive_record(efs, text, cursor, history);
+
+    // Config directives are saved as soon as they change something
+    if *ops != options && ops.save().is_err() {
+        return ("PrefsNotSaved <user.pref>".to_string(), false);
+    }
+
+    result
+}
+
+/// Open a file at a 0-based line and column, e.g. a console list item,
+/// recorded like a directive so the history follows the file switch
+pub fn open_file_at(
+    file: PathBuf,
+    line: usize,
+    column: usize,
+    efs: &mut EditorFileSystem,
+    text: &mut Vec<String>,
+    cursor: &mut EditorCursor,
+    elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers
+) {
+    open_file(file, efs, text, cursor, elk, history, buffers);
+    place_cursor(line, column, text, cursor);
+}
+
+/// Open a file where its cursor was, recorded like `open_file_at`
+pub fn open_file(
+    file: PathBuf,
+    efs: &mut EditorFileSystem,
+    text: &mut Vec<String>,
+    cursor: &mut EditorCursor,
+    elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers
+) {
+    history.begin(text, cursor, 0, text.len(), EditorEditKind::Directive);
+
+    buffers.open(file, efs, text, cursor, elk);
+
+    end_directive_record(efs, text, cursor, history);
+}
+
+/// open_file for a file that has to be read, nothing changes if it can't be
+pub fn try_open_file(
+    file: PathBuf,
+    efs: &mut EditorFileSystem,
+    text: &mut Vec<String>,
+    cursor: &mut EditorCursor,
+    elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers
+) -> std::io::Result<()> {
+    history.begin(text, cursor, 0, text.len(), EditorEditKind::Directive);
+
+    let opened = buffers.try_open(file, efs, text, cursor, elk);
+
+    end_directive_record(efs, text, cursor, history);
+    opened
+}
+
+/// Move the cursor to a 0-based line and column, clamped to the text
+pub fn place_cursor(
+    line: usize,
+    column: usize,
+    text: &[String],
+    cursor: &mut EditorCursor
+) {
+    let line = line.min(text.len().saturating_sub(1));
+    let column = column.min(text.get(line).map_or(0, |l| l.chars().count()));
+    cursor.xy = (column, line);
+}
+
+/// Close a directive's history record, swapping histories if the file changed
+fn end_directive_record(
+    efs: &EditorFileSystem,
+    text: &[String],
+    cursor: &mut EditorCursor,
+    history: &mut EditorHistory
+) {
+    if efs.current_file != history.current_file {
+        history.switch_file(&efs.current_file, text);
+    } else {
+        history.end(text, cursor);
+    }
+}
+
+/// Match and execute a single directive, see `execute_directive`
+fn match_directive(
+    directive: &mut String,
+    efs: &mut EditorFileSystem, 
+    text: &mut Vec<String>, 
+    cursor: &mut EditorCursor,
+    ops: &mut EditorOptions,
+    elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers,
+    search: &mut EditorSearch,
+    list: &mut EditorConsoleList
 ) -> (String, bool) {
     if directive.starts_with(':') {
         let directive_command = directive.trim_start_matches(':').trim();
@@ -86,7 +270,100 @@ pub fn execute_directive(
 
         match command {
             "t" | "T" => { // Execute terminal command
-                return execute_terminal_command(directive_command, &efs.current_dir);
+                return execute_terminal_command(directive_command, &efs.current_dir, &mut efs.terminal);
+            }
+
+            "tk" | "TK" => { // Kill the running terminal command
+                if !efs.terminal.cancel() {
+                    return ("NoCommandRunning <:tk>".to_string(), false);
+                }
+            }
+
+            "to" | "TO" => { // Show/hide the terminal output
+                efs.terminal.visible = !efs.terminal.visible;
+            }
+
+            // Build, see editor_build.rs
+            "mk" | "MK" | "make" | "MAKE" => {
+                let Some(dir) = efs.current_dir.clone() else {
+                    return ("InvalidPathForCommand <:mk>".to_string(), false);
+                };
+
+                if efs.terminal.is_running() {
+                    return ("CommandStillRunning <:tk>".to_string(), false);
+                }
+
+                let given = directive_command[command.len()..].trim();
+
+                let build = if !given.is_empty() {
+                    given.to_string()
+                } else if !ops.build.is_empty() {
+                    ops.build.clone()
+                } else if let Some(picked) = default_build_command(&dir) {
+                    picked
+                } else {
+                    return ("NoBuildCommand, set one with :ebd <c> <:mk>".to_string(), false);
+                };
+
+                if let Err(e) = efs.terminal.run(&build, &dir) {
+                    return (format!("Failed to execute command: {} <:mk>", e), false);
+                }
+
+                efs.quickfix.start(&dir);
+            }
+
+            "cn" | "CN" | "cp" | "CP" => {
+                let forward = command.eq_ignore_ascii_case("cn");
+
+                let Some(diagnostic) = efs.quickfix.next(forward).cloned() else {
+                    return (format!("NoDiagnostics <:{}>", command.to_lowercase()), false);
+                };
+
+                buffers.open(diagnostic.file, efs, text, cursor, elk);
+                place_cursor(diagnostic.line, diagnostic.column, text, cursor);
+
+                // Kept in the console, Enter goes on to the next one
+                let position = efs.quickfix.current.map_or(0, |i| i + 1);
+                return (format!("{} {}/{}: {}", diagnostic.severity.name(), position, efs.quickfix.items.len(), diagnostic.message), false);
+            }
+
+            "cl" | "CL" => {
+                let items = efs.quickfix.list_items(&efs.current_dir);
+
+                if items.is_empty() {
+                    return ("NoDiagnostics <:cl>".to_string(), false);
+                }
+
+                list.show(format!("{} diagnostics", items.len()), items);
+            }
+
+            "pty" | "PTY" => { // Show/hide the interactive shell
+                if efs.pty.visible {
+                    efs.pty.hide();
+                } else {
+                    let Some(dir) = efs.current_dir.clone() else {
+                        return ("InvalidPathForCommand <:pty>".to_string(), false);
+                    };
+
+                    if let Err(e) = efs.pty.open(&dir) {
+                        return (format!("{} <:pty>", e), false);
+                    }
+
+                    efs.terminal.visible = false;
+                }
+            }
+
+            "ptk" | "PTK" => { // Kill the interactive shell
+                if !efs.pty.kill() {
+                    return ("NoShellRunning <:ptk>".to_string(), false);
+                }
+            }
+
+            "ptd" | "PTD" => { // Dock the shell at the bottom/right
+                efs.pty.dock = match efs.pty.dock {
+                    EditorPtyDock::Bottom => EditorPtyDock::Right,
+                    EditorPtyDock::Right => EditorPtyDock::Bottom,
+                };
             }
         
             "od" | "o" | "O" | "Od" | "oD" | "OD" => efs.open_file_explorer(),
@@ -98,6 +375,8 @@ pub fn execute_directive(
                     if !r {
                         return ("FileNotFound <:b>".to_string(), false);
                     }
+
+                    history.rename_file(&efs.current_file);
                 } else {
                     return ("NoFileNameProvided <:b>".to_string(), false);
                 }
@@ -114,16 +393,351 @@ pub fn execute_directive(
             //     }
             // }
 
-            "f" | "F" => {
-                if let Some(param) = parameter {                
-                    let r = find_word_in_text(param, &text, cursor);
-                    
-                    if !r {
-                        return ("IdentifierNotFound <:f>".to_string(), false);
+            // Next/previous match of the last search
+            "fn" | "FN" | "fp" | "FP" => {
+                let forward = command.eq_ignore_ascii_case("fn");
+
+                if !search.active {
+                    return (format!("NoActiveSearch <:{}>", command.to_lowercase()), false);
+                }
+
+                if !search.goto_match(cursor, text, forward, false) {
+                    return (format!("IdentifierNotFound <:{}>", command.to_lowercase()), false);
+                }
+            }
+
+            // Project search, the pattern is everything after the command
+            "grep" | "GREP" | "gr" | "GR" => {
+                let pattern = directive_command[command.len()..].trim_start();
+
+                if pattern.is_empty() {
+                    return ("NoIdentifierProvided <:grep>".to_string(), false);
+                }
+
+                let Ok(regex) = regex::Regex::new(pattern) else {
+                    return ("InvalidRegex <:grep>".to_string(), false);
+                };
+
+                let Some(dir) = &efs.current_dir else {
+                    return ("NoDirectory <:grep>".to_string(), false);
+                };
+
+                let (items, truncated) = grep_directory(dir, &regex);
+
+                if items.is_empty() {
+                    return ("IdentifierNotFound <:grep>".to_string(), false);
+                }
+
+                let title = format!("{}{} matches of '{}'", if truncated { "First " } else { "" }, items.len(), pattern);
+                list.show(title, items);
+            }
+
+            // Symbol outline of the current buffer, see editor_outline.rs
+            "ol" | "OL" | "outline" | "OUTLINE" => {
+                let symbols = outline_symbols(text, elk);
+
+                if symbols.is_empty() {
+                    return ("NoSymbolsFound <:ol>".to_string(), false);
+                }
+
+                let items = symbols.iter()
+                    .map(|symbol| EditorConsoleListItem {
+                        label: symbol.label(),
+                        file: efs.current_file.clone(),
+                        line: symbol.line,
+                        column: symbol.column,
+                    })
+                    .collect();
+
+                let title = format!("{} symbols of {}", symbols.len(), elk.name);
+                list.show_filtered(title, items, parameter.unwrap_or(""));
+            }
+
+            // Definitions of the project symbol index, see editor_symbol_index.rs
+            "def" | "DEF" | "gd" | "GD" => {
+                let word = match parameter {
+                    Some(param) => Some(param.to_string()),
+                    None => text.get(cursor.xy.1).and_then(|line| identifier_at(line, cursor.xy.0)),
+                };
+
+                let Some(word) = word else {
+                    return ("NoIdentifierProvided <:def>".to_string(), false);
+                };
+
+                let jump = EditorJump {
+                    file: efs.current_file.clone(),
+                    line: cursor.xy.1,
+                    column: cursor.xy.0,
+                };
+
+                // The buffer's language server knows the identifier under the cursor best,
+                // its answer is acted on by apply_lsp_event
+                let asked = parameter.is_none() && efs.current_file_path()
+                    .is_some_and(|file| efs.lsp.definition(&file, text, cursor.xy, word.clone(), jump.clone()).is_ok());
+
+                if !asked {
+                    let items = efs.symbols.find(&word);
+
+                    if items.is_empty() {
+                        let error = if efs.symbols.is_indexing() { "StillIndexing" } else { "DefinitionNotFound" };
+                        return (format!("{} <:def>", error), false);
                     }
-                    
-                } else {
+
+                    efs.symbols.push_jump(jump);
+
+                    if let [item] = items.as_slice() && let Some(file) = item.file.clone() {
+                        buffers.open(file, efs, text, cursor, elk);
+                        place_cursor(item.line, item.column, text, cursor);
+                    } else {
+                        list.show(format!("{} definitions of '{}'", items.len(), word), items);
+                    }
+                }
+            }
+
+            "back" | "BACK" => {
+                let Some(jump) = efs.symbols.jumps.pop() else {
+                    return ("NoJumpsBack <:back>".to_string(), false);
+                };
+
+                if let Some(file) = jump.file {
+                    buffers.open(file, efs, text, cursor, elk);
+                }
+
+                place_cursor(jump.line, jump.column, text, cursor);
+            }
+
+            // Language servers, see editor_lsp.rs
+            "hv" | "HV" | "hover" | "HOVER" => {
+                if cursor.word.trim().is_empty() {
+                    return ("NoIdentifierProvided <:hv>".to_string(), false);
+                }
+
+                let Some(file) = efs.current_file_path() else {
+                    return ("NoLanguageServer <:hv>".to_string(), false);
+                };
+
+                if let Err(e) = efs.lsp.hover(&file, text, cursor.xy) {
+                    return (format!("{} <:hv>", e), false);
+                }
+            }
+
+            "refs" | "REFS" | "rf" | "RF" => {
+                let Some(word) = text.get(cursor.xy.1).and_then(|line| identifier_at(line, cursor.xy.0)) else {
+                    return ("NoIdentifierProvided <:refs>".to_string(), false);
+                };
+
+                let Some(file) = efs.current_file_path() else {
+                    return ("NoLanguageServer <:refs>".to_string(), false);
+                };
+
+                if let Err(e) = efs.lsp.references(&file, text, cursor.xy, word) {
+                    return (format!("{} <:refs>", e), false);
+                }
+            }
+
+            "rn" | "RN" | "rename" | "RENAME" => {
+                let Some(name) = parameter else {
+                    return ("NoNameProvided <:rn>".to_string(), false);
+                };
+
+                if text.get(cursor.xy.1).and_then(|line| identifier_at(line, cursor.xy.0)).is_none() {
+                    return ("NoIdentifierProvided <:rn>".to_string(), false);
+                }
+
+                let Some(file) = efs.current_file_path() else {
+                    return ("NoLanguageServer <:rn>".to_string(), false);
+                };
+
+                if let Err(e) = efs.lsp.rename(&file, text, cursor.xy, name.to_string()) {
+                    return (format!("{} <:rn>", e), false);
+                }
+            }
+
+            "lsp" | "LSP" => {
+                ops.toggle_lsp();
+            }
+
+            // Git, see editor_git.rs
+            "gst" | "GST" => {
+                let Some(dir) = &efs.current_dir else {
+                    return ("NoDirectory <:gst>".to_string(), false);
+                };
+
+                let items = match git_status(dir) {
+                    Ok(items) => items,
+                    Err(e) => return (format!("{} <:gst>", e), false),
+                };
+
+                let branch = git_branch(dir).unwrap_or_default();
+
+                if items.is_empty() {
+                    directive.clear();
+                    return (format!("NothingToCommit on {} <:gst>", branch), false);
+                }
+
+                list.show(format!("{} changes on {}", items.len(), branch), items);
+            }
+
+            "gdi" | "GDI" | "glo" | "GLO" => {
+                let (Some(dir), Some(file)) = (&efs.current_dir, efs.current_file_path()) else {
+                    return (format!("NoFileOpen <:{}>", command.to_lowercase()), false);
+                };
+
+                if command.eq_ignore_ascii_case("glo") {
+                    return match git_log(dir, &file) {
+                        Ok(log) if log.trim().is_empty() => ("NoCommits <:glo>".to_string(), false),
+                        Ok(log) => {
+                            directive.clear();
+                            (log, true)
+                        }
+                        Err(e) => (format!("{} <:glo>", e), false),
+                    };
+                }
+
+                match git_diff(dir, &file) {
+                    Ok(items) if items.is_empty() => return ("NoChanges <:gdi>".to_string(), false),
+                    Ok(items) => list.show(format!("Changes of {}", path_buffer_file_to_string(&efs.current_file)), items),
+                    Err(e) => return (format!("{} <:gdi>", e), false),
+                }
+            }
+
+            // Changes since HEAD, see editor_changes.rs
+            "gn" | "GN" | "gp" | "GP" => {
+                let forward = command.eq_ignore_ascii_case("gn");
+
+                let Some(hunk) = efs.changes.next_hunk(cursor.xy.1, forward).copied() else {
+                    return (format!("NoChanges <:{}>", command.to_lowercase()), false);
+                };
+
+                place_cursor(hunk.anchor(), 0, text, cursor);
+            }
+
+            "grv" | "GRV" => {
+                let Some(hunk) = efs.changes.hunk_at(cursor.xy.1) else {
+                    return ("NoChangeAtCursor <:grv>".to_string(), false);
+                };
+
+                let end = (hunk.new_start + hunk.new_len).min(text.len());
+                let start = hunk.new_start.min(end);
+
+                text.splice(start..end, efs.changes.head_lines(&hunk));
+                efs.unsaved_changes = true;
+                place_cursor(hunk.new_start, 0, text, cursor);
+            }
+
+            // Stage/unstage the file as it is on disk
+            "gad" | "GAD" | "gun" | "GUN" => {
+                let stage = command.eq_ignore_ascii_case("gad");
+                let name = command.to_lowercase();
+
+                let (Some(dir), Some(file)) = (&efs.current_dir, efs.current_file_path()) else {
+                    return (format!("NoFileOpen <:{}>", name), false);
+                };
+
+                if efs.unsaved_changes {
+                    return (format!("UnsavedChanges, write the file first <:{}>", name), false);
+                }
+
+                let path = file.to_string_lossy();
+                let args: &[&str] = if stage { &["add", "--", &path] } else { &["restore", "--staged", "--", &path] };
+
+                if let Err(e) = run_git(dir, args) {
+                    return (format!("{} <:{}>", e, name), false);
+                }
+            }
+
+            // The message is everything after the command
+            "gcm" | "GCM" => {
+                let message = directive_command[command.len()..].trim();
+
+                if message.is_empty() {
+                    return ("NoCommitMessage <:gcm>".to_string(), false);
+                }
+
+                let Some(dir) = &efs.current_dir else {
+                    return ("NoDirectory <:gcm>".to_string(), false);
+                };
+
+                match run_git(dir, &["commit", "-m", message]) {
+                    Ok(output) => {
+                        efs.changes.refresh();
+                        directive.clear();
+                        return (output.lines().next().unwrap_or("").to_string(), false);
+                    }
+                    Err(e) => return (format!("{} <:gcm>", e), false),
+                }
+            }
+
+            // Switching reloads the open files, so unsaved edits can't be overwritten
+            "gbr" | "GBR" => {
+                let Some(dir) = efs.current_dir.clone() else {
+                    return ("NoDirectory <:gbr>".to_string(), false);
+                };
+
+                let Some(branch) = parameter else {
+                    return match git_branches(&dir) {
+                        Ok(branches) => (format!("Branches:\n{}", branches), true),
+                        Err(e) => (format!("{} <:gbr>", e), false),
+                    };
+                };
+
+                if (0..buffers.buffers.len()).any(|i| buffers.is_unsaved(i, efs)) {
+                    return ("UnsavedChanges, write the open files first <:gbr>".to_string(), false);
+                }
+
+                if let Err(e) = run_git(&dir, &["switch", branch]) {
+                    return (format!("{} <:gbr>", e), false);
+                }
+
+                buffers.reload_all(efs, text, cursor);
+                efs.changes.refresh();
+                efs.symbols.restart();
+            }
+
+            // Substitute, see editor_substitute.rs
+            _ if is_substitute_command(command) => {
+                let mut substitute = match EditorSubstitute::new(directive_command, cursor, text) {
+                    Ok(substitute) => substitute,
+                    Err(e) => return (format!("{} <:s>", e), false),
+                };
+
+                directive.clear();
+
+                if substitute.confirm {
+                    if !substitute.next_match(text, cursor) {
+                        return ("IdentifierNotFound <:s>".to_string(), false);
+                    }
+
+                    search.substitute = Some(substitute);
+
+                    return ("Replace? [y]es [n]o [a]ll [q]uit <:s>".to_string(), false);
+                }
+
+                substitute.replace_all(text, cursor);
+
+                if substitute.count > 0 {
+                    efs.unsaved_changes = true;
+                }
+
+                return (format!("Replaced {} occurrence(s) <:s>", substitute.count), false);
+            }
+
+            // Search, the flags follow the 'f', see editor_search.rs
+            _ if parse_search_command(command).is_some() => {
+                let Some((flags, query)) = parse_search_directive(directive) else {
                     return ("NoIdentifierProvided <:f>".to_string(), false);
+                };
+
+                if query.is_empty() {
+                    return ("NoIdentifierProvided <:f>".to_string(), false);
+                }
+
+                if !search.set_query(query, flags, text) {
+                    return ("InvalidRegex <:f>".to_string(), false);
+                }
+
+                if !search.goto_match(cursor, text, true, true) {
+                    return ("IdentifierNotFound <:f>".to_string(), false);
                 }
             }
 
@@ -167,13 +781,47 @@ pub fn execute_directive(
                         return ("FileNameUsed <:c>".to_string(), false);
                     }
 
-                    efs.change_current_file(param.to_string());
-                    *text = efs.load_current_file().unwrap_or_default();
+                    // The new file gets its own buffer
+                    if let Some(path) = efs.find_file(param) {
+                        buffers.open(path, efs, text, cursor, elk);
+                    }
                 } else {
                     return ("NoFileNameProvided <:c>".to_string(), false);
                 }
             }
 
+            // Buffers
+            "ls" | "LS" => return (buffers.list(efs), true),
+
+            "bn" | "BN" => buffers.cycle(true, efs, text, cursor, elk),
+
+            "bp" | "BP" => buffers.cycle(false, efs, text, cursor, elk),
+
+            "bs" | "BS" => {
+                if let Some(param) = parameter {
+                    let index = match param.parse::<usize>() {
+                        Ok(n) => Some(n),
+                        Err(_) => efs.find_file(param).and_then(|path| buffers.find(&path, efs)),
+                    };
+
+                    let switched = index.map(|i| buffers.switch_to(i, efs, text, cursor, elk)).unwrap_or(false);
+
+                    if !switched {
+                        return ("BufferNotFound <:bs>".to_string(), false);
+                    }
+                } else {
+                    return ("NoBufferProvided <:bs>".to_string(), false);
+                }
+            }
+
+            "bc" | "BC" | "bc!" | "BC!" => {
+                if efs.unsaved_changes && !command.ends_with('!') {
+                    return ("UnsavedChanges, use :bc! to discard <:bc>".to_string(), false);
+                }
+
+                buffers.close(efs, text, cursor, elk);
+            }
+
             "cd" | "CD" | "Cd" | "cD" => {
                 if let Some(param) = parameter {
                     efs.change_current_directory(param.to_string());
@@ -209,9 +857,68 @@ pub fn execute_directive(
 
             "w" | "W" => {
                 let _ = efs.write_current_file(text);
+
+                // Saved marks follow the saved text
+                if let Some(file) = &efs.current_file {
+                    let _ = save_marks(file, &cursor.marks);
+                }
+            }
+
+            // Markings
+            "mark" | "MARK" => {
+                let Some(file) = efs.current_file.clone() else {
+                    return ("NoFileOpen <:mark>".to_string(), false);
+                };
+
+                toggle_mark(cursor);
+
+                if save_marks(&file, &cursor.marks).is_err() {
+                    return ("MarksNotSaved <:mark>".to_string(), false);
+                }
             }
 
-            "e" | "q" | "E" | "Q" => std::process::exit(0),
+            "spot" | "SPOT" => {
+                if let Some(param) = parameter {
+                    let Ok(n) = param.parse::<usize>() else {
+                        return ("InvalidSpotArgument <:spot>".to_string(), false);
+                    };
+
+                    if !goto_spot(cursor, n) {
+                        return ("SpotNotFound <:spot>".to_string(), false);
+                    }
+                } else {
+                    if next_spot(cursor).is_none() {
+                        return ("NoMarksInFile <:spot>".to_string(), false);
+                    }
+
+                    // Keep the directive, so pressing enter again moves to the next spot
+                    return ("".to_string(), false);
+                }
+            }
+
+            "u" | "U" => {
+                if !history.undo(text, cursor) {
+                    return ("NothingToUndo <:u>".to_string(), false);
+                }
+
+                efs.unsaved_changes = true;
+            }
+
+            "re" | "RE" | "Re" | "rE" => {
+                if !history.redo(text, cursor) {
+                    return ("NothingToRedo <:re>".to_string(), false);
+                }
+
+                efs.unsaved_changes = true;
+            }
+
+            "e" | "q" | "E" | "Q" => {
+                // The command's process group outlives the editor otherwise
+                efs.terminal.cancel();
+                efs.pty.kill();
+                efs.lsp.stop_all();
+                std::process::exit(0)
+            }
 
             // Manuals
             "egman" | "man" => return (console_manual(0), true),
@@ -238,6 +945,35 @@ pub fn execute_directive(
             }
 
             // Options
+            "epa" | "EPA" => {
+                let Some(param) = parameter else {
+                    let current = if ops.pallete.is_empty() { DEFAULT_PALLETE } else { &ops.pallete };
+                    return (format!("Palletes: {} (current: {})", list_palletes().join(", "), current), false);
+                };
+
+                match load_pallete(param) {
+                    Ok(pallete) => {
+                        set_pallete(pallete);
+                        ops.pallete = param.to_string();
+                    }
+                    Err(e) => return (format!("{} <:epa>", e), false),
+                }
+            }
+
+            "efn" | "EFN" => {
+                let Some(param) = parameter else {
+                    refresh_fonts();
+                    let current = if ops.font.is_empty() { DEFAULT_FONT } else { &ops.font };
+                    return (format!("Fonts: {} (current: {})", list_fonts().join(", "), current), false);
+                };
+
+                // Only validated here, the stylizers swap to ops.font on the next frame
+                match load_font(param) {
+                    Ok(_) => ops.font = param.to_string(),
+                    Err(e) => return (format!("{} <:efn>", e), false),
+                }
+            }
+
             "eau" => {
                 ops.toggle_audio();
             }
@@ -250,30 +986,63 @@ pub fn execute_directive(
                 ops.toggle_fullscreen();    
             }
             
-            "ehi" => {
-                ops.toggle_highlight();    
+            "ehi" | "EHI" => {
+                match parameter {
+                    None => ops.toggle_highlight(),
+                    Some(class) if ops.toggle_highlight_class(class) => {}
+                    Some(_) => return (format!("UnknownHighlightClass, expected {} <:ehi>", HIGHLIGHT_CLASSES.join(", ")), false),
+                }
+            }
+
+            "ela" | "ELA" => {
+                // Definitions may have been added or edited since they were read
+                let errors = refresh_languages();
+
+                let Some(param) = parameter else {
+                    let listed = format!("Languages: {} (current: {})", list_language_names().join(", "), elk.name);
+
+                    if errors.is_empty() {
+                        return (listed, false);
+                    }
+
+                    return (format!("\n\n{}\n\n{}", listed, errors.join("\n")), true);
+                };
+
+                // Kept with the buffer until the file is opened again
+                if param.eq_ignore_ascii_case("auto") {
+                    *elk = load_keywords_for_file(&efs.current_file, text);
+                } else if let Some(language) = find_language(param) {
+                    *elk = language;
+                } else {
+                    return ("LanguageNotFound <:ela>".to_string(), false);
+                }
+
+                if errors.len() == 1 {
+                    return (errors[0].clone(), false);
+                } else if errors.len() > 1 {
+                    return (format!("\n\n{}", errors.join("\n")), true);
+                }
+            }
+
+            "ebd" | "EBD" => {
+                let build = directive_command[command.len()..].trim();
+
+                if build.is_empty() {
+                    let picked = efs.current_dir.as_deref().and_then(default_build_command).unwrap_or_default();
+                    let current = if ops.build.is_empty() { format!("auto, {}", picked) } else { ops.build.clone() };
+                    return (format!("Build command: {}", current), false);
+                }
+
+                ops.build = if build.eq_ignore_ascii_case("auto") { String::new() } else { build.to_string() };
             }
 
             _ => return ("UnknownDirective".to_string(), false),
         }
     } else {
-        // File switch
-        if efs.change_current_file(directive.to_string()) {
-            text.clear();
-            *text = efs.load_current_file().unwrap_or_default();
-            
-            let fname = path_buffer_file_to_string(&efs.current_file);
-
-            let ext = Path::new(&fname)
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or("");
-
-            // Load the new language support
-            *elk = load_keywords_for_extension(ext);
+        // File switch, already open files keep their buffer
+        if let Some(path) = efs.find_file(directive) {
+            buffers.open(path, efs, text, cursor, elk);
         } else {
-            text.clear();
-            efs.current_file = None;
             return ("FileNotFound".to_string(), false);
         }
     }
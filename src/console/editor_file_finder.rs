@@ -0,0 +1,240 @@
+// Fuzzy file finder module
+//
+// Typing a switch-to-file directive (no ':' prefix) ranks every
+// file under the current directory against it, the query's
+// characters have to appear in order in the file's relative path.
+// Matches on path segment starts, camelCase and '_' boundaries
+// and consecutive characters score higher, recently opened files
+// get a bonus. Up/Down select a candidate, TAB completes it and
+// Enter opens it, e.g. "src/text/cur" opens src/text/editor_cursor.rs.
+
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+
+use crate::audio::editor_audio::EditorAudio;
+use crate::console::editor_console_list::{EditorConsoleList, EditorConsoleListItem};
+use crate::text::editor_cursor::EditorCursor;
+
+pub const FINDER_MAX_FILES: usize = 20000;
+pub const FINDER_MAX_CANDIDATES: usize = 200;
+
+pub const FINDER_BOUNDARY_BONUS: i64 = 16;
+pub const FINDER_CONSECUTIVE_BONUS: i64 = 8;
+pub const FINDER_FILE_NAME_BONUS: i64 = 4;
+pub const FINDER_RECENT_BONUS: i64 = 40;
+pub const FINDER_GAP_PENALTY_CAP: i64 = 8;
+
+pub struct EditorFileFinder {
+    root: Option<PathBuf>,
+    /// Paths relative to the root, '/' separated
+    files: Vec<String>,
+    indexed: bool,
+    query: String,
+    pub candidates: EditorConsoleList,
+}
+
+impl EditorFileFinder {
+    pub fn new() -> EditorFileFinder {
+        EditorFileFinder {
+            root: None,
+            files: Vec::new(),
+            indexed: false,
+            query: String::new(),
+            candidates: EditorConsoleList::new(),
+        }
+    }
+
+    /// Re-rank the candidates if the query changed, an empty query
+    /// ends the session so the next one sees new files
+    pub fn update(
+        &mut self,
+        dir: &Option<PathBuf>,
+        query: &str,
+        recent: &[PathBuf]
+    ) {
+        let Some(dir) = dir else {
+            return;
+        };
+
+        if query.is_empty() {
+            self.indexed = false;
+            self.query.clear();
+            self.candidates.close();
+            return;
+        }
+
+        if !self.indexed || self.root.as_ref() != Some(dir) {
+            self.index(dir);
+            self.query.clear();
+        }
+
+        if query == self.query {
+            return;
+        }
+
+        self.query = query.to_string();
+
+        let mut ranked: Vec<(i64, &String)> = self.files.iter()
+            .filter_map(|file| {
+                let mut score = fuzzy_score(query, file)?;
+
+                if let Some(rank) = recent.iter().position(|r| r.ends_with(file)) {
+                    score += (FINDER_RECENT_BONUS - rank as i64 * 4).max(0);
+                }
+
+                Some((score, file))
+            })
+            .collect();
+
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.len().cmp(&b.1.len())).then(a.1.cmp(b.1)));
+        ranked.truncate(FINDER_MAX_CANDIDATES);
+
+        let items = ranked.into_iter()
+            .map(|(_, file)| EditorConsoleListItem {
+                label: file.clone(),
+                file: Some(dir.join(file)),
+                line: 0,
+                column: 0,
+            })
+            .collect::<Vec<_>>();
+
+        self.candidates.show(format!("{} of {} files", items.len(), self.files.len()), items);
+    }
+
+    /// Collect the files under `dir`, honouring .gitignore
+    fn index(
+        &mut self,
+        dir: &Path
+    ) {
+        self.files = WalkBuilder::new(dir)
+            .require_git(false)
+            .build()
+            .flatten()
+            .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+            .filter_map(|entry| {
+                let relative = entry.path().strip_prefix(dir).ok()?;
+                Some(relative.to_string_lossy().replace('\\', "/"))
+            })
+            .take(FINDER_MAX_FILES)
+            .collect();
+
+        self.root = Some(dir.to_path_buf());
+        self.indexed = true;
+    }
+
+    /// Up/Down selection, returns true if the selection moved
+    pub fn record_keys(
+        &mut self,
+        cursor: &mut EditorCursor,
+        audio: &EditorAudio
+    ) -> bool {
+        self.candidates.active && self.candidates.record_keys(cursor, audio)
+    }
+
+    /// Relative path of the selected candidate
+    pub fn selected(&self) -> Option<&str> {
+        if !self.candidates.active {
+            return None;
+        }
+
+        self.candidates.selected_item().map(|item| item.label.as_str())
+    }
+}
+
+/// Score `candidate` against `query`, case insensitive,
+/// returns None if the query's characters don't appear in order.
+/// Every placement of the query is considered, keeping the best one
+pub fn fuzzy_score(
+    query: &str,
+    candidate: &str
+) -> Option<i64> {
+    const NONE: i64 = i64::MIN / 2;
+
+    let query: Vec<char> = query.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let chars: Vec<char> = candidate.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let file_name_start = candidate.rfind('/').map_or(0, |i| candidate[..=i].chars().count());
+    let cap = FINDER_GAP_PENALTY_CAP as usize;
+
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let bonus = |j: usize| {
+        let mut bonus = 0;
+
+        if is_boundary(&chars, j) {
+            bonus += FINDER_BOUNDARY_BONUS;
+        }
+
+        if j >= file_name_start {
+            bonus += FINDER_FILE_NAME_BONUS;
+        }
+
+        bonus
+    };
+
+    // Best score of the query so far, with its last character at j
+    let mut previous: Vec<i64> = (0..lower.len())
+        .map(|j| if lower[j] == query[0] { bonus(j) } else { NONE })
+        .collect();
+
+    for &q in &query[1..] {
+        let mut current = vec![NONE; lower.len()];
+
+        // Best of the placements further back than the gap penalty cap
+        let mut far_best = NONE;
+
+        for j in 0..lower.len() {
+            if j >= cap + 2 {
+                far_best = far_best.max(previous[j - cap - 2]);
+            }
+
+            if lower[j] != q {
+                continue;
+            }
+
+            let mut best = far_best - FINDER_GAP_PENALTY_CAP;
+
+            let from = j.saturating_sub(cap + 1);
+
+            for (k, &score) in previous.iter().enumerate().take(j).skip(from) {
+                if score == NONE {
+                    continue;
+                }
+
+                let score = if k + 1 == j {
+                    score + FINDER_CONSECUTIVE_BONUS
+                } else {
+                    score - (j - k - 1) as i64
+                };
+
+                best = best.max(score);
+            }
+
+            if best > NONE / 2 {
+                current[j] = best + bonus(j);
+            }
+        }
+
+        previous = current;
+    }
+
+    previous.into_iter().filter(|&s| s > NONE / 2).max()
+}
+
+/// Path segment, word or camelCase start
+fn is_boundary(
+    chars: &[char],
+    i: usize
+) -> bool {
+    if i == 0 {
+        return true;
+    }
+
+    let (previous, current) = (chars[i - 1], chars[i]);
+
+    matches!(previous, '/' | '\\' | '_' | '-' | '.' | ' ')
+        || (previous.is_lowercase() && current.is_uppercase())
+}
@@ -5,12 +5,31 @@ use std::{fs, io, path::{Path, PathBuf}};
 use std::io::Write;
 
 use crate::console::editor_console::*;
+use crate::console::editor_symbol_index::EditorSymbolIndex;
+use crate::console::editor_build::EditorQuickfix;
+use crate::console::editor_lsp::EditorLsp;
+use crate::console::editor_pty::EditorPtyPanel;
+use crate::console::editor_terminal::EditorTerminal;
+use crate::text::editor_changes::EditorGitChanges;
+use crate::text::editor_language_manager::{LANGUAGE_EXTENSION, refresh_languages};
 use crate::options::editor_pallete::*;
 
 pub struct EditorFileSystem {
     pub current_dir: Option<PathBuf>,
     pub current_file: Option<PathBuf>,
-    pub unsaved_changes: bool
+    pub unsaved_changes: bool,
+    /// Definitions of the current directory's files, see <:def>
+    pub symbols: EditorSymbolIndex,
+    /// Changes of the current file since HEAD, drawn in the gutter
+    pub changes: EditorGitChanges,
+    /// Background command of <:t>, and its output
+    pub terminal: EditorTerminal,
+    /// Interactive shell of <:pty>
+    pub pty: EditorPtyPanel,
+    /// Diagnostics of the last <:mk>
+    pub quickfix: EditorQuickfix,
+    /// Language servers of the open files, see editor_lsp.rs
+    pub lsp: EditorLsp
 }
 
 impl EditorFileSystem {
@@ -18,33 +37,57 @@ impl EditorFileSystem {
         EditorFileSystem {
             current_dir: None,
             current_file: None,
-            unsaved_changes: false
+            unsaved_changes: false,
+            symbols: EditorSymbolIndex::new(),
+            changes: EditorGitChanges::new(),
+            terminal: EditorTerminal::new(),
+            pty: EditorPtyPanel::new(),
+            quickfix: EditorQuickfix::new(),
+            lsp: EditorLsp::new()
         }
     }
 
     /// Load the contents of the currently open file
     pub fn load_current_file(&self) -> io::Result<Vec<String>> {
         if let Some(ref file) = self.current_file {
-            let path = self.current_dir.as_ref().unwrap_or(&std::env::current_dir().unwrap()).join(file);
-            let content = fs::read_to_string(path)?;
-            
-            Ok(content.lines().map(|s| s.to_string()).collect())
+            self.load_file(file)
         } else {
             Ok(vec![])  // no file selected
         }
     }
 
+    /// Path of the currently open file, relative paths are taken from the current directory
+    pub fn current_file_path(&self) -> Option<PathBuf> {
+        let file = self.current_file.as_ref()?;
+
+        Some(self.current_dir.as_ref().unwrap_or(&std::env::current_dir().unwrap()).join(file))
+    }
+
+    /// Load the contents of a file, relative to the current directory
+    pub fn load_file(&self, file: &Path) -> io::Result<Vec<String>> {
+        let path = self.current_dir.as_ref().unwrap_or(&std::env::current_dir().unwrap()).join(file);
+        let content = fs::read_to_string(path)?;
+
+        Ok(content.lines().map(|s| s.to_string()).collect())
+    }
+
     /// Write a Vec<String> back to the current file
     pub fn write_current_file(&mut self, text: &[String]) -> io::Result<()> {
         if let Some(ref file) = self.current_file {
             let path = self.current_dir.as_ref().unwrap_or(&std::env::current_dir().unwrap()).join(file);
-            let mut f = fs::File::create(path)?;
+            let mut f = fs::File::create(&path)?;
 
             for line in text {
                 writeln!(f, "{}", line)?;
             }
 
             self.unsaved_changes = false;
+            self.symbols.update_file(&path, text);
+
+            // Saved definitions apply to files opened after them
+            if path.extension().is_some_and(|e| e == LANGUAGE_EXTENSION) {
+                refresh_languages();
+            }
         }
 
         Ok(())
@@ -100,21 +143,22 @@ impl EditorFileSystem {
         }
     }
 
-    /// Change to another file inside the current directory
-    /// by typing its name in the console
-    /// returns true if the change was valid, else false
-    pub fn change_current_file(
-        &mut self,
-        f: String
-    ) -> bool {
-        let Some(dir) = &self.current_dir else {
-            return false;
-        };
-    
-        let entries = match std::fs::read_dir(dir) {
-            Ok(e) => e,
-            Err(_) => return false,
-        };
+    /// Find a file inside the current directory by its name,
+    /// used to switch files by typing its name in the console
+    /// returns its path if found
+    pub fn find_file(
+        &self,
+        f: &str
+    ) -> Option<PathBuf> {
+        let dir = self.current_dir.as_ref()?;
+
+        // Relative paths, e.g. picked by the file finder
+        if f.contains('/') || f.contains('\\') {
+            let path = dir.join(f);
+            return path.is_file().then_some(path);
+        }
+
+        let entries = std::fs::read_dir(dir).ok()?;
     
         for entry in entries.flatten() {
             let path = entry.path();
@@ -123,14 +167,13 @@ impl EditorFileSystem {
             if path.is_file() {
                 if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
                     if name == f {
-                        self.current_file = Some(path);
-                        return true;
+                        return Some(path);
                     }
                 }
             }
         }
     
-        false
+        None
     }
 
     /// Create a file of name <fname>
@@ -378,11 +421,11 @@ pub fn draw_dir_contents(
 
         // Highlight and formatting
         let color = if Some(&path) == current_file.as_ref() {
-            SELECTED_FILE_COLOR
+            pallete().selected_file
         } else if is_dir {
-            FOLDER_COLOR
+            pallete().folder
         } else {
-            FILE_COLOR
+            pallete().file
         };
 
         if is_dir {
@@ -0,0 +1,205 @@
+// Git module, used by the git directives.
+//
+// Runs the local git binary, found on the PATH with the which crate,
+// in the current directory. Status and diff are listed in the console
+// so Enter opens the file, or the line, a row refers to:
+//      <:gst>          : Changed files, as git status --short
+//      <:gdi>          : Diff of the current file against HEAD
+//      <:gad>/<:gun>   : Stage/unstage the current file
+//      <:gcm> <m>      : Commit the staged changes with message 'm'
+//      <:glo>          : Log of the current file
+//      <:gbr> [b]      : List the branches, or switch to 'b'
+// The gutter's change markers diff the buffer with the file at HEAD,
+// see editor_changes.rs.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::console::editor_console_list::EditorConsoleListItem;
+
+pub const GIT_LOG_LIMIT: usize = 40;
+
+/// Run git with `args` in `dir`, returns its output,
+/// or the first line of its errors if it failed
+pub fn run_git(
+    dir: &Path,
+    args: &[&str]
+) -> Result<String, String> {
+    let Ok(git) = which::which("git") else {
+        return Err("GitNotFound".to_string());
+    };
+
+    let output = Command::new(git)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|_| "GitNotExecutable".to_string())?;
+
+    if !output.status.success() {
+        let errors = String::from_utf8_lossy(&output.stderr);
+        let first = errors.lines().find(|l| !l.trim().is_empty()).unwrap_or("").trim();
+
+        return Err(format!("GitFailed '{}'", first));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The top directory of the repository `dir` is in
+pub fn git_root(
+    dir: &Path
+) -> Result<PathBuf, String> {
+    let root = run_git(dir, &["rev-parse", "--show-toplevel"])?;
+
+    Ok(PathBuf::from(root.trim()))
+}
+
+/// Changed files as "XY path" rows, staged and unstaged state first
+pub fn git_status(
+    dir: &Path
+) -> Result<Vec<EditorConsoleListItem>, String> {
+    let root = git_root(dir)?;
+    let status = run_git(dir, &["status", "--porcelain", "-z", "--untracked-files=all"])?;
+
+    Ok(parse_status(&status, &root))
+}
+
+/// Parse NUL separated porcelain entries, paths are neither quoted nor escaped
+fn parse_status(
+    status: &str,
+    root: &Path
+) -> Vec<EditorConsoleListItem> {
+    let mut items = Vec::new();
+    let mut entries = status.split('\0');
+
+    while let Some(entry) = entries.next() {
+        if entry.len() <= 3 {
+            continue;
+        }
+
+        let (state, path) = entry.split_at(3);
+
+        // Renames and copies are followed by their source as a field of its own
+        let label = if state.starts_with(['R', 'C']) && let Some(source) = entries.next() {
+            format!("{}{} -> {}", state, source, path)
+        } else {
+            entry.to_string()
+        };
+
+        items.push(EditorConsoleListItem {
+            label,
+            file: Some(root.join(path)),
+            line: 0,
+            column: 0,
+        });
+    }
+
+    items
+}
+
+/// The current branch's name
+pub fn git_branch(
+    dir: &Path
+) -> Result<String, String> {
+    Ok(run_git(dir, &["branch", "--show-current"])?.trim().to_string())
+}
+
+/// Diff of `file` against HEAD, staged and unstaged changes alike, each row
+/// refers to the line of the file it shows, removed lines to where they were
+pub fn git_diff(
+    dir: &Path,
+    file: &Path
+) -> Result<Vec<EditorConsoleListItem>, String> {
+    let path = file.to_string_lossy();
+
+    // A repository without commits has no HEAD to diff against
+    let diff = match run_git(dir, &["diff", "HEAD", "--", &path]) {
+        Ok(diff) => diff,
+        Err(_) => run_git(dir, &["diff", "--cached", "--", &path])?,
+    };
+
+    let mut items = Vec::new();
+    let mut line = 0;
+
+    for row in diff.lines() {
+        if row.starts_with("diff ") || row.starts_with("index ")
+            || row.starts_with("--- ") || row.starts_with("+++ ") {
+            continue;
+        }
+
+        // @@ -a,b +c,d @@, new lines start at c
+        if let Some(header) = row.strip_prefix("@@ ") {
+            line = header.split_whitespace()
+                .find_map(|range| range.strip_prefix('+'))
+                .and_then(|range| range.split(',').next())
+                .and_then(|start| start.parse::<usize>().ok())
+                .map_or(0, |start| start.saturating_sub(1));
+        }
+
+        items.push(EditorConsoleListItem {
+            label: row.replace('\t', "    "),
+            file: Some(file.to_path_buf()),
+            line,
+            column: 0,
+        });
+
+        if row.starts_with('+') || row.starts_with(' ') {
+            line += 1;
+        }
+    }
+
+    Ok(items)
+}
+
+/// One line per commit of `file`, newest first
+pub fn git_log(
+    dir: &Path,
+    file: &Path
+) -> Result<String, String> {
+    let limit = format!("-n{}", GIT_LOG_LIMIT);
+
+    run_git(dir, &["log", &limit, "--follow", "--format=%h %ad %an  %s", "--date=short", "--", &file.to_string_lossy()])
+}
+
+/// Lines of `file` as committed at HEAD
+pub fn git_head_text(
+    file: &Path
+) -> Result<Vec<String>, String> {
+    let (Some(dir), Some(name)) = (file.parent(), file.file_name()) else {
+        return Err("NoFileOpen".to_string());
+    };
+
+    let spec = format!("HEAD:./{}", name.to_string_lossy());
+    let content = run_git(dir, &["show", &spec])?;
+
+    Ok(content.lines().map(|l| l.to_string()).collect())
+}
+
+/// Local branches, the current one marked with '*'
+pub fn git_branches(
+    dir: &Path
+) -> Result<String, String> {
+    run_git(dir, &["branch", "--format=%(HEAD) %(refname:short)"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_entries_keep_odd_paths_whole() {
+        let root = Path::new("/repo");
+        let status = " M a -> b.txt\0R  new name.rs\0old name.rs\0?? dir/#1.txt\0";
+        let items = parse_status(status, root);
+
+        let rows: Vec<(&str, PathBuf)> = items.iter()
+            .map(|item| (item.label.as_str(), item.file.clone().unwrap()))
+            .collect();
+
+        assert_eq!(rows, vec![
+            (" M a -> b.txt", root.join("a -> b.txt")),
+            ("R  old name.rs -> new name.rs", root.join("new name.rs")),
+            ("?? dir/#1.txt", root.join("dir/#1.txt")),
+        ]);
+    }
+}
@@ -0,0 +1,75 @@
+// Project search module, used by the <:grep> directive.
+//
+// Walks the current directory recursively, honouring .gitignore
+// and skipping hidden and binary files, and lists every matching
+// line as <file>:<line>: <text> in the console list.
+
+use std::fs;
+use std::path::Path;
+
+use ignore::WalkBuilder;
+use regex::Regex;
+
+use crate::console::editor_console_list::EditorConsoleListItem;
+
+pub const GREP_MAX_RESULTS: usize = 1000;
+pub const GREP_MAX_LINE_CHARS: usize = 120;
+
+/// Bytes checked for a NUL byte to tell binary files apart
+pub const GREP_BINARY_CHECK_LEN: usize = 8000;
+
+/// Search every text file under `dir`, returns the matches
+/// and whether the results were cut at GREP_MAX_RESULTS
+pub fn grep_directory(
+    dir: &Path,
+    regex: &Regex
+) -> (Vec<EditorConsoleListItem>, bool) {
+    let mut items = Vec::new();
+
+    let mut paths: Vec<_> = WalkBuilder::new(dir)
+        .require_git(false)
+        .build()
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path())
+        .collect();
+
+    paths.sort();
+
+    for path in paths {
+        let Ok(bytes) = fs::read(&path) else {
+            continue;
+        };
+
+        if bytes.iter().take(GREP_BINARY_CHECK_LEN).any(|&b| b == 0) {
+            continue;
+        }
+
+        let Ok(content) = String::from_utf8(bytes) else {
+            continue;
+        };
+
+        let name = path.strip_prefix(dir).unwrap_or(&path).display().to_string();
+
+        for (line_index, line) in content.lines().enumerate() {
+            let Some(m) = regex.find(line) else {
+                continue;
+            };
+
+            if items.len() == GREP_MAX_RESULTS {
+                return (items, true);
+            }
+
+            let shown: String = line.trim().chars().take(GREP_MAX_LINE_CHARS).collect();
+
+            items.push(EditorConsoleListItem {
+                label: format!("{}:{}: {}", name, line_index, shown),
+                file: Some(path.clone()),
+                line: line_index,
+                column: line[..m.start()].chars().count(),
+            });
+        }
+    }
+
+    (items, false)
+}
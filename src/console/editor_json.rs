@@ -0,0 +1,518 @@
+// JSON module, used by the language server client, see editor_lsp_client.rs.
+//
+// Just what JSON-RPC needs: values are parsed from text and written
+// back to it, objects keep their keys in the order they were given.
+// Looking up a missing key or index gives null, so nested fields are
+// read as value.get("a").get("b") without checking every step.
+// Arrays and objects nest JSON_MAX_DEPTH deep at most, so a message
+// can't run the recursive parser out of stack.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditorJson {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<EditorJson>),
+    Object(Vec<(String, EditorJson)>),
+}
+
+pub const JSON_MAX_DEPTH: usize = 128;
+
+static NULL: EditorJson = EditorJson::Null;
+
+impl EditorJson {
+    /// An object of `pairs`, in their order
+    pub fn object<const N: usize>(
+        pairs: [(&str, EditorJson); N]
+    ) -> EditorJson {
+        EditorJson::Object(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn parse(
+        text: &str
+    ) -> Result<EditorJson, String> {
+        let mut parser = EditorJsonParser { bytes: text.as_bytes(), at: 0, depth: 0 };
+
+        let value = parser.value()?;
+        parser.whitespace();
+
+        if parser.at != parser.bytes.len() {
+            return Err(format!("TrailingCharacters at {}", parser.at));
+        }
+
+        Ok(value)
+    }
+
+    /// The value of `key`, null if this isn't an object or lacks it
+    pub fn get(
+        &self,
+        key: &str
+    ) -> &EditorJson {
+        match self {
+            EditorJson::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map_or(&NULL, |(_, v)| v),
+            _ => &NULL,
+        }
+    }
+
+    /// Set `key` of an object, replacing its value if it has one
+    pub fn set(
+        &mut self,
+        key: &str,
+        value: EditorJson
+    ) {
+        if let EditorJson::Object(pairs) = self {
+            match pairs.iter_mut().find(|(k, _)| k == key) {
+                Some(pair) => pair.1 = value,
+                None => pairs.push((key.to_string(), value)),
+            }
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == EditorJson::Null
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            EditorJson::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            EditorJson::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_i64().and_then(|n| usize::try_from(n).ok())
+    }
+
+    /// The items of an array, none if this isn't one
+    pub fn as_array(&self) -> &[EditorJson] {
+        match self {
+            EditorJson::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    /// The pairs of an object, none if this isn't one
+    pub fn as_object(&self) -> &[(String, EditorJson)] {
+        match self {
+            EditorJson::Object(pairs) => pairs,
+            _ => &[],
+        }
+    }
+}
+
+impl From<&str> for EditorJson {
+    fn from(value: &str) -> EditorJson {
+        EditorJson::String(value.to_string())
+    }
+}
+
+impl From<String> for EditorJson {
+    fn from(value: String) -> EditorJson {
+        EditorJson::String(value)
+    }
+}
+
+impl From<bool> for EditorJson {
+    fn from(value: bool) -> EditorJson {
+        EditorJson::Bool(value)
+    }
+}
+
+impl From<i64> for EditorJson {
+    fn from(value: i64) -> EditorJson {
+        EditorJson::Number(value as f64)
+    }
+}
+
+impl From<usize> for EditorJson {
+    fn from(value: usize) -> EditorJson {
+        EditorJson::Number(value as f64)
+    }
+}
+
+impl From<Vec<EditorJson>> for EditorJson {
+    fn from(value: Vec<EditorJson>) -> EditorJson {
+        EditorJson::Array(value)
+    }
+}
+
+impl fmt::Display for EditorJson {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditorJson::Null => write!(f, "null"),
+            EditorJson::Bool(b) => write!(f, "{}", b),
+            // Integers without a fraction, ids and positions are read as such
+            EditorJson::Number(n) if !n.is_finite() => write!(f, "null"),
+            EditorJson::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            EditorJson::Number(n) => write!(f, "{}", n),
+            EditorJson::String(s) => write_string(f, s),
+            EditorJson::Array(items) => {
+                write!(f, "[")?;
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    write!(f, "{}", item)?;
+                }
+
+                write!(f, "]")
+            }
+            EditorJson::Object(pairs) => {
+                write!(f, "{{")?;
+
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(
+    f: &mut fmt::Formatter,
+    s: &str
+) -> fmt::Result {
+    write!(f, "\"")?;
+
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+
+    write!(f, "\"")
+}
+
+struct EditorJsonParser<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    /// Arrays and objects the parser is inside of
+    depth: usize,
+}
+
+impl EditorJsonParser<'_> {
+    fn whitespace(&mut self) {
+        while self.bytes.get(self.at).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn expect(
+        &mut self,
+        literal: &str,
+        value: EditorJson
+    ) -> Result<EditorJson, String> {
+        if !self.bytes[self.at..].starts_with(literal.as_bytes()) {
+            return Err(format!("UnexpectedCharacter at {}", self.at));
+        }
+
+        self.at += literal.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<EditorJson, String> {
+        self.whitespace();
+
+        match self.bytes.get(self.at) {
+            None => Err("UnexpectedEnd".to_string()),
+            Some(b'n') => self.expect("null", EditorJson::Null),
+            Some(b't') => self.expect("true", EditorJson::Bool(true)),
+            Some(b'f') => self.expect("false", EditorJson::Bool(false)),
+            Some(b'"') => self.string().map(EditorJson::String),
+            Some(b'[' | b'{') => self.nested(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(format!("UnexpectedCharacter at {}", self.at)),
+        }
+    }
+
+    /// An array or object, one level deeper
+    fn nested(&mut self) -> Result<EditorJson, String> {
+        if self.depth == JSON_MAX_DEPTH {
+            return Err(format!("NestedTooDeep at {}", self.at));
+        }
+
+        self.depth += 1;
+
+        let value = match self.bytes[self.at] {
+            b'[' => self.array(),
+            _ => self.object(),
+        };
+
+        self.depth -= 1;
+        value
+    }
+
+    fn array(&mut self) -> Result<EditorJson, String> {
+        self.at += 1;
+        let mut items = Vec::new();
+
+        self.whitespace();
+
+        if self.bytes.get(self.at) == Some(&b']') {
+            self.at += 1;
+            return Ok(EditorJson::Array(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+
+            match self.bytes.get(self.at) {
+                Some(b',') => self.at += 1,
+                Some(b']') => {
+                    self.at += 1;
+                    return Ok(EditorJson::Array(items));
+                }
+                _ => return Err(format!("ExpectedCommaOrBracket at {}", self.at)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<EditorJson, String> {
+        self.at += 1;
+        let mut pairs = Vec::new();
+
+        self.whitespace();
+
+        if self.bytes.get(self.at) == Some(&b'}') {
+            self.at += 1;
+            return Ok(EditorJson::Object(pairs));
+        }
+
+        loop {
+            self.whitespace();
+
+            if self.bytes.get(self.at) != Some(&b'"') {
+                return Err(format!("ExpectedKey at {}", self.at));
+            }
+
+            let key = self.string()?;
+            self.whitespace();
+
+            if self.bytes.get(self.at) != Some(&b':') {
+                return Err(format!("ExpectedColon at {}", self.at));
+            }
+
+            self.at += 1;
+            pairs.push((key, self.value()?));
+            self.whitespace();
+
+            match self.bytes.get(self.at) {
+                Some(b',') => self.at += 1,
+                Some(b'}') => {
+                    self.at += 1;
+                    return Ok(EditorJson::Object(pairs));
+                }
+                _ => return Err(format!("ExpectedCommaOrBrace at {}", self.at)),
+            }
+        }
+    }
+
+    /// -?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?, Rust's own float syntax is looser
+    fn number(&mut self) -> Result<EditorJson, String> {
+        let start = self.at;
+
+        self.skip(b"-");
+
+        let integer = if self.skip(b"0") { true } else { self.digits() > 0 };
+        let fraction = !self.skip(b".") || self.digits() > 0;
+        let exponent = !self.skip(b"eE") || { self.skip(b"+-"); self.digits() > 0 };
+
+        if !(integer && fraction && exponent) {
+            return Err(format!("InvalidNumber at {}", start));
+        }
+
+        std::str::from_utf8(&self.bytes[start..self.at])
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .map(EditorJson::Number)
+            .ok_or_else(|| format!("InvalidNumber at {}", start))
+    }
+
+    /// Step over one of `bytes`, true if there was one
+    fn skip(
+        &mut self,
+        bytes: &[u8]
+    ) -> bool {
+        let found = self.bytes.get(self.at).is_some_and(|b| bytes.contains(b));
+        self.at += found as usize;
+        found
+    }
+
+    /// Step over ASCII digits, returns how many
+    fn digits(&mut self) -> usize {
+        let start = self.at;
+
+        while self.bytes.get(self.at).is_some_and(|b| b.is_ascii_digit()) {
+            self.at += 1;
+        }
+
+        self.at - start
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.at += 1;
+        let mut bytes = Vec::new();
+
+        loop {
+            let Some(&b) = self.bytes.get(self.at) else {
+                return Err("UnterminatedString".to_string());
+            };
+
+            self.at += 1;
+
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.bytes.get(self.at) else {
+                        return Err("UnterminatedString".to_string());
+                    };
+
+                    self.at += 1;
+
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(format!("InvalidEscape at {}", self.at - 1)),
+                    };
+
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b => bytes.push(b),
+            }
+        }
+
+        String::from_utf8(bytes).map_err(|_| "InvalidUtf8".to_string())
+    }
+
+    /// The character of a \uXXXX escape, and of the low half after it for surrogate pairs,
+    /// a surrogate without its other half is U+FFFD
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4(self.at)?;
+        self.at += 4;
+
+        if (0xD800..0xDC00).contains(&high) && self.bytes[self.at..].starts_with(b"\\u") {
+            let low = self.hex4(self.at + 2)?;
+
+            // Otherwise the next escape is a character of its own
+            if (0xDC00..0xE000).contains(&low) {
+                self.at += 6;
+                return Ok(char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).unwrap());
+            }
+        }
+
+        Ok(char::from_u32(high).unwrap_or('\u{FFFD}'))
+    }
+
+    /// The 4 hex digits at `at`
+    fn hex4(
+        &self,
+        at: usize
+    ) -> Result<u32, String> {
+        let digits = self.bytes.get(at..at + 4).ok_or("UnterminatedString")?;
+
+        if !digits.iter().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("InvalidEscape at {}", at));
+        }
+
+        Ok(u32::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trips() {
+        let text = r#"{"a":[1,-2.5,true,null],"b":"q\"\\\n\u00e9\ud83d\ude00","c":{}}"#;
+        let json = EditorJson::parse(text).unwrap();
+
+        assert_eq!(json.get("a").as_array()[0].as_i64(), Some(1));
+        assert_eq!(json.get("b").as_str(), Some("q\"\\\né😀"));
+        assert!(json.get("missing").get("deeper").is_null());
+        assert_eq!(EditorJson::parse(&json.to_string()).unwrap(), json);
+        assert!(EditorJson::parse("{\"a\":}").is_err());
+    }
+
+    #[test]
+    fn numbers_follow_the_json_grammar() {
+        for (text, number) in [("0", 0.0), ("-0.5", -0.5), ("12e2", 1200.0), ("1.5E-1", 0.15), ("-7e+1", -70.0)] {
+            assert_eq!(EditorJson::parse(text), Ok(EditorJson::Number(number)), "{}", text);
+        }
+
+        for text in ["-", "01", "-.5", "1.", ".5", "1e", "1e+", "+1", "1.2.3", "--1", "0x10", "1_000"] {
+            assert!(EditorJson::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn lone_surrogates_are_replaced() {
+        let parse = |text: &str| EditorJson::parse(text).unwrap().as_str().map(|s| s.to_string());
+
+        assert_eq!(parse(r#""\ud83d\ude00""#).as_deref(), Some("😀"));
+        assert_eq!(parse(r#""\ud83d""#).as_deref(), Some("\u{FFFD}"));
+        assert_eq!(parse(r#""\ude00\ud83d""#).as_deref(), Some("\u{FFFD}\u{FFFD}"));
+        // The escape after a high half stays a character of its own
+        assert_eq!(parse(r#""\ud83d\u0041""#).as_deref(), Some("\u{FFFD}A"));
+        assert_eq!(parse(r#""\ud83d\n""#).as_deref(), Some("\u{FFFD}\n"));
+
+        assert!(EditorJson::parse(r#""\u+041""#).is_err());
+        assert!(EditorJson::parse(r#""\ud83d\u00""#).is_err());
+    }
+
+    #[test]
+    fn trailing_characters_are_errors() {
+        for text in ["[1] 2", "{} {}", "null,", "\"a\"\"", "tru", "nulll"] {
+            assert!(EditorJson::parse(text).is_err(), "{}", text);
+        }
+
+        assert_eq!(EditorJson::parse(" [1] \r\n"), Ok(EditorJson::Array(vec![EditorJson::Number(1.0)])));
+    }
+
+    #[test]
+    fn nesting_is_capped() {
+        // Arrays holding objects holding arrays, `depth` of them in all
+        let nested = |depth: usize| format!("{}1{}", "[{\"a\":".repeat(depth / 2), "}]".repeat(depth / 2));
+
+        assert!(EditorJson::parse(&nested(JSON_MAX_DEPTH)).is_ok());
+        assert!(EditorJson::parse(&nested(JSON_MAX_DEPTH + 2)).is_err());
+        assert!(EditorJson::parse(&format!("{}1{}", "[".repeat(JSON_MAX_DEPTH), "]".repeat(JSON_MAX_DEPTH))).is_ok());
+        assert!(EditorJson::parse(&format!("{}1{}", "[".repeat(JSON_MAX_DEPTH + 1), "]".repeat(JSON_MAX_DEPTH + 1))).is_err());
+
+        // Far past the stack a recursive parser has, without the cap
+        assert!(EditorJson::parse(&"[".repeat(1_000_000)).is_err());
+    }
+}
@@ -0,0 +1,741 @@
+// Language server module, used by <:hv>, <:def>, <:refs>, <:rn> and completion.
+//
+// Files are served by the locally installed server of their extension,
+// matched as load_keywords_for_extension matches languages:
+//      rs                          : rust-analyzer
+//      c h cpp hpp cc cxx hxx      : clangd
+//      java                        : jdtls
+// A server starts the first time one of its files is the current buffer,
+// in the project root above the file, and is sent the buffer's text
+// whenever it changes. Its diagnostics are drawn as the build's are, see
+// editor_build.rs. Answers arrive while the editor keeps drawing, and
+// apply_lsp_event acts on them once they do. A server that isn't
+// installed or exits isn't started again until <:lsp> switches them
+// off and back on. A rename is dropped if a file it edits changed since
+// the server was asked, and files that can't be read are left out of it.
+// The protocol itself is in editor_lsp_client.rs.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::console::editor_build::{EditorDiagnostic, EditorSeverity};
+use crate::console::editor_console::EditorConsole;
+use crate::console::editor_console_list::EditorConsoleListItem;
+use crate::console::editor_directives::{open_file, open_file_at, place_cursor, try_open_file};
+use crate::console::editor_file_system::EditorFileSystem;
+use crate::console::editor_json::EditorJson;
+use crate::console::editor_lsp_client::*;
+use crate::console::editor_symbol_index::EditorJump;
+use crate::text::editor_autocomplete::EditorAutocomplete;
+use crate::text::editor_buffer::EditorBuffers;
+use crate::text::editor_cursor::EditorCursor;
+use crate::text::editor_history::{EditorEditKind, EditorHistory};
+use crate::text::editor_language_manager::EditorLanguageKeywords;
+
+pub struct EditorLspServer {
+    pub command: &'static str,
+    pub args: &'static [&'static str],
+    /// Extensions served, and the language id the server knows each by
+    pub languages: &'static [(&'static str, &'static str)],
+    /// Files marking a project's root, the nearest directory above with one is used
+    pub roots: &'static [&'static str],
+}
+
+pub static LSP_SERVERS: [EditorLspServer; 3] = [
+    EditorLspServer {
+        command: "rust-analyzer",
+        args: &[],
+        languages: &[("rs", "rust")],
+        roots: &["Cargo.toml"],
+    },
+    EditorLspServer {
+        command: "clangd",
+        args: &[],
+        languages: &[("c", "c"), ("h", "c"), ("cpp", "cpp"), ("hpp", "cpp"), ("cc", "cpp"), ("cxx", "cpp"), ("hxx", "cpp")],
+        roots: &["compile_commands.json", "compile_flags.txt", ".clangd", ".git"],
+    },
+    EditorLspServer {
+        command: "jdtls",
+        args: &[],
+        languages: &[("java", "java")],
+        roots: &["pom.xml", "build.gradle", "build.gradle.kts", ".git"],
+    },
+];
+
+/// The server of a file's extension, and the file's language id
+pub fn lsp_server_for(
+    file: &Path
+) -> Option<(&'static EditorLspServer, &'static str)> {
+    let ext = file.extension()?.to_str()?;
+
+    LSP_SERVERS.iter().find_map(|server| {
+        server.languages.iter()
+            .find(|(e, _)| *e == ext)
+            .map(|(_, id)| (server, *id))
+    })
+}
+
+/// What a request was sent for, kept until its answer arrives
+enum EditorLspRequest {
+    Hover { file: PathBuf, line: usize },
+    Definition { word: String, from: EditorJump },
+    References { word: String },
+    Completion { start: (usize, usize) },
+    /// The version of `file` the server was asked about
+    Rename { name: String, file: PathBuf, version: i64 },
+}
+
+/// An answer of a server, see apply_lsp_event
+pub enum EditorLspEvent {
+    Message(String),
+    Hover { file: PathBuf, line: usize, text: Option<String> },
+    Definition { word: String, from: EditorJump, locations: Vec<EditorLspLocation> },
+    References { word: String, locations: Vec<EditorLspLocation> },
+    Completion { start: (usize, usize), words: Vec<String> },
+    Rename { name: String, edits: Vec<EditorLspFileEdit> },
+}
+
+/// A file the server was given, and the text it has of it
+struct EditorLspDocument {
+    server: &'static str,
+    uri: String,
+    /// The path the server names the file by, diagnostics are kept by it
+    canonical: PathBuf,
+    version: i64,
+    text: Vec<String>,
+    unsaved: bool,
+}
+
+pub struct EditorLsp {
+    /// Servers are switched on, see <:lsp>
+    pub enabled: bool,
+    clients: HashMap<&'static str, EditorLspClient>,
+    /// Servers that couldn't start or exited, and why
+    failed: HashMap<&'static str, String>,
+    documents: HashMap<PathBuf, EditorLspDocument>,
+    diagnostics: HashMap<PathBuf, Vec<EditorDiagnostic>>,
+    pending: HashMap<(&'static str, i64), EditorLspRequest>,
+    events: Vec<EditorLspEvent>,
+}
+
+impl EditorLsp {
+    pub fn new() -> EditorLsp {
+        EditorLsp {
+            enabled: true,
+            clients: HashMap::new(),
+            failed: HashMap::new(),
+            documents: HashMap::new(),
+            diagnostics: HashMap::new(),
+            pending: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Keep the current buffer's server in step with it, and take in what the servers sent
+    pub fn update(
+        &mut self,
+        enabled: bool,
+        file: Option<&Path>,
+        text: &[String],
+        unsaved: bool
+    ) -> Vec<EditorLspEvent> {
+        if self.enabled != enabled {
+            self.stop_all();
+            self.enabled = enabled;
+        }
+
+        // Servers like rust-analyzer check the project again once a file is saved
+        if let Some(file) = file && let Ok(server) = self.sync(file, text) && let Some(document) = self.documents.get_mut(file) {
+            if document.unsaved && !unsaved && let Some(client) = self.clients.get_mut(server) {
+                client.notify("textDocument/didSave", EditorJson::object([
+                    ("textDocument", EditorJson::object([("uri", document.uri.clone().into())])),
+                ]));
+            }
+
+            document.unsaved = unsaved;
+        }
+
+        let mut received = Vec::new();
+        let mut exited = Vec::new();
+
+        for (&server, client) in self.clients.iter_mut() {
+            received.extend(client.poll().into_iter().map(|message| (server, message)));
+
+            if client.has_exited() {
+                exited.push(server);
+            }
+        }
+
+        for (server, message) in received {
+            self.receive(server, message);
+        }
+
+        for server in exited {
+            self.clients.remove(server);
+            self.failed.insert(server, "ServerExited".to_string());
+            self.documents.retain(|_, d| d.server != server);
+            self.pending.retain(|(s, _), _| *s != server);
+            self.events.push(EditorLspEvent::Message(format!("ServerExited {} <:lsp>", server)));
+        }
+
+        std::mem::take(&mut self.events)
+    }
+
+    /// Stop every server, and forget what they said
+    pub fn stop_all(&mut self) {
+        for client in self.clients.values_mut() {
+            client.stop();
+        }
+
+        self.clients.clear();
+        self.failed.clear();
+        self.documents.clear();
+        self.diagnostics.clear();
+        self.pending.clear();
+    }
+
+    /// Lines of `file` with errors or warnings, and the worst of each
+    pub fn underlines(
+        &self,
+        file: Option<&Path>
+    ) -> Vec<(usize, EditorSeverity)> {
+        let Some(diagnostics) = file.and_then(|file| self.file_diagnostics(file)) else {
+            return Vec::new();
+        };
+
+        let mut lines: Vec<(usize, EditorSeverity)> = diagnostics.iter()
+            .filter(|d| d.severity != EditorSeverity::Note)
+            .map(|d| (d.line, d.severity))
+            .collect();
+
+        lines.sort();
+        lines.dedup_by_key(|(line, _)| *line);
+        lines
+    }
+
+    /// The diagnostics of a line of `file`
+    pub fn line_diagnostics(
+        &self,
+        file: &Path,
+        line: usize
+    ) -> Vec<&EditorDiagnostic> {
+        self.file_diagnostics(file).map_or(Vec::new(), |diagnostics| {
+            diagnostics.iter().filter(|d| d.line == line).collect()
+        })
+    }
+
+    fn file_diagnostics(
+        &self,
+        file: &Path
+    ) -> Option<&Vec<EditorDiagnostic>> {
+        let document = self.documents.get(file)?;
+        self.diagnostics.get(&document.canonical)
+    }
+
+    /// Ask for the hover info at `xy`, (column, line) of `text`
+    pub fn hover(
+        &mut self,
+        file: &Path,
+        text: &[String],
+        xy: (usize, usize)
+    ) -> Result<(), String> {
+        let request = EditorLspRequest::Hover { file: file.to_path_buf(), line: xy.1 };
+        self.request(file, text, xy, "textDocument/hover", request, |_| {})
+    }
+
+    /// Ask where the identifier at `xy` is defined, `from` is where <:back> returns to
+    pub fn definition(
+        &mut self,
+        file: &Path,
+        text: &[String],
+        xy: (usize, usize),
+        word: String,
+        from: EditorJump
+    ) -> Result<(), String> {
+        self.request(file, text, xy, "textDocument/definition", EditorLspRequest::Definition { word, from }, |_| {})
+    }
+
+    pub fn references(
+        &mut self,
+        file: &Path,
+        text: &[String],
+        xy: (usize, usize),
+        word: String
+    ) -> Result<(), String> {
+        self.request(file, text, xy, "textDocument/references", EditorLspRequest::References { word }, |params| {
+            params.set("context", EditorJson::object([("includeDeclaration", true.into())]));
+        })
+    }
+
+    /// Ask for completions at `xy`, of the identifier starting at `start`
+    pub fn completion(
+        &mut self,
+        file: &Path,
+        text: &[String],
+        start: (usize, usize),
+        xy: (usize, usize)
+    ) -> Result<(), String> {
+        self.request(file, text, xy, "textDocument/completion", EditorLspRequest::Completion { start }, |_| {})
+    }
+
+    pub fn rename(
+        &mut self,
+        file: &Path,
+        text: &[String],
+        xy: (usize, usize),
+        name: String
+    ) -> Result<(), String> {
+        let new_name = EditorJson::from(name.as_str());
+
+        self.sync(file, text)?;
+        let version = self.documents.get(file).map_or(0, |document| document.version);
+
+        self.request(file, text, xy, "textDocument/rename", EditorLspRequest::Rename { name, file: file.to_path_buf(), version }, |params| {
+            params.set("newName", new_name);
+        })
+    }
+
+    /// Send a request about the position `xy` of `file`, once the server has its text
+    fn request(
+        &mut self,
+        file: &Path,
+        text: &[String],
+        xy: (usize, usize),
+        method: &str,
+        request: EditorLspRequest,
+        extend: impl FnOnce(&mut EditorJson)
+    ) -> Result<(), String> {
+        let server = self.sync(file, text)?;
+
+        let (Some(client), Some(document)) = (self.clients.get_mut(server), self.documents.get(file)) else {
+            return Err("NoLanguageServer".to_string());
+        };
+
+        let mut params = text_document_position(&document.uri, text, xy);
+        extend(&mut params);
+
+        let id = client.request(method, params);
+        self.pending.insert((server, id), request);
+
+        Ok(())
+    }
+
+    /// Start the server of `file` if needed, and send it the file's text if it changed
+    fn sync(
+        &mut self,
+        file: &Path,
+        text: &[String]
+    ) -> Result<&'static str, String> {
+        if !self.enabled {
+            return Err("LanguageServersOff".to_string());
+        }
+
+        let Some((server, language_id)) = lsp_server_for(file) else {
+            return Err("NoLanguageServer".to_string());
+        };
+
+        if let Some(reason) = self.failed.get(server.command) {
+            return Err(format!("{} {}", reason, server.command));
+        }
+
+        if !self.clients.contains_key(server.command) {
+            self.start(server, file)?;
+        }
+
+        let Some(client) = self.clients.get_mut(server.command) else {
+            return Err("NoLanguageServer".to_string());
+        };
+
+        match self.documents.get_mut(file) {
+            Some(document) => {
+                if document.text != text {
+                    document.version += 1;
+                    document.text = text.to_vec();
+
+                    client.notify("textDocument/didChange", EditorJson::object([
+                        ("textDocument", EditorJson::object([
+                            ("uri", document.uri.clone().into()),
+                            ("version", document.version.into()),
+                        ])),
+                        ("contentChanges", vec![EditorJson::object([("text", document_text(text).into())])].into()),
+                    ]));
+                }
+            }
+
+            None => {
+                let canonical = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+                let uri = path_to_uri(&canonical);
+
+                client.notify("textDocument/didOpen", EditorJson::object([
+                    ("textDocument", EditorJson::object([
+                        ("uri", uri.clone().into()),
+                        ("languageId", language_id.into()),
+                        ("version", 1i64.into()),
+                        ("text", document_text(text).into()),
+                    ])),
+                ]));
+
+                self.documents.insert(file.to_path_buf(), EditorLspDocument {
+                    server: server.command,
+                    uri,
+                    canonical,
+                    version: 1,
+                    text: text.to_vec(),
+                    unsaved: false,
+                });
+            }
+        }
+
+        Ok(server.command)
+    }
+
+    /// Start `server` in the project of `file`, one that isn't installed is not tried again
+    fn start(
+        &mut self,
+        server: &'static EditorLspServer,
+        file: &Path
+    ) -> Result<(), String> {
+        if which::which(server.command).is_err() {
+            self.failed.insert(server.command, "ServerNotFound".to_string());
+            return Err(format!("ServerNotFound {}", server.command));
+        }
+
+        let dir = file.parent().unwrap_or(file);
+
+        let root = dir.ancestors()
+            .find(|d| server.roots.iter().any(|r| d.join(r).exists()))
+            .unwrap_or(dir);
+
+        match EditorLspClient::spawn(server.command, server.args, root) {
+            Ok(client) => {
+                self.clients.insert(server.command, client);
+                Ok(())
+            }
+
+            Err(e) => {
+                self.failed.insert(server.command, "ServerNotStarted".to_string());
+                Err(format!("ServerNotStarted {}: {}", server.command, e))
+            }
+        }
+    }
+
+    fn receive(
+        &mut self,
+        server: &'static str,
+        message: EditorLspMessage
+    ) {
+        match message {
+            EditorLspMessage::Notification { method, params } => match method.as_str() {
+                "textDocument/publishDiagnostics" => {
+                    if let Some((file, diagnostics)) = parse_published_diagnostics(&params) {
+                        if diagnostics.is_empty() {
+                            self.diagnostics.remove(&file);
+                        } else {
+                            self.diagnostics.insert(file, diagnostics);
+                        }
+                    }
+                }
+
+                // Errors only, servers are chatty about the rest
+                "window/showMessage" if params.get("type").as_i64() == Some(1) => {
+                    let message = params.get("message").as_str().unwrap_or("").lines().next().unwrap_or("");
+                    self.events.push(EditorLspEvent::Message(format!("{}: {} <:lsp>", server, message)));
+                }
+
+                _ => {}
+            },
+
+            EditorLspMessage::Response { id, result } => {
+                let Some(request) = self.pending.remove(&(server, id)) else {
+                    return;
+                };
+
+                let result = match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        let directive = match request {
+                            EditorLspRequest::Hover { .. } => "hv",
+                            EditorLspRequest::Definition { .. } => "def",
+                            EditorLspRequest::References { .. } => "refs",
+                            EditorLspRequest::Rename { .. } => "rn",
+                            // The popup goes on with the buffer's identifiers
+                            EditorLspRequest::Completion { .. } => return,
+                        };
+
+                        self.events.push(EditorLspEvent::Message(format!("{} <:{}>", e, directive)));
+                        return;
+                    }
+                };
+
+                self.events.push(match request {
+                    EditorLspRequest::Hover { file, line } => EditorLspEvent::Hover { file, line, text: parse_hover(&result) },
+                    EditorLspRequest::Definition { word, from } => EditorLspEvent::Definition { word, from, locations: parse_locations(&result) },
+                    EditorLspRequest::References { word } => EditorLspEvent::References { word, locations: parse_locations(&result) },
+                    EditorLspRequest::Completion { start } => EditorLspEvent::Completion { start, words: parse_completion(&result) },
+                    EditorLspRequest::Rename { name, file, version } => self.rename_event(name, &file, version, &result),
+                });
+            }
+        }
+    }
+
+    /// The edits of a rename, unless the text of a file they edit changed since
+    /// the server made them, a server sending no versions is only checked on `file`
+    fn rename_event(
+        &self,
+        name: String,
+        file: &Path,
+        version: i64,
+        result: &EditorJson
+    ) -> EditorLspEvent {
+        let edits = parse_workspace_edit(result);
+        let version_of = |canonical: &Path| self.documents.values().find(|d| d.canonical == canonical).map(|d| d.version);
+
+        let moved = self.documents.get(file).map(|d| d.version) != Some(version)
+            || edits.iter().any(|e| e.version.is_some() && e.version != version_of(&e.file));
+
+        if moved {
+            return EditorLspEvent::Message("RenameOutdated, the text changed, try again <:rn>".to_string());
+        }
+
+        EditorLspEvent::Rename { name, edits }
+    }
+}
+
+/// The text of a document as sent to the server, every line ending with a line break
+fn document_text(
+    text: &[String]
+) -> String {
+    text.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Act on an answer of a server, once it arrived
+pub fn apply_lsp_event(
+    event: EditorLspEvent,
+    efs: &mut EditorFileSystem,
+    text: &mut Vec<String>,
+    cursor: &mut EditorCursor,
+    elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers,
+    console: &mut EditorConsole,
+    completion: &mut EditorAutocomplete
+) {
+    let mut show = |message: String| {
+        console.message = message;
+        console.showing_message = true;
+    };
+
+    match event {
+        EditorLspEvent::Message(message) => show(message),
+
+        // The line's diagnostics first, then what the server knows of the identifier
+        EditorLspEvent::Hover { file, line, text: hover } => {
+            let mut rows: Vec<String> = efs.lsp.line_diagnostics(&file, line).iter()
+                .map(|d| format!("{}: {}", d.severity.name(), d.message))
+                .collect();
+
+            rows.extend(hover.iter().flat_map(|h| h.lines().map(str::to_string)));
+
+            match rows.as_slice() {
+                [] => show("NoHoverInfo <:hv>".to_string()),
+                [row] => show(row.clone()),
+                _ => {
+                    show(format!("\n\n{}", rows.join("\n")));
+                    console.showing_manual = true;
+                }
+            }
+        }
+
+        // The symbol index is asked if the server doesn't know, e.g. while it still indexes
+        EditorLspEvent::Definition { word, from, locations } => {
+            let items = if locations.is_empty() {
+                efs.symbols.find(&word)
+            } else {
+                location_items(&locations, efs, text, buffers)
+            };
+
+            if items.is_empty() {
+                show("DefinitionNotFound <:def>".to_string());
+                return;
+            }
+
+            efs.symbols.push_jump(from);
+
+            if let [item] = items.as_slice() && let Some(file) = item.file.clone() {
+                open_file_at(file, item.line, item.column, efs, text, cursor, elk, history, buffers);
+            } else {
+                console.list.show(format!("{} definitions of '{}'", items.len(), word), items);
+                console.mode = true;
+            }
+        }
+
+        EditorLspEvent::References { word, locations } => {
+            if locations.is_empty() {
+                show("ReferencesNotFound <:refs>".to_string());
+                return;
+            }
+
+            let items = location_items(&locations, efs, text, buffers);
+            console.list.show(format!("{} references of '{}'", items.len(), word), items);
+            console.mode = true;
+        }
+
+        EditorLspEvent::Completion { start, words } => {
+            if completion.set_server(start, words) {
+                completion.update(text, cursor, elk, buffers);
+            }
+        }
+
+        // Every file is edited in its buffer and left unsaved, one undo step each
+        EditorLspEvent::Rename { name, edits } => {
+            if edits.is_empty() {
+                show("NothingToRename <:rn>".to_string());
+                return;
+            }
+
+            let origin = efs.current_file.clone();
+            let mut count = 0;
+            let mut skipped = Vec::new();
+
+            for file_edit in edits {
+                let file = buffer_path(&file_edit.file, efs, buffers);
+
+                // Renaming in an empty buffer would write over the file on <:w>
+                if let Err(e) = try_open_file(file.clone(), efs, text, cursor, elk, history, buffers) {
+                    skipped.push(format!("FileNotRenamed '{}': {}", file.display(), e));
+                    continue;
+                }
+
+                history.begin(text, cursor, 0, text.len(), EditorEditKind::Directive);
+                apply_text_edits(text, &file_edit.edits);
+                place_cursor(cursor.xy.1, cursor.xy.0, text, cursor);
+                history.end(text, cursor);
+                history.seal();
+
+                efs.unsaved_changes = true;
+                count += 1;
+            }
+
+            if let Some(origin) = origin {
+                open_file(origin, efs, text, cursor, elk, history, buffers);
+            }
+
+            let renamed = format!("Renamed to '{}' in {} files, unsaved <:w>", name, count);
+
+            if skipped.is_empty() {
+                show(renamed);
+            } else {
+                show(format!("\n\n{}\n{}", renamed, skipped.join("\n")));
+                console.showing_manual = true;
+            }
+        }
+    }
+}
+
+/// The path an open buffer has for a server's file, or the file's own
+fn buffer_path(
+    file: &Path,
+    efs: &EditorFileSystem,
+    buffers: &EditorBuffers
+) -> PathBuf {
+    (0..buffers.buffers.len())
+        .filter_map(|i| buffers.file_of(i, efs))
+        .find(|open| {
+            let absolute = efs.current_dir.as_ref().map_or(open.clone(), |dir| dir.join(open));
+            open == file || fs::canonicalize(absolute).is_ok_and(|c| c == file)
+        })
+        .unwrap_or_else(|| file.to_path_buf())
+}
+
+/// Console list rows of locations, "file:line: text" as <:grep> shows them
+fn location_items(
+    locations: &[EditorLspLocation],
+    efs: &EditorFileSystem,
+    text: &[String],
+    buffers: &EditorBuffers
+) -> Vec<EditorConsoleListItem> {
+    let mut lines_of: HashMap<PathBuf, Vec<String>> = HashMap::new();
+
+    locations.iter()
+        .map(|location| {
+            let file = buffer_path(&location.file, efs, buffers);
+
+            // Open buffers may differ from the disk
+            let lines = lines_of.entry(file.clone()).or_insert_with(|| match buffers.find(&file, efs) {
+                Some(i) if i == buffers.active => text.to_vec(),
+                Some(i) => buffers.buffers[i].text.clone(),
+                None => efs.load_file(&file).unwrap_or_default(),
+            });
+
+            let line = lines.get(location.line).map_or("", String::as_str);
+            let shown = efs.current_dir.as_ref().and_then(|dir| file.strip_prefix(dir).ok()).unwrap_or(&file).display();
+
+            EditorConsoleListItem {
+                label: format!("{}:{}: {}", shown, location.line, line.trim()),
+                file: Some(file.clone()),
+                line: location.line,
+                column: char_column(line, location.character),
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A server that was sent `file` at `version`
+    fn with_document(
+        file: &str,
+        version: i64
+    ) -> EditorLsp {
+        let mut lsp = EditorLsp::new();
+
+        lsp.documents.insert(PathBuf::from(file), EditorLspDocument {
+            server: "rust-analyzer",
+            uri: path_to_uri(Path::new(file)),
+            canonical: PathBuf::from(file),
+            version,
+            text: vec![],
+            unsaved: false,
+        });
+
+        lsp
+    }
+
+    /// A rename result editing `/p/main.rs`, made for `version` of it
+    fn rename_result(
+        version: &str
+    ) -> EditorJson {
+        let edit = r#"{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":1}},"newText":"b"}"#;
+        let text = format!(r#"{{"documentChanges":[{{"textDocument":{{"uri":"file:///p/main.rs","version":{}}},"edits":[{}]}}]}}"#, version, edit);
+
+        EditorJson::parse(&text).unwrap()
+    }
+
+    fn is_rename(
+        event: &EditorLspEvent
+    ) -> bool {
+        matches!(event, EditorLspEvent::Rename { edits, .. } if edits.len() == 1)
+    }
+
+    #[test]
+    fn renames_apply_to_the_text_they_were_made_for() {
+        let lsp = with_document("/p/main.rs", 4);
+        let file = Path::new("/p/main.rs");
+
+        assert!(is_rename(&lsp.rename_event("b".to_string(), file, 4, &rename_result("4"))));
+        assert!(is_rename(&lsp.rename_event("b".to_string(), file, 4, &rename_result("null"))));
+    }
+
+    #[test]
+    fn renames_of_changed_text_are_dropped() {
+        let lsp = with_document("/p/main.rs", 5);
+        let file = Path::new("/p/main.rs");
+
+        // Typed in since the request, or the server answered for an older text
+        assert!(!is_rename(&lsp.rename_event("b".to_string(), file, 4, &rename_result("4"))));
+        assert!(!is_rename(&lsp.rename_event("b".to_string(), file, 5, &rename_result("3"))));
+    }
+}
@@ -0,0 +1,921 @@
+// Language server client module, the protocol side of editor_lsp.rs.
+//
+// A server is a child process speaking JSON-RPC over its stdin/stdout,
+// every message framed by a Content-Length header, see editor_json.rs.
+// A reader thread parses the server's messages and sends them on, the
+// editor takes them in every frame and matches responses to requests
+// by id. Messages sent before the server answered initialize wait in a
+// queue. Requests of the server are answered right away with nothing,
+// the editor has no settings or progress bars to give it.
+// Positions are UTF-16 code units, as the protocol counts them.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::VERSION;
+use crate::console::editor_build::{EditorDiagnostic, EditorSeverity};
+use crate::console::editor_json::EditorJson;
+
+/// A message of the server, requests it makes are answered by the client
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditorLspMessage {
+    /// The result of the request with `id`, or its error's message
+    Response { id: i64, result: Result<EditorJson, String> },
+    Notification { method: String, params: EditorJson },
+}
+
+/// A place in a file, line and character are 0-based, the character in UTF-16 units
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorLspLocation {
+    pub file: PathBuf,
+    pub line: usize,
+    pub character: usize,
+}
+
+/// Replace the text from `start` to `end`, (line, UTF-16 character), with `text`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorLspTextEdit {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub text: String,
+}
+
+/// The edits of one file of a WorkspaceEdit
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorLspFileEdit {
+    pub file: PathBuf,
+    /// The version of the file the server made the edits for, if it said
+    pub version: Option<i64>,
+    pub edits: Vec<EditorLspTextEdit>,
+}
+
+pub struct EditorLspClient {
+    child: Child,
+    stdin: ChildStdin,
+    receiver: Receiver<EditorJson>,
+    next_id: i64,
+    initialize_id: i64,
+    /// The server answered initialize, until then messages wait in `queued`
+    pub initialized: bool,
+    queued: Vec<EditorJson>,
+}
+
+impl EditorLspClient {
+    /// Start `command` in the project at `root` and send it initialize
+    pub fn spawn(
+        command: &str,
+        args: &[&str],
+        root: &Path
+    ) -> Result<EditorLspClient, String> {
+        let mut child = Command::new(command)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let stdin = child.stdin.take().ok_or("NoServerInput")?;
+        let stdout = child.stdout.take().ok_or("NoServerOutput")?;
+
+        let (sender, receiver) = mpsc::channel();
+        spawn_reader(stdout, sender);
+
+        let mut client = EditorLspClient {
+            child,
+            stdin,
+            receiver,
+            next_id: 1,
+            initialize_id: 0,
+            initialized: false,
+            queued: Vec::new(),
+        };
+
+        client.initialize_id = client.next_id;
+        client.next_id += 1;
+
+        let message = EditorJson::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", client.initialize_id.into()),
+            ("method", "initialize".into()),
+            ("params", initialize_params(root)),
+        ]);
+
+        client.write(&message);
+
+        Ok(client)
+    }
+
+    /// Send a request, returns the id its response will have
+    pub fn request(
+        &mut self,
+        method: &str,
+        params: EditorJson
+    ) -> i64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.send(EditorJson::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+
+        id
+    }
+
+    pub fn notify(
+        &mut self,
+        method: &str,
+        params: EditorJson
+    ) {
+        self.send(EditorJson::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+    }
+
+    /// The messages that arrived since the last call
+    pub fn poll(&mut self) -> Vec<EditorLspMessage> {
+        let mut messages = Vec::new();
+
+        while let Ok(json) = self.receiver.try_recv() {
+            let id = json.get("id");
+
+            match json.get("method").as_str() {
+                Some(method) if !id.is_null() => self.answer(id.clone(), method, json.get("params")),
+
+                Some(method) => messages.push(EditorLspMessage::Notification {
+                    method: method.to_string(),
+                    params: json.get("params").clone(),
+                }),
+
+                None => {
+                    let Some(id) = id.as_i64() else {
+                        continue;
+                    };
+
+                    // A server that failed to initialize still gets the queue, and answers it with errors
+                    if id == self.initialize_id {
+                        self.finish_initialize();
+                        continue;
+                    }
+
+                    let error = json.get("error");
+
+                    let result = if error.is_null() {
+                        Ok(json.get("result").clone())
+                    } else {
+                        Err(error.get("message").as_str().unwrap_or("ServerError").to_string())
+                    };
+
+                    messages.push(EditorLspMessage::Response { id, result });
+                }
+            }
+        }
+
+        messages
+    }
+
+    /// True once the server's process is gone
+    pub fn has_exited(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Ask the server to exit, and make sure it does
+    pub fn stop(&mut self) {
+        self.write(&EditorJson::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", self.next_id.into()),
+            ("method", "shutdown".into()),
+        ]));
+
+        self.write(&EditorJson::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "exit".into()),
+        ]));
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    fn finish_initialize(&mut self) {
+        self.initialized = true;
+        self.notify("initialized", EditorJson::object([]));
+
+        for message in std::mem::take(&mut self.queued) {
+            self.write(&message);
+        }
+    }
+
+    fn answer(
+        &mut self,
+        id: EditorJson,
+        method: &str,
+        params: &EditorJson
+    ) {
+        // One setting per item asked for, none of them set
+        let result = match method {
+            "workspace/configuration" => EditorJson::Array(vec![EditorJson::Null; params.get("items").as_array().len()]),
+            _ => EditorJson::Null,
+        };
+
+        self.write(&EditorJson::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id),
+            ("result", result),
+        ]));
+    }
+
+    fn send(
+        &mut self,
+        message: EditorJson
+    ) {
+        if self.initialized {
+            self.write(&message);
+        } else {
+            self.queued.push(message);
+        }
+    }
+
+    /// Write a framed message, a server that is gone is noticed by has_exited
+    fn write(
+        &mut self,
+        message: &EditorJson
+    ) {
+        let body = message.to_string();
+        let _ = write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = self.stdin.flush();
+    }
+}
+
+/// Send every message of `stream` until it closes
+fn spawn_reader(
+    stream: impl Read + Send + 'static,
+    sender: Sender<EditorJson>
+) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+
+        while let Some(message) = read_message(&mut reader) {
+            // The client was dropped
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// The next framed message, none once the stream ends,
+/// bodies that aren't JSON are skipped
+fn read_message(
+    reader: &mut impl BufRead
+) -> Option<EditorJson> {
+    loop {
+        let mut length = None;
+
+        loop {
+            let mut header = String::new();
+
+            if reader.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+
+            let header = header.trim_end();
+
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') && name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let Some(length) = length else {
+            continue;
+        };
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+
+        if let Ok(message) = EditorJson::parse(&String::from_utf8_lossy(&body)) {
+            return Some(message);
+        }
+    }
+}
+
+/// What the editor can do, sent with initialize
+fn initialize_params(
+    root: &Path
+) -> EditorJson {
+    let uri = path_to_uri(root);
+    let name = root.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string());
+
+    EditorJson::object([
+        ("processId", (std::process::id() as i64).into()),
+        ("clientInfo", EditorJson::object([("name", "Muse".into()), ("version", VERSION.into())])),
+        ("rootUri", uri.clone().into()),
+        ("workspaceFolders", vec![EditorJson::object([("uri", uri.into()), ("name", name.into())])].into()),
+        ("capabilities", EditorJson::object([
+            ("textDocument", EditorJson::object([
+                ("synchronization", EditorJson::object([("didSave", true.into())])),
+                ("hover", EditorJson::object([("contentFormat", vec!["plaintext".into(), "markdown".into()].into())])),
+                ("completion", EditorJson::object([("completionItem", EditorJson::object([("snippetSupport", false.into())]))])),
+                ("definition", EditorJson::object([("linkSupport", true.into())])),
+                ("references", EditorJson::object([])),
+                ("rename", EditorJson::object([])),
+                ("publishDiagnostics", EditorJson::object([])),
+            ])),
+            ("workspace", EditorJson::object([
+                ("workspaceEdit", EditorJson::object([("documentChanges", true.into())])),
+                ("workspaceFolders", true.into()),
+            ])),
+            ("general", EditorJson::object([("positionEncodings", vec!["utf-16".into()].into())])),
+        ])),
+    ])
+}
+
+/// A file:// URI of an absolute path, reserved characters percent-encoded
+pub fn path_to_uri(
+    path: &Path
+) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+
+    // Windows paths start with their drive, C:/...
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(b as char),
+            // The drive's colon is kept, as servers write it
+            b':' if uri.len() <= "file:///C".len() => uri.push(':'),
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    uri
+}
+
+/// The path of a file:// URI, none for other schemes
+pub fn uri_to_path(
+    uri: &str
+) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    let path = String::from_utf8_lossy(&decoded).to_string();
+
+    // file:///C:/... on Windows
+    #[cfg(target_os = "windows")]
+    let path = path.strip_prefix('/').filter(|p| p.as_bytes().get(1) == Some(&b':')).map_or(path.clone(), str::to_string);
+
+    Some(PathBuf::from(path))
+}
+
+/// The UTF-16 character of a char column of `line`
+pub fn utf16_column(
+    line: &str,
+    column: usize
+) -> usize {
+    line.chars().take(column).map(char::len_utf16).sum()
+}
+
+/// The char column of a UTF-16 character of `line`, clamped to its end
+pub fn char_column(
+    line: &str,
+    character: usize
+) -> usize {
+    let mut units = 0;
+
+    for (column, c) in line.chars().enumerate() {
+        if units >= character {
+            return column;
+        }
+
+        units += c.len_utf16();
+    }
+
+    line.chars().count()
+}
+
+/// TextDocumentPositionParams of the char column and line `xy` of `text`
+pub fn text_document_position(
+    uri: &str,
+    text: &[String],
+    xy: (usize, usize)
+) -> EditorJson {
+    let character = text.get(xy.1).map_or(0, |line| utf16_column(line, xy.0));
+
+    EditorJson::object([
+        ("textDocument", EditorJson::object([("uri", uri.into())])),
+        ("position", EditorJson::object([("line", xy.1.into()), ("character", character.into())])),
+    ])
+}
+
+/// The diagnostics of a publishDiagnostics notification, and their file,
+/// columns are UTF-16 characters
+pub fn parse_published_diagnostics(
+    params: &EditorJson
+) -> Option<(PathBuf, Vec<EditorDiagnostic>)> {
+    let file = uri_to_path(params.get("uri").as_str()?)?;
+
+    let diagnostics = params.get("diagnostics").as_array().iter()
+        .map(|d| {
+            let start = d.get("range").get("start");
+
+            EditorDiagnostic {
+                file: file.clone(),
+                line: start.get("line").as_usize().unwrap_or(0),
+                column: start.get("character").as_usize().unwrap_or(0),
+                // Left to the client without one, errors are the safe guess
+                severity: match d.get("severity").as_i64() {
+                    Some(1) | None => EditorSeverity::Error,
+                    Some(2) => EditorSeverity::Warning,
+                    _ => EditorSeverity::Note,
+                },
+                message: d.get("message").as_str().and_then(|m| m.lines().next()).unwrap_or("").to_string(),
+            }
+        })
+        .collect();
+
+    Some((file, diagnostics))
+}
+
+/// The text of a hover result, markdown code fences dropped
+pub fn parse_hover(
+    result: &EditorJson
+) -> Option<String> {
+    // MarkupContent, a MarkedString, or an array of MarkedStrings
+    let marked = |c: &EditorJson| c.as_str().or_else(|| c.get("value").as_str()).map(str::to_string);
+
+    let contents = result.get("contents");
+
+    let parts: Vec<String> = match contents {
+        EditorJson::Array(items) => items.iter().filter_map(marked).collect(),
+        c => marked(c).into_iter().collect(),
+    };
+
+    let text = parts.join("\n")
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    (!text.is_empty()).then_some(text)
+}
+
+/// Locations of a definition or references result, a Location,
+/// an array of them or of LocationLinks, or null
+pub fn parse_locations(
+    result: &EditorJson
+) -> Vec<EditorLspLocation> {
+    let items: Vec<&EditorJson> = match result {
+        EditorJson::Array(items) => items.iter().collect(),
+        EditorJson::Null => Vec::new(),
+        single => vec![single],
+    };
+
+    items.into_iter()
+        .filter_map(|l| {
+            let (uri, range) = if l.get("targetUri").is_null() {
+                (l.get("uri"), l.get("range"))
+            } else {
+                (l.get("targetUri"), l.get("targetSelectionRange"))
+            };
+
+            let start = range.get("start");
+
+            Some(EditorLspLocation {
+                file: uri_to_path(uri.as_str()?)?,
+                line: start.get("line").as_usize()?,
+                character: start.get("character").as_usize()?,
+            })
+        })
+        .collect()
+}
+
+/// The words a completion result inserts, in its order without repeats,
+/// cut at the first character that can't be part of an identifier
+pub fn parse_completion(
+    result: &EditorJson
+) -> Vec<String> {
+    // A CompletionList, or just its items
+    let items = match result {
+        EditorJson::Array(items) => items.as_slice(),
+        list => list.get("items").as_array(),
+    };
+
+    let mut seen = HashSet::new();
+
+    items.iter()
+        .filter_map(|item| {
+            let text = item.get("textEdit").get("newText").as_str()
+                .or_else(|| item.get("insertText").as_str())
+                .or_else(|| item.get("label").as_str())?;
+
+            let word: String = text.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+
+            (!word.is_empty() && seen.insert(word.clone())).then_some(word)
+        })
+        .collect()
+}
+
+/// The text edits of a WorkspaceEdit by file, from its changes or document changes,
+/// creating, renaming and deleting files is left out
+pub fn parse_workspace_edit(
+    result: &EditorJson
+) -> Vec<EditorLspFileEdit> {
+    let mut files: Vec<EditorLspFileEdit> = Vec::new();
+
+    for (uri, edits) in result.get("changes").as_object() {
+        if let Some(file) = uri_to_path(uri) {
+            files.push(EditorLspFileEdit { file, version: None, edits: parse_text_edits(edits) });
+        }
+    }
+
+    for change in result.get("documentChanges").as_array() {
+        let document = change.get("textDocument");
+
+        if let Some(file) = document.get("uri").as_str().and_then(uri_to_path) {
+            files.push(EditorLspFileEdit {
+                file,
+                version: document.get("version").as_i64(),
+                edits: parse_text_edits(change.get("edits")),
+            });
+        }
+    }
+
+    files
+}
+
+fn parse_text_edits(
+    edits: &EditorJson
+) -> Vec<EditorLspTextEdit> {
+    let position = |p: &EditorJson| Some((p.get("line").as_usize()?, p.get("character").as_usize()?));
+
+    edits.as_array().iter()
+        .filter_map(|edit| {
+            let range = edit.get("range");
+
+            Some(EditorLspTextEdit {
+                start: position(range.get("start"))?,
+                end: position(range.get("end"))?,
+                text: edit.get("newText").as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Apply the edits of one file to its text, last first so the positions
+/// of the ones before stay valid, edits at the same place keep their order
+pub fn apply_text_edits(
+    text: &mut Vec<String>,
+    edits: &[EditorLspTextEdit]
+) {
+    let mut ordered: Vec<(usize, &EditorLspTextEdit)> = edits.iter().enumerate().collect();
+    ordered.sort_by(|(i, a), (j, b)| b.start.cmp(&a.start).then(j.cmp(i)));
+
+    for (_, edit) in ordered {
+        if text.is_empty() {
+            text.push(String::new());
+        }
+
+        let last = text.len() - 1;
+        let (start_line, end_line) = (edit.start.0.min(last), edit.end.0.min(last));
+
+        // Past the end of the text means its end
+        let byte_at = |line: usize, (l, character): (usize, usize)| -> usize {
+            let line_text = &text[line];
+
+            if l > last {
+                return line_text.len();
+            }
+
+            line_text.char_indices().nth(char_column(line_text, character)).map_or(line_text.len(), |(b, _)| b)
+        };
+
+        let from = byte_at(start_line, edit.start);
+        let to = byte_at(end_line, edit.end).max(if start_line == end_line { from } else { 0 });
+
+        let joined = format!("{}{}{}", &text[start_line][..from], edit.text, &text[end_line][to..]);
+        let lines: Vec<String> = joined.split('\n').map(|l| l.trim_end_matches('\r').to_string()).collect();
+
+        text.splice(start_line..=end_line.max(start_line), lines);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::io::Cursor;
+    use std::time::{Duration, Instant};
+
+    use crate::console::editor_test_dir::EditorTestDir;
+
+    /// A stand-in server, answering initialize and the methods of `cases`,
+    /// shell case arms with `$id` of the request and `send` to answer.
+    /// Every message it reads is logged to `log`, one per line
+    fn stand_in(
+        name: &str,
+        cases: &str
+    ) -> (EditorLspClient, EditorTestDir) {
+        let dir = EditorTestDir::new(&format!("lsp-{}", name));
+
+        let script = format!(r#"
+send() {{ printf 'Content-Length: %s\r\n\r\n%s' "${{#1}}" "$1"; }}
+while :; do
+    length=
+    while IFS= read -r header; do
+        header=$(printf '%s' "$header" | tr -d '\r')
+        [ -z "$header" ] && break
+        case $header in Content-Length:*) length=${{header#Content-Length: }} ;; esac
+    done
+    [ -z "$length" ] && exit 0
+    body=$(dd bs=1 count="$length" 2>/dev/null)
+    printf '%s\n' "$body" >> log
+    id=$(printf '%s' "$body" | sed -n 's/^{{"jsonrpc":"2.0","id":\([0-9]*\),.*/\1/p')
+    method=$(printf '%s' "$body" | sed -n 's/.*"method":"\([^"]*\)".*/\1/p')
+    case $method in
+        initialize) send '{{"jsonrpc":"2.0","id":'"$id"',"result":{{"capabilities":{{}}}}}}' ;;
+        exit) exit 0 ;;
+        {}
+    esac
+done
+"#, cases);
+
+        fs::write(dir.join("server.sh"), script).unwrap();
+
+        let client = EditorLspClient::spawn("sh", &["server.sh"], &dir).unwrap();
+        (client, dir)
+    }
+
+    /// Poll until `count` messages arrived, or give up after a while
+    fn receive(
+        client: &mut EditorLspClient,
+        count: usize
+    ) -> Vec<EditorLspMessage> {
+        let started = Instant::now();
+        let mut messages = Vec::new();
+
+        while messages.len() < count && started.elapsed() < Duration::from_secs(10) {
+            messages.extend(client.poll());
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        messages
+    }
+
+    /// The methods, or ids of responses, the stand-in read so far
+    fn logged(
+        dir: &Path
+    ) -> Vec<EditorJson> {
+        fs::read_to_string(dir.join("log")).unwrap_or_default()
+            .lines()
+            .map(|line| EditorJson::parse(line).unwrap())
+            .collect()
+    }
+
+    fn result_of(
+        message: &EditorLspMessage
+    ) -> &EditorJson {
+        match message {
+            EditorLspMessage::Response { result: Ok(result), .. } => result,
+            other => panic!("not a successful response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_framed_messages() {
+        let stream = "Content-Length: 8\r\n\r\n{\"a\":1}\nContent-Type: x\r\ncontent-length: 2\r\n\r\n[]";
+        let mut reader = Cursor::new(stream.as_bytes());
+
+        assert_eq!(read_message(&mut reader).unwrap().get("a").as_i64(), Some(1));
+        assert_eq!(read_message(&mut reader), Some(EditorJson::Array(vec![])));
+        assert_eq!(read_message(&mut reader), None);
+    }
+
+    #[test]
+    fn converts_uris_and_columns() {
+        let path = Path::new("/tmp/a dir/naïve.rs");
+        let uri = path_to_uri(path);
+
+        assert_eq!(uri, "file:///tmp/a%20dir/na%C3%AFve.rs");
+        assert_eq!(uri_to_path(&uri).as_deref(), Some(path));
+        assert_eq!(uri_to_path("untitled:1"), None);
+
+        // é is one unit, 😀 two
+        let line = "é😀x";
+        assert_eq!(utf16_column(line, 2), 3);
+        assert_eq!(char_column(line, 3), 2);
+        assert_eq!(char_column(line, 99), 3);
+    }
+
+    #[test]
+    fn initializes_before_sending_the_queue() {
+        let (mut client, dir) = stand_in("queue", r#"
+        textDocument/hover) send '{"jsonrpc":"2.0","id":'"$id"',"result":{"contents":{"kind":"markdown","value":"```rust\nfn main()\n```\n---\nThe entry"}}}' ;;
+        "#);
+
+        client.notify("textDocument/didOpen", EditorJson::object([("textDocument", EditorJson::object([("uri", "file:///x.rs".into())]))]));
+        let id = client.request("textDocument/hover", text_document_position("file:///x.rs", &["fn main() {}".to_string()], (3, 0)));
+
+        assert!(!client.initialized);
+
+        let messages = receive(&mut client, 1);
+        assert!(client.initialized);
+        assert!(matches!(messages[0], EditorLspMessage::Response { id: i, .. } if i == id));
+        assert_eq!(parse_hover(result_of(&messages[0])).as_deref(), Some("fn main()\n---\nThe entry"));
+
+        let methods: Vec<String> = logged(&dir).iter().map(|m| m.get("method").as_str().unwrap_or("").to_string()).collect();
+        assert_eq!(methods, ["initialize", "initialized", "textDocument/didOpen", "textDocument/hover"]);
+
+        let hover = &logged(&dir)[3];
+        assert_eq!(hover.get("params").get("position").get("character").as_i64(), Some(3));
+
+        client.stop();
+    }
+
+    #[test]
+    fn takes_in_published_diagnostics() {
+        let (mut client, _dir) = stand_in("diagnostics", r#"
+        textDocument/didOpen) send '{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///p/main.rs","diagnostics":[{"range":{"start":{"line":2,"character":4},"end":{"line":2,"character":5}},"severity":1,"message":"cannot find value\nhelp: a local"},{"range":{"start":{"line":7,"character":0},"end":{"line":7,"character":1}},"severity":2,"message":"unused"},{"range":{"start":{"line":9,"character":0},"end":{"line":9,"character":1}},"severity":4,"message":"hint"}]}}' ;;
+        "#);
+
+        client.notify("textDocument/didOpen", EditorJson::object([]));
+
+        let messages = receive(&mut client, 1);
+
+        let EditorLspMessage::Notification { method, params } = &messages[0] else {
+            panic!("not a notification: {:?}", messages[0]);
+        };
+
+        assert_eq!(method, "textDocument/publishDiagnostics");
+
+        let (file, diagnostics) = parse_published_diagnostics(params).unwrap();
+        assert_eq!(file, PathBuf::from("/p/main.rs"));
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column, diagnostics[0].severity), (2, 4, EditorSeverity::Error));
+        assert_eq!(diagnostics[0].message, "cannot find value");
+        assert_eq!(diagnostics[1].severity, EditorSeverity::Warning);
+        assert_eq!(diagnostics[2].severity, EditorSeverity::Note);
+
+        client.stop();
+    }
+
+    #[test]
+    fn finds_definitions_and_references() {
+        let (mut client, _dir) = stand_in("locations", r#"
+        textDocument/definition) send '{"jsonrpc":"2.0","id":'"$id"',"result":[{"originSelectionRange":{"start":{"line":0,"character":0},"end":{"line":0,"character":1}},"targetUri":"file:///p/lib.rs","targetRange":{"start":{"line":10,"character":0},"end":{"line":12,"character":1}},"targetSelectionRange":{"start":{"line":10,"character":7},"end":{"line":10,"character":10}}}]}' ;;
+        textDocument/references) send '{"jsonrpc":"2.0","id":'"$id"',"result":[{"uri":"file:///p/a.rs","range":{"start":{"line":1,"character":2},"end":{"line":1,"character":5}}},{"uri":"file:///p/b.rs","range":{"start":{"line":3,"character":0},"end":{"line":3,"character":3}}}]}' ;;
+        textDocument/implementation) send '{"jsonrpc":"2.0","id":'"$id"',"error":{"code":-32601,"message":"MethodNotFound"}}' ;;
+        "#);
+
+        let definition = client.request("textDocument/definition", EditorJson::object([]));
+        let references = client.request("textDocument/references", EditorJson::object([]));
+        let unknown = client.request("textDocument/implementation", EditorJson::object([]));
+
+        let messages = receive(&mut client, 3);
+        let response = |id: i64| messages.iter().find(|m| matches!(m, EditorLspMessage::Response { id: i, .. } if *i == id)).unwrap();
+
+        assert_eq!(parse_locations(result_of(response(definition))), [EditorLspLocation { file: PathBuf::from("/p/lib.rs"), line: 10, character: 7 }]);
+
+        let found = parse_locations(result_of(response(references)));
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[1].file.as_path(), found[1].line), (Path::new("/p/b.rs"), 3));
+
+        assert_eq!(response(unknown), &EditorLspMessage::Response { id: unknown, result: Err("MethodNotFound".to_string()) });
+        assert!(parse_locations(&EditorJson::Null).is_empty());
+
+        client.stop();
+    }
+
+    #[test]
+    fn completes_words() {
+        let (mut client, _dir) = stand_in("completion", r#"
+        textDocument/completion) send '{"jsonrpc":"2.0","id":'"$id"',"result":{"isIncomplete":false,"items":[{"label":"push(...)","insertText":"push"},{"label":"push_str","textEdit":{"range":{"start":{"line":0,"character":2},"end":{"line":0,"character":4}},"newText":"push_str"}},{"label":"pop()"},{"label":"push"}]}}' ;;
+        "#);
+
+        let id = client.request("textDocument/completion", EditorJson::object([]));
+        let messages = receive(&mut client, 1);
+
+        assert!(matches!(messages[0], EditorLspMessage::Response { id: i, .. } if i == id));
+        assert_eq!(parse_completion(result_of(&messages[0])), ["push", "push_str", "pop"]);
+
+        client.stop();
+    }
+
+    #[test]
+    fn renames_across_files() {
+        let (mut client, _dir) = stand_in("rename", r#"
+        textDocument/rename) send '{"jsonrpc":"2.0","id":'"$id"',"result":{"documentChanges":[{"textDocument":{"uri":"file:///p/main.rs","version":3},"edits":[{"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":7}},"newText":"total"},{"range":{"start":{"line":1,"character":15},"end":{"line":1,"character":18}},"newText":"total"}]},{"kind":"rename","oldUri":"file:///p/a.rs","newUri":"file:///p/b.rs"}]}}' ;;
+        "#);
+
+        let id = client.request("textDocument/rename", EditorJson::object([("newName", "total".into())]));
+        let messages = receive(&mut client, 1);
+
+        assert!(matches!(messages[0], EditorLspMessage::Response { id: i, .. } if i == id));
+
+        let files = parse_workspace_edit(result_of(&messages[0]));
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file, PathBuf::from("/p/main.rs"));
+        assert_eq!(files[0].version, Some(3));
+
+        let mut text = vec!["let sum = 1;".to_string(), "println!(\"{}\", sum);".to_string()];
+        apply_text_edits(&mut text, &files[0].edits);
+        assert_eq!(text, ["let total = 1;", "println!(\"{}\", total);"]);
+
+        client.stop();
+    }
+
+    #[test]
+    fn applies_edits_spanning_lines() {
+        let edit = |start, end, text: &str| EditorLspTextEdit { start, end, text: text.to_string() };
+        let mut text = vec!["a😀b".to_string(), "cd".to_string(), "ef".to_string()];
+
+        // Joins the first two lines after the emoji, and splits the last one
+        apply_text_edits(&mut text, &[edit((0, 3), (1, 1), "X"), edit((2, 1), (2, 1), "\n")]);
+        assert_eq!(text, ["a😀Xd", "e", "f"]);
+
+        // Inserts at the same place keep their order
+        apply_text_edits(&mut text, &[edit((1, 0), (1, 0), "1"), edit((1, 0), (1, 0), "2")]);
+        assert_eq!(text[1], "12e");
+
+        // Past the end appends
+        apply_text_edits(&mut text, &[edit((9, 0), (9, 0), "\nlast")]);
+        assert_eq!(text.last().map(String::as_str), Some("last"));
+    }
+
+    #[test]
+    fn answers_requests_of_the_server() {
+        let (mut client, dir) = stand_in("server-requests", r#"
+        initialized) send '{"jsonrpc":"2.0","id":70,"method":"workspace/configuration","params":{"items":[{"section":"a"},{"section":"b"}]}}'
+                     send '{"jsonrpc":"2.0","id":"token","method":"window/workDoneProgress/create","params":{"token":"t"}}' ;;
+        "#);
+
+        let started = Instant::now();
+
+        while logged(&dir).len() < 4 && started.elapsed() < Duration::from_secs(10) {
+            assert!(client.poll().is_empty());
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let log = logged(&dir);
+        assert_eq!(log[2].get("id").as_i64(), Some(70));
+        assert_eq!(log[2].get("result"), &EditorJson::Array(vec![EditorJson::Null, EditorJson::Null]));
+        assert_eq!(log[3].get("id").as_str(), Some("token"));
+        assert!(log[3].get("result").is_null());
+
+        client.stop();
+    }
+
+    #[test]
+    fn notices_the_server_exiting() {
+        let (mut client, _dir) = stand_in("exit", r#"
+        crash) exit 3 ;;
+        "#);
+
+        client.notify("crash", EditorJson::Null);
+
+        let started = Instant::now();
+
+        while !client.has_exited() && started.elapsed() < Duration::from_secs(10) {
+            client.poll();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(client.has_exited());
+    }
+}
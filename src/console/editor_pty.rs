@@ -0,0 +1,601 @@
+// PTY panel module
+//
+// A persistent interactive shell, the one <:t> runs commands with,
+// see editor_terminal.rs, attached to a pseudo-terminal so programs
+// like htop, less or a REPL see a real terminal. Its output is parsed
+// into a VT100 screen, see editor_vt.rs, drawn in a panel docked at
+// the bottom or the right of the editor:
+//      <:pty>  : Show the panel and focus it, starting the shell, or hide it
+//      <:ptk>  : Kill the shell
+//      <:ptd>  : Dock the panel at the bottom/right
+// F12 moves the keyboard between the editor and the panel, while the
+// panel is focused every key goes to the shell, except LShift +
+// PageUp/PageDown that scroll the scrollback. The grid follows the
+// panel's size, the shell is told through the pseudo-terminal.
+//
+// Pseudo-terminals are a unix feature, elsewhere <:pty> only reports
+// PtyUnsupported.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::Child;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use macroquad::prelude::*;
+
+use crate::console::editor_terminal::terminal_shell;
+use crate::console::editor_vt::*;
+use crate::options::editor_pallete::*;
+use crate::text::editor_cursor::EditorCursor;
+use crate::text::editor_text_stylizer::EditorGeneralTextStylizer;
+
+pub const PTY_PANEL_BOTTOM_RATIO: f32 = 0.4;
+pub const PTY_PANEL_RIGHT_RATIO: f32 = 0.45;
+pub const PTY_FONT_SIZE: u16 = 18;
+pub const PTY_ROW_HEIGHT: f32 = 20.0;
+pub const PTY_MARGINS: f32 = 10.0;
+pub const PTY_TITLE_HEIGHT: f32 = 30.0;
+pub const PTY_SCROLL_STEP: usize = 3;
+pub const PTY_READ_BUFFER: usize = 4096;
+pub const PTY_TERM: &str = "xterm-256color";
+
+/// Keys sent as escape sequences, with repeat
+const PTY_KEYS: [(KeyCode, &str); 21] = [
+    (KeyCode::Enter, "\r"),
+    (KeyCode::KpEnter, "\r"),
+    (KeyCode::Backspace, "\x7f"),
+    (KeyCode::Tab, "\t"),
+    (KeyCode::Escape, "\x1b"),
+    (KeyCode::Home, "\x1b[H"),
+    (KeyCode::End, "\x1b[F"),
+    (KeyCode::Insert, "\x1b[2~"),
+    (KeyCode::Delete, "\x1b[3~"),
+    (KeyCode::PageUp, "\x1b[5~"),
+    (KeyCode::PageDown, "\x1b[6~"),
+    (KeyCode::F1, "\x1bOP"),
+    (KeyCode::F2, "\x1bOQ"),
+    (KeyCode::F3, "\x1bOR"),
+    (KeyCode::F4, "\x1bOS"),
+    (KeyCode::F5, "\x1b[15~"),
+    (KeyCode::F6, "\x1b[17~"),
+    (KeyCode::F7, "\x1b[18~"),
+    (KeyCode::F8, "\x1b[19~"),
+    (KeyCode::F9, "\x1b[20~"),
+    (KeyCode::F10, "\x1b[21~"),
+];
+
+/// Arrow keys and their final byte, after ESC [ or ESC O
+const PTY_ARROWS: [(KeyCode, char); 4] = [
+    (KeyCode::Up, 'A'),
+    (KeyCode::Down, 'B'),
+    (KeyCode::Right, 'C'),
+    (KeyCode::Left, 'D'),
+];
+
+/// Letters sent as control characters with LCtrl, e.g. Ctrl+C as 0x03
+const PTY_LETTERS: [KeyCode; 26] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorPtyDock {
+    Bottom,
+    Right,
+}
+
+/// A shell running in a pseudo-terminal
+pub struct EditorPty {
+    master: File,
+    child: Child,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl EditorPty {
+    /// Start the shell in `dir` on a `cols` x `rows` terminal
+    #[cfg(unix)]
+    pub fn spawn(
+        dir: &Path,
+        cols: usize,
+        rows: usize
+    ) -> Result<EditorPty, String> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+        use std::os::unix::process::CommandExt;
+        use std::process::{Command, Stdio};
+
+        let mut master = 0;
+        let mut slave = 0;
+        let size = window_size(cols, rows);
+
+        // SAFETY: openpty only writes the two descriptors it opens
+        let opened = unsafe {
+            libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size)
+        };
+
+        if opened != 0 {
+            return Err("PtyNotOpened".to_string());
+        }
+
+        // SAFETY: both descriptors were just opened and are owned here only
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+        let stdio = || slave.try_clone().map(Stdio::from).map_err(|e| e.to_string());
+        let (shell, _) = terminal_shell();
+
+        let mut command = Command::new(shell);
+        command.current_dir(dir)
+            .env("TERM", PTY_TERM)
+            .stdin(stdio()?)
+            .stdout(stdio()?)
+            .stderr(stdio()?);
+
+        // SAFETY: only async-signal-safe calls, between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                // A session of its own, with the pseudo-terminal as its controlling terminal
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+
+                Ok(())
+            });
+        }
+
+        let child = command.spawn().map_err(|e| e.to_string())?;
+
+        // The shell holds the slave side now, reads of the master end when it exits
+        drop(command);
+        drop(slave);
+
+        let mut reader = master.try_clone().map_err(|e| e.to_string())?;
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; PTY_READ_BUFFER];
+
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => {
+                        if sender.send(buffer[..n].to_vec()).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(EditorPty { master, child, receiver })
+    }
+
+    #[cfg(not(unix))]
+    pub fn spawn(
+        _dir: &Path,
+        _cols: usize,
+        _rows: usize
+    ) -> Result<EditorPty, String> {
+        Err("PtyUnsupported".to_string())
+    }
+
+    /// Tell the shell the terminal's new size, it gets a SIGWINCH
+    pub fn resize(
+        &self,
+        cols: usize,
+        rows: usize
+    ) {
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+
+            let size = window_size(cols, rows);
+
+            // SAFETY: TIOCSWINSZ only reads the winsize given
+            unsafe {
+                libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+            }
+        }
+
+        #[cfg(not(unix))]
+        let _ = (cols, rows);
+    }
+
+    /// Send keys, or replies, to the shell
+    pub fn write(
+        &mut self,
+        bytes: &[u8]
+    ) {
+        let _ = self.master.write_all(bytes);
+    }
+
+    /// Kill the shell's session, every program started in it
+    pub fn kill(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(self.child.id() as libc::pid_t), libc::SIGKILL);
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    /// True once the shell exited
+    pub fn has_exited(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+#[cfg(unix)]
+fn window_size(
+    cols: usize,
+    rows: usize
+) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows.min(u16::MAX as usize) as u16,
+        ws_col: cols.min(u16::MAX as usize) as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+pub struct EditorPtyPanel {
+    pub visible: bool,
+    /// Keys go to the shell rather than the editor
+    pub focused: bool,
+    pub dock: EditorPtyDock,
+    pub screen: EditorVtScreen,
+    /// Lines scrolled back from the screen, 0 shows the screen
+    pub scroll: usize,
+    pty: Option<EditorPty>,
+}
+
+impl EditorPtyPanel {
+    pub fn new() -> EditorPtyPanel {
+        EditorPtyPanel {
+            visible: false,
+            focused: false,
+            dock: EditorPtyDock::Bottom,
+            screen: EditorVtScreen::new(80, 24),
+            scroll: 0,
+            pty: None,
+        }
+    }
+
+    /// Show and focus the panel, starting the shell in `dir` if it isn't running
+    pub fn open(
+        &mut self,
+        dir: &Path
+    ) -> Result<(), String> {
+        if self.pty.is_none() {
+            let pty = EditorPty::spawn(dir, self.screen.cols, self.screen.rows)?;
+
+            self.screen = EditorVtScreen::new(self.screen.cols, self.screen.rows);
+            self.scroll = 0;
+            self.pty = Some(pty);
+        }
+
+        self.visible = true;
+        self.focused = true;
+
+        Ok(())
+    }
+
+    /// Hide the panel, the shell keeps running
+    pub fn hide(&mut self) {
+        self.visible = false;
+        self.focused = false;
+    }
+
+    /// Kill the shell, false if none runs
+    pub fn kill(&mut self) -> bool {
+        let Some(mut pty) = self.pty.take() else {
+            return false;
+        };
+
+        pty.kill();
+        self.focused = false;
+
+        true
+    }
+
+    /// F12, move the keyboard between the editor and the panel
+    pub fn toggle_focus(
+        &mut self,
+        dir: &Path
+    ) -> Result<(), String> {
+        if self.focused {
+            self.focused = false;
+            return Ok(());
+        }
+
+        self.open(dir)
+    }
+
+    /// Parse what the shell wrote, answer its queries and notice it exiting
+    pub fn update(&mut self) {
+        let Some(pty) = &mut self.pty else {
+            return;
+        };
+
+        let mut disconnected = false;
+
+        loop {
+            match pty.receiver.try_recv() {
+                Ok(bytes) => {
+                    self.screen.feed(&bytes);
+
+                    // New output brings the view back to the screen
+                    if !self.focused || self.screen.is_alternate() {
+                        self.scroll = 0;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        if !self.screen.responses.is_empty() {
+            let responses = std::mem::take(&mut self.screen.responses);
+            pty.write(&responses);
+        }
+
+        if disconnected || pty.has_exited() {
+            pty.kill();
+            self.pty = None;
+            self.focused = false;
+        }
+    }
+
+    /// The panel's area on screen
+    fn rect(&self) -> Rect {
+        match self.dock {
+            EditorPtyDock::Bottom => {
+                let height = screen_height() * PTY_PANEL_BOTTOM_RATIO;
+                Rect::new(0.0, screen_height() - height, screen_width(), height)
+            }
+            EditorPtyDock::Right => {
+                let width = screen_width() * PTY_PANEL_RIGHT_RATIO;
+                Rect::new(screen_width() - width, 0.0, width, screen_height())
+            }
+        }
+    }
+
+    /// Width of the screen's right and height of its bottom the panel covers,
+    /// none while hidden
+    pub fn occupied(&self) -> (f32, f32) {
+        if !self.visible {
+            return (0.0, 0.0);
+        }
+
+        let rect = self.rect();
+
+        match self.dock {
+            EditorPtyDock::Bottom => (0.0, rect.h),
+            EditorPtyDock::Right => (rect.w, 0.0),
+        }
+    }
+
+    /// Send the pressed keys to the shell
+    pub fn record_keyboard(
+        &mut self,
+        cursor: &mut EditorCursor
+    ) {
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        let alt = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+
+        // Scrollback, never sent
+        if is_key_down(KeyCode::LeftShift) && (is_key_down(KeyCode::PageUp) || is_key_down(KeyCode::PageDown)) {
+            let page = (self.screen.rows / 2) as isize;
+
+            if cursor.is_combo_active(KeyCode::PageUp, Some(KeyCode::LeftShift)) {
+                self.scroll_by(page);
+            } else if cursor.is_combo_active(KeyCode::PageDown, Some(KeyCode::LeftShift)) {
+                self.scroll_by(-page);
+            }
+
+            return;
+        }
+
+        let mut input: Vec<u8> = Vec::new();
+
+        for (key, sequence) in PTY_KEYS {
+            if cursor.is_combo_active(key, None) {
+                input.extend_from_slice(sequence.as_bytes());
+            }
+        }
+
+        for (key, last) in PTY_ARROWS {
+            if cursor.is_combo_active(key, None) {
+                let prefix = if self.screen.application_cursor { "\x1bO" } else { "\x1b[" };
+                input.extend(format!("{}{}", prefix, last).bytes());
+            }
+        }
+
+        if ctrl {
+            for (i, key) in PTY_LETTERS.iter().enumerate() {
+                if is_key_pressed(*key) {
+                    input.push(i as u8 + 1);
+                }
+            }
+        }
+
+        // Typed text, control combinations were sent as their letters above
+        while let Some(c) = get_char_pressed() {
+            if ctrl || c.is_control() {
+                continue;
+            }
+
+            if alt {
+                input.push(0x1B);
+            }
+
+            let mut bytes = [0u8; 4];
+            input.extend_from_slice(c.encode_utf8(&mut bytes).as_bytes());
+        }
+
+        if input.is_empty() {
+            return;
+        }
+
+        self.scroll = 0;
+
+        if let Some(pty) = &mut self.pty {
+            pty.write(&input);
+        }
+    }
+
+    /// Scroll the view by `delta` lines, positive goes back into the scrollback
+    pub fn scroll_by(
+        &mut self,
+        delta: isize
+    ) {
+        self.scroll = self.scroll
+            .saturating_add_signed(delta)
+            .min(self.screen.scrollback.len());
+    }
+
+    /// Mouse wheel scrolling over the panel
+    pub fn record_mouse(&mut self) {
+        if !self.visible {
+            return;
+        }
+
+        let (_, wheel) = mouse_wheel();
+
+        if wheel != 0.0 && self.rect().contains(mouse_position().into()) {
+            let step = PTY_SCROLL_STEP as isize;
+            self.scroll_by(if wheel > 0.0 { step } else { -step });
+        }
+    }
+
+    /// Draw the panel, resizing the terminal to the cells that fit in it
+    pub fn draw(
+        &mut self,
+        gts: &mut EditorGeneralTextStylizer
+    ) {
+        if !self.visible {
+            return;
+        }
+
+        let pal = pallete();
+        let rect = self.rect();
+
+        let previous_size = gts.font_size;
+        let previous_color = gts.color;
+        gts.font_size = PTY_FONT_SIZE;
+
+        let cell_w = gts.measure("M").max(1.0);
+        let cols = ((rect.w - PTY_MARGINS * 2.0) / cell_w) as usize;
+        let rows = ((rect.h - PTY_TITLE_HEIGHT - PTY_MARGINS * 2.0) / PTY_ROW_HEIGHT) as usize;
+
+        if cols.max(1) != self.screen.cols || rows.max(1) != self.screen.rows {
+            self.screen.resize(cols, rows);
+            self.scroll = self.scroll.min(self.screen.scrollback.len());
+
+            if let Some(pty) = &self.pty {
+                pty.resize(self.screen.cols, self.screen.rows);
+            }
+        }
+
+        let frame = if self.focused { pal.composite_type } else { pal.console_frame };
+        draw_rectangle(rect.x - 1.0, rect.y - 1.0, rect.w + 1.0, rect.h + 1.0, frame);
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, pal.console_container);
+
+        // Title, the shell's title or how to focus it
+        let state = match (self.pty.is_some(), self.focused) {
+            (false, _) => "exited, :pty restarts".to_string(),
+            (true, true) => "focused, F12 returns".to_string(),
+            (true, false) => "F12 focuses".to_string(),
+        };
+
+        let mut title = if self.screen.title.is_empty() {
+            format!("shell  {}", state)
+        } else {
+            format!("{}  {}", self.screen.title, state)
+        };
+
+        if self.scroll > 0 {
+            title = format!("{}  [-{}]", title, self.scroll);
+        }
+
+        gts.color = if self.focused { pal.composite_type } else { pal.console_text };
+        gts.draw(&title, rect.x + PTY_MARGINS, rect.y + PTY_TITLE_HEIGHT - 8.0);
+
+        let left = rect.x + PTY_MARGINS;
+        let top = rect.y + PTY_TITLE_HEIGHT + PTY_MARGINS;
+
+        for row in 0..self.screen.rows {
+            let Some(line) = self.screen.view_line(row, self.scroll) else {
+                continue;
+            };
+
+            let y = top + row as f32 * PTY_ROW_HEIGHT;
+            let mut col = 0;
+
+            // Runs of cells drawn alike
+            while col < line.len() {
+                let cell = line[col];
+                let end = line[col..].iter()
+                    .position(|other| !other.same_style(&cell))
+                    .map_or(line.len(), |n| col + n);
+
+                let mut fg = vt_color(cell.fg, cell.bold, pal.console_text);
+                let mut bg = vt_color(cell.bg, false, pal.console_container);
+
+                if cell.reverse {
+                    (fg, bg) = (bg, fg);
+                }
+
+                let x = left + col as f32 * cell_w;
+                let width = (end - col) as f32 * cell_w;
+
+                if cell.bg != EditorVtColor::Default || cell.reverse {
+                    draw_rectangle(x, y, width, PTY_ROW_HEIGHT, bg);
+                }
+
+                let run: String = line[col..end].iter().map(|c| c.c).collect();
+
+                if !run.trim().is_empty() {
+                    gts.color = fg;
+
+                    // One cell each, whatever the font's widths are
+                    for (i, c) in run.chars().enumerate() {
+                        if c != ' ' {
+                            gts.draw(c.encode_utf8(&mut [0u8; 4]), x + i as f32 * cell_w, y + PTY_ROW_HEIGHT - 5.0);
+                        }
+                    }
+                }
+
+                if cell.underline {
+                    draw_rectangle(x, y + PTY_ROW_HEIGHT - 2.0, width, 1.0, fg);
+                }
+
+                col = end;
+            }
+        }
+
+        // Cursor, a block while focused and an outline otherwise
+        if self.pty.is_some() && self.screen.cursor_visible && self.scroll == 0 {
+            let (x, y) = self.screen.cursor;
+            let cx = left + x as f32 * cell_w;
+            let cy = top + y as f32 * PTY_ROW_HEIGHT;
+
+            if self.focused {
+                let mut color = pal.cursor;
+                color.a = 0.6;
+                draw_rectangle(cx, cy, cell_w, PTY_ROW_HEIGHT, color);
+            } else {
+                draw_rectangle_lines(cx, cy, cell_w, PTY_ROW_HEIGHT, 1.0, pal.cursor);
+            }
+        }
+
+        gts.font_size = previous_size;
+        gts.color = previous_color;
+    }
+}
@@ -0,0 +1,247 @@
+// Project symbol index module, used by the <:def> and <:back> directives.
+//
+// A background thread walks the current directory, honouring .gitignore,
+// and outlines every file whose language defines symbols, see
+// editor_outline.rs. The editor picks its results up every frame, and
+// files saved through write_current_file are outlined again right away.
+// Changing directory starts a new index.
+//
+// <:def> jumps to the definition of the identifier under the cursor,
+// several candidates are listed in the console instead. Every jump
+// leaves the position it started from on a stack <:back> returns to.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use ignore::WalkBuilder;
+
+use crate::console::editor_console_list::EditorConsoleListItem;
+use crate::text::editor_language_manager::*;
+use crate::text::editor_outline::{EditorSymbol, outline_symbols};
+
+pub const SYMBOL_INDEX_MAX_FILES: usize = 20000;
+pub const SYMBOL_INDEX_MAX_FILE_SIZE: u64 = 1024 * 1024;
+pub const SYMBOL_JUMPS_LIMIT: usize = 100;
+
+/// A position jumped away from, line and column are 0-based
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorJump {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub struct EditorSymbolIndex {
+    root: Option<PathBuf>,
+    files: HashMap<PathBuf, Vec<EditorSymbol>>,
+    receiver: Option<Receiver<(PathBuf, Vec<EditorSymbol>)>>,
+    /// Positions <:back> returns to, most recent last
+    pub jumps: Vec<EditorJump>,
+}
+
+impl EditorSymbolIndex {
+    pub fn new() -> EditorSymbolIndex {
+        EditorSymbolIndex {
+            root: None,
+            files: HashMap::new(),
+            receiver: None,
+            jumps: Vec::new(),
+        }
+    }
+
+    /// True while the background thread is still walking the directory
+    pub fn is_indexing(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /// Start over if the directory changed, then take in what the thread found
+    pub fn update(
+        &mut self,
+        dir: &Option<PathBuf>
+    ) {
+        let Some(dir) = dir else {
+            return;
+        };
+
+        if self.root.as_ref() != Some(dir) {
+            self.files.clear();
+            self.receiver = Some(spawn_indexer(dir.clone()));
+            self.root = Some(dir.clone());
+        }
+
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+
+        loop {
+            match receiver.try_recv() {
+                Ok((path, symbols)) => {
+                    self.files.insert(path, symbols);
+                }
+
+                Err(TryRecvError::Empty) => break,
+
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Walk the directory again on the next update, e.g. after a branch switch
+    pub fn restart(
+        &mut self
+    ) {
+        self.root = None;
+    }
+
+    /// Outline a saved file again
+    pub fn update_file(
+        &mut self,
+        path: &Path,
+        text: &[String]
+    ) {
+        if !self.root.as_ref().is_some_and(|root| path.starts_with(root)) {
+            return;
+        }
+
+        let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string());
+        let elk = load_keywords_for_file_name(&name, text.first().map(|l| l.as_str()));
+
+        self.files.insert(path.to_path_buf(), definitions(text, &elk));
+    }
+
+    /// Definitions named `name`, Foo::bar also counts as bar, in path order
+    pub fn find(
+        &self,
+        name: &str
+    ) -> Vec<EditorConsoleListItem> {
+        let qualified = format!("::{}", name);
+        let mut paths: Vec<&PathBuf> = self.files.keys().collect();
+        paths.sort();
+
+        let mut items = Vec::new();
+
+        for path in paths {
+            let shown = self.root.as_ref()
+                .and_then(|root| path.strip_prefix(root).ok())
+                .unwrap_or(path)
+                .display()
+                .to_string();
+
+            for symbol in &self.files[path] {
+                if symbol.name != name && !symbol.name.ends_with(&qualified) {
+                    continue;
+                }
+
+                items.push(EditorConsoleListItem {
+                    label: format!("{}:{}: {} {}", shown, symbol.line, symbol.kind, symbol.name),
+                    file: Some(path.clone()),
+                    line: symbol.line,
+                    column: symbol.column,
+                });
+            }
+        }
+
+        items
+    }
+
+    /// Remember a position before jumping away from it
+    pub fn push_jump(
+        &mut self,
+        jump: EditorJump
+    ) {
+        if self.jumps.last() == Some(&jump) {
+            return;
+        }
+
+        self.jumps.push(jump);
+
+        if self.jumps.len() > SYMBOL_JUMPS_LIMIT {
+            self.jumps.remove(0);
+        }
+    }
+}
+
+/// Walk `root` on another thread, sending each outlined file
+fn spawn_indexer(
+    root: PathBuf
+) -> Receiver<(PathBuf, Vec<EditorSymbol>)> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let languages = list_languages();
+
+        let paths = WalkBuilder::new(&root)
+            .require_git(false)
+            .build()
+            .flatten()
+            .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+            .filter(|entry| entry.metadata().is_ok_and(|m| m.len() <= SYMBOL_INDEX_MAX_FILE_SIZE))
+            .take(SYMBOL_INDEX_MAX_FILES)
+            .map(|entry| entry.into_path());
+
+        for path in paths {
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+
+            let text: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+            let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string());
+            let elk = load_keywords_for_file_name_in(&name, text.first().map(|l| l.as_str()), &languages);
+
+            if elk.symbols.is_empty() && !elk.function_declarations {
+                continue;
+            }
+
+            // The editor moved on to another directory or started over
+            if sender.send((path, definitions(&text, &elk))).is_err() {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Outline symbols that name a definition, impl headers don't
+fn definitions(
+    text: &[String],
+    elk: &EditorLanguageKeywords
+) -> Vec<EditorSymbol> {
+    outline_symbols(text, elk)
+        .into_iter()
+        .filter(|symbol| !elk.symbol_headers.contains(&symbol.kind))
+        .collect()
+}
+
+/// The identifier at or right before `column`, e.g. the call in "foo(bar)"
+pub fn identifier_at(
+    line: &str,
+    column: usize
+) -> Option<String> {
+    let chars: Vec<char> = line.chars().collect();
+    let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+
+    let mut start = column.min(chars.len());
+
+    if !chars.get(start).is_some_and(is_word) && start > 0 && is_word(&chars[start - 1]) {
+        start -= 1;
+    }
+
+    if !chars.get(start).is_some_and(is_word) {
+        return None;
+    }
+
+    while start > 0 && is_word(&chars[start - 1]) {
+        start -= 1;
+    }
+
+    let word: String = chars[start..].iter().take_while(|c| is_word(c)).collect();
+
+    word.starts_with(|c: char| !c.is_ascii_digit()).then_some(word)
+}
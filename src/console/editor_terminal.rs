@@ -1,53 +1,398 @@
 // Editor's 'virtual' terminal/shell bridge
+//
+// <:t> $ <command> runs the command through the native shell in the
+// background, so the editor keeps drawing while e.g. a build runs.
+// Reader threads send stdout and stderr line by line as they arrive,
+// the editor takes them in every frame and draws them in an output
+// panel docked at the bottom of the screen. The top bar shows the
+// running command, <:tk> kills it and <:to> shows/hides the panel.
+
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Instant;
+
+use macroquad::prelude::*;
+
+use crate::options::editor_pallete::*;
+use crate::text::editor_text_stylizer::EditorGeneralTextStylizer;
+
+pub const TERMINAL_OUTPUT_MAX_LINES: usize = 10000;
+pub const TERMINAL_PANEL_HEIGHT_RATIO: f32 = 0.35;
+pub const TERMINAL_FONT_SIZE: u16 = 18;
+pub const TERMINAL_ROW_HEIGHT: f32 = 20.0;
+pub const TERMINAL_MARGINS: f32 = 10.0;
+pub const TERMINAL_SCROLL_STEP: usize = 3;
+pub const TERMINAL_SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorTerminalStatus {
+    Idle,
+    Running,
+    /// Exit code, None if a signal ended it
+    Exited(Option<i32>),
+    Cancelled,
+}
+
+pub struct EditorTerminal {
+    pub command: String,
+    pub output: Vec<String>,
+    pub status: EditorTerminalStatus,
+    /// The output panel is drawn
+    pub visible: bool,
+    /// Lines scrolled up from the end, 0 follows the output
+    pub scroll: usize,
+    child: Option<Child>,
+    receiver: Option<Receiver<String>>,
+    started: Instant,
+}
+
+impl EditorTerminal {
+    pub fn new() -> EditorTerminal {
+        EditorTerminal {
+            command: String::new(),
+            output: Vec::new(),
+            status: EditorTerminalStatus::Idle,
+            visible: false,
+            scroll: 0,
+            child: None,
+            receiver: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// True while the last command's process is alive
+    pub fn is_running(&self) -> bool {
+        self.child.is_some()
+    }
+
+    /// Start `command` in `dir`, its output replaces the panel's
+    pub fn run(
+        &mut self,
+        command: &str,
+        dir: &Path
+    ) -> Result<(), String> {
+        let (shell, flag) = terminal_shell();
+
+        let mut process = Command::new(shell);
+        process.arg(flag)
+            .arg(command)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // A group of its own, so cancelling also kills what the shell started
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            process.process_group(0);
+        }
+
+        let mut child = process.spawn().map_err(|e| e.to_string())?;
+        let (sender, receiver) = mpsc::channel();
+
+        if let Some(stdout) = child.stdout.take() {
+            spawn_reader(stdout, sender.clone());
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            spawn_reader(stderr, sender);
+        }
+
+        self.command = command.to_string();
+        self.output.clear();
+        self.scroll = 0;
+        self.status = EditorTerminalStatus::Running;
+        self.child = Some(child);
+        self.receiver = Some(receiver);
+        self.started = Instant::now();
+        self.visible = true;
+
+        Ok(())
+    }
+
+    /// Take in the output that arrived, and notice the command exiting
+    pub fn update(&mut self) {
+        if let Some(receiver) = &self.receiver {
+            let mut lines = Vec::new();
+
+            loop {
+                match receiver.try_recv() {
+                    Ok(line) => lines.push(line),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.receiver = None;
+                        break;
+                    }
+                }
+            }
+
+            for line in lines {
+                self.push_line(line);
+            }
+        }
+
+        let Some(child) = &mut self.child else {
+            return;
+        };
+
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                self.child = None;
+
+                if self.status == EditorTerminalStatus::Running {
+                    self.status = EditorTerminalStatus::Exited(status.code());
+                }
+            }
+
+            Ok(None) => {}
+
+            Err(_) => {
+                self.child = None;
+                self.status = EditorTerminalStatus::Exited(None);
+            }
+        }
+    }
+
+    /// Kill the running command and everything it started, false if none runs
+    pub fn cancel(&mut self) -> bool {
+        let Some(child) = &mut self.child else {
+            return false;
+        };
+
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+        }
+
+        let _ = child.kill();
+        self.status = EditorTerminalStatus::Cancelled;
+
+        true
+    }
+
+    /// Scroll the output by `delta` lines, positive goes back in time
+    pub fn scroll_by(
+        &mut self,
+        delta: isize
+    ) {
+        self.scroll = self.scroll
+            .saturating_add_signed(delta)
+            .min(self.output.len().saturating_sub(1));
+    }
+
+    fn push_line(
+        &mut self,
+        line: String
+    ) {
+        self.output.push(line);
+
+        // Keep the lines being read in place while scrolled up
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+
+        if self.output.len() > TERMINAL_OUTPUT_MAX_LINES {
+            let excess = self.output.len() - TERMINAL_OUTPUT_MAX_LINES;
+            self.output.drain(..excess);
+            self.scroll = self.scroll.min(self.output.len().saturating_sub(1));
+        }
+    }
+
+    /// The command and how it is doing, e.g. "$ make  running 3s /"
+    pub fn status_line(&self) -> String {
+        let seconds = self.started.elapsed().as_secs();
+
+        let status = match self.status {
+            EditorTerminalStatus::Idle => return String::new(),
+            EditorTerminalStatus::Running => {
+                let spin = TERMINAL_SPINNER[(get_time() * 8.0) as usize % TERMINAL_SPINNER.len()];
+                format!("running {}s {}", seconds, spin)
+            }
+            EditorTerminalStatus::Exited(Some(code)) => format!("exited with {}", code),
+            EditorTerminalStatus::Exited(None) => "terminated".to_string(),
+            EditorTerminalStatus::Cancelled => "cancelled".to_string(),
+        };
+
+        format!("$ {}  {}", self.command, status)
+    }
+
+    /// Mouse wheel scrolling over the panel
+    pub fn record_mouse(&mut self) {
+        if !self.visible {
+            return;
+        }
+
+        let (_, wheel) = mouse_wheel();
+        let (_, mouse_y) = mouse_position();
+
+        if wheel != 0.0 && mouse_y >= panel_top() {
+            let step = TERMINAL_SCROLL_STEP as isize;
+            self.scroll_by(if wheel > 0.0 { step } else { -step });
+        }
+    }
+
+    /// Height of the screen's bottom the panel covers, none while hidden
+    pub fn occupied_height(&self) -> f32 {
+        if self.visible { screen_height() - panel_top() } else { 0.0 }
+    }
+
+    /// Draw the output panel along the bottom of the screen
+    pub fn draw(
+        &self,
+        gts: &mut EditorGeneralTextStylizer
+    ) {
+        if !self.visible {
+            return;
+        }
+
+        let pal = pallete();
+        let top = panel_top();
+
+        draw_rectangle(0.0, top - 1.0, screen_width(), screen_height() - top + 1.0, pal.console_frame);
+        draw_rectangle(0.0, top, screen_width(), screen_height() - top, pal.console_container);
+
+        let previous_size = gts.font_size;
+        let previous_color = gts.color;
+        gts.font_size = TERMINAL_FONT_SIZE;
+
+        let x = TERMINAL_MARGINS;
+        let mut y = top + TERMINAL_MARGINS + TERMINAL_ROW_HEIGHT;
+
+        gts.color = match self.status {
+            EditorTerminalStatus::Exited(Some(0)) => pal.git_added,
+            EditorTerminalStatus::Exited(_) | EditorTerminalStatus::Cancelled => pal.git_deleted,
+            _ => pal.composite_type,
+        };
+
+        let mut title = self.status_line();
+
+        if self.scroll > 0 {
+            title = format!("{}  [-{}]", title, self.scroll);
+        }
+
+        gts.draw(&title, x, y);
+        y += TERMINAL_ROW_HEIGHT * 1.5;
+
+        // The rows that fit, ending `scroll` lines before the last one
+        let rows = (((screen_height() - y) / TERMINAL_ROW_HEIGHT) as usize + 1).max(1);
+        let end = self.output.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(rows);
+
+        gts.color = pal.console_text;
+
+        for line in &self.output[start..end] {
+            gts.draw(line, x, y);
+            y += TERMINAL_ROW_HEIGHT;
+        }
+
+        gts.font_size = previous_size;
+        gts.color = previous_color;
+    }
+
+    /// Draw the running command at the right of the top bar
+    pub fn draw_status(
+        &self,
+        gts: &mut EditorGeneralTextStylizer,
+        y: f32
+    ) {
+        if !self.is_running() {
+            return;
+        }
+
+        let previous_size = gts.font_size;
+        let previous_color = gts.color;
+        gts.font_size = TERMINAL_FONT_SIZE;
+        gts.color = pallete().composite_type;
+
+        let status = self.status_line();
+        let width = gts.measure(&status);
+        gts.draw(&status, screen_width() - width - TERMINAL_MARGINS * 2.0, y);
+
+        gts.font_size = previous_size;
+        gts.color = previous_color;
+    }
+}
+
+/// Screen height the output panel starts at
+fn panel_top() -> f32 {
+    screen_height() * (1.0 - TERMINAL_PANEL_HEIGHT_RATIO)
+}
+
+/// The native shell and its flag to run a command line
+pub fn terminal_shell() -> (&'static str, &'static str) {
+    #[cfg(target_os = "windows")]
+    let (shell, flag) = {
+        // Prefer pwsh if installed (PowerShell 7+), fallback to Windows PowerShell
+        if which::which("pwsh").is_ok() {
+            ("pwsh", "-Command")
+        } else {
+            ("powershell", "-Command")
+        }
+    };
+
+    #[cfg(not(target_os = "windows"))]
+    let (shell, flag) = ("/bin/sh", "-c");
+
+    (shell, flag)
+}
+
+/// Send every line of `stream` until it closes
+fn spawn_reader(
+    stream: impl Read + Send + 'static,
+    sender: Sender<String>
+) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+
+            let text = String::from_utf8_lossy(&line)
+                .trim_end_matches(['\r', '\n'])
+                .replace('\t', "    ");
+
+            // The editor started another command
+            if sender.send(text).is_err() {
+                return;
+            }
+        }
+    });
+}
 
 /// EXPERIMENTAL: Execute terminal commands, with the <:t> $ <command> directive
-pub fn execute_terminal_command(directive_command: &str, current_dir: &Option<std::path::PathBuf>) -> (String, bool) {
+pub fn execute_terminal_command(
+    directive_command: &str,
+    current_dir: &Option<std::path::PathBuf>,
+    terminal: &mut EditorTerminal
+) -> (String, bool) {
     // Extract command after $
     let t_command = directive_command
-        .split('$')
-        .nth(1)
-        .map(|s| s.trim());
+        .split_once('$')
+        .map(|(_, cmd)| cmd.trim());
 
     match t_command {
         Some(cmd) if !cmd.is_empty() => {
-            if let Some(dir) = current_dir {
-                #[cfg(target_os = "windows")]
-                let (shell, flag) = {
-                    // Prefer pwsh if installed (PowerShell 7+), fallback to Windows PowerShell
-                    if which::which("pwsh").is_ok() {
-                        ("pwsh", "-Command")
-                    } else {
-                        ("powershell", "-Command")
-                    }
-                };
-
-                #[cfg(not(target_os = "windows"))]
-                let (shell, flag) = ("/bin/sh", "-c");
-
-                let output = std::process::Command::new(shell)
-                    .arg(flag)
-                    .arg(cmd)
-                    .current_dir(dir)
-                    .stdout(std::process::Stdio::piped())
-                    .stderr(std::process::Stdio::piped())
-                    .output();
-
-                match output {
-                    Ok(output) => {
-                        let mut result = String::new();
-                        if !output.stdout.is_empty() {
-                            result.push_str(&String::from_utf8_lossy(&output.stdout));
-                        }
-                        if !output.stderr.is_empty() {
-                            if !result.is_empty() { result.push('\n'); }
-                            result.push_str(&String::from_utf8_lossy(&output.stderr));
-                        }
-                        (result, true)
-                    }
-                    Err(e) => (format!("Failed to execute command: {} <:t>", e), false),
-                }
-            } else {
-                ("InvalidPathForCommand <:t>".to_string(), false)
+            let Some(dir) = current_dir else {
+                return ("InvalidPathForCommand <:t>".to_string(), false);
+            };
+
+            if terminal.is_running() {
+                return ("CommandStillRunning <:tk>".to_string(), false);
+            }
+
+            match terminal.run(cmd, dir) {
+                Ok(()) => (String::new(), false),
+                Err(e) => (format!("Failed to execute command: {} <:t>", e), false),
             }
         }
         _ => ("NoCommandGiven <:t>".to_string(), false),
@@ -0,0 +1,40 @@
+// Test directory module
+//
+// Fixtures of tests that need files on disk get a directory of their
+// own under the system's temporary one, removed again when dropped.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+pub struct EditorTestDir {
+    path: PathBuf,
+}
+
+impl EditorTestDir {
+    /// An empty directory, `name` tells the tests of a run apart
+    pub fn new(
+        name: &str
+    ) -> EditorTestDir {
+        let path = std::env::temp_dir().join(format!("muse-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        // Canonical, as the paths the editor resolves are
+        EditorTestDir { path: fs::canonicalize(&path).unwrap() }
+    }
+}
+
+impl Deref for EditorTestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for EditorTestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,982 @@
+// VT100 screen module, used by the PTY panel.
+//
+// Output of the shell is fed byte by byte to a small escape sequence
+// parser that keeps a grid of cells, as an xterm would, so full screen
+// programs like htop, less or a REPL draw correctly. It handles:
+//      UTF-8 text, CR, LF, BS, TAB
+//      ESC 7/8, D, E, M, c, ( 0/B (line drawing characters)
+//      CSI cursor movement, erase, insert/delete, scroll region, SGR colours
+//      CSI ? 1, 7, 25, 47/1047/1049 modes, cursor position and device reports
+//      OSC titles
+// Anything else is swallowed. Lines scrolled off the top of the main
+// screen go to the scrollback, the alternate screen keeps none.
+
+use std::collections::VecDeque;
+
+use macroquad::prelude::Color;
+
+pub const VT_SCROLLBACK_LIMIT: usize = 5000;
+pub const VT_TAB_WIDTH: usize = 8;
+pub const VT_OSC_LIMIT: usize = 1024;
+
+type EditorVtGrid = Vec<Vec<EditorVtCell>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditorVtColor {
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EditorVtCell {
+    pub c: char,
+    pub fg: EditorVtColor,
+    pub bg: EditorVtColor,
+    pub bold: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl EditorVtCell {
+    pub fn blank() -> EditorVtCell {
+        EditorVtCell {
+            c: ' ',
+            fg: EditorVtColor::Default,
+            bg: EditorVtColor::Default,
+            bold: false,
+            underline: false,
+            reverse: false,
+        }
+    }
+
+    /// True if both cells are drawn the same way, characters aside
+    pub fn same_style(
+        &self,
+        other: &EditorVtCell
+    ) -> bool {
+        self.fg == other.fg && self.bg == other.bg && self.bold == other.bold
+            && self.underline == other.underline && self.reverse == other.reverse
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EditorVtState {
+    Ground,
+    Escape,
+    /// ESC ( or ESC ), the next byte picks a character set
+    Charset(u8),
+    Csi,
+    Osc,
+    /// ESC inside an OSC, the start of its terminator
+    OscEscape,
+}
+
+pub struct EditorVtScreen {
+    pub cols: usize,
+    pub rows: usize,
+    pub lines: EditorVtGrid,
+    pub scrollback: VecDeque<Vec<EditorVtCell>>,
+    /// Column and row of the cursor
+    pub cursor: (usize, usize),
+    pub cursor_visible: bool,
+    /// Arrow keys are sent as ESC O x rather than ESC [ x
+    pub application_cursor: bool,
+    pub title: String,
+    /// Replies to the program's queries, written back to it
+    pub responses: Vec<u8>,
+    pen: EditorVtCell,
+    saved: ((usize, usize), EditorVtCell),
+    scroll_top: usize,
+    scroll_bottom: usize,
+    /// The last column was written, the next character wraps
+    wrap_pending: bool,
+    autowrap: bool,
+    insert_mode: bool,
+    line_drawing: bool,
+    last_char: char,
+    /// The main screen and its cursor, while the alternate one is shown
+    main_screen: Option<(EditorVtGrid, (usize, usize))>,
+    state: EditorVtState,
+    sequence: String,
+    osc: Vec<u8>,
+    utf8: Vec<u8>,
+}
+
+impl EditorVtScreen {
+    pub fn new(
+        cols: usize,
+        rows: usize
+    ) -> EditorVtScreen {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+
+        EditorVtScreen {
+            cols,
+            rows,
+            lines: vec![vec![EditorVtCell::blank(); cols]; rows],
+            scrollback: VecDeque::new(),
+            cursor: (0, 0),
+            cursor_visible: true,
+            application_cursor: false,
+            title: String::new(),
+            responses: Vec::new(),
+            pen: EditorVtCell::blank(),
+            saved: ((0, 0), EditorVtCell::blank()),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            wrap_pending: false,
+            autowrap: true,
+            insert_mode: false,
+            line_drawing: false,
+            last_char: ' ',
+            main_screen: None,
+            state: EditorVtState::Ground,
+            sequence: String::new(),
+            osc: Vec::new(),
+            utf8: Vec::new(),
+        }
+    }
+
+    /// True while a full screen program uses the alternate screen
+    pub fn is_alternate(&self) -> bool {
+        self.main_screen.is_some()
+    }
+
+    /// Line `row` of the view scrolled `scroll` lines back into the scrollback
+    pub fn view_line(
+        &self,
+        row: usize,
+        scroll: usize
+    ) -> Option<&Vec<EditorVtCell>> {
+        let index = (self.scrollback.len() + row).checked_sub(scroll)?;
+
+        if index < self.scrollback.len() {
+            self.scrollback.get(index)
+        } else {
+            self.lines.get(index - self.scrollback.len())
+        }
+    }
+
+    /// Change the grid's size, lines above the cursor go to the scrollback
+    pub fn resize(
+        &mut self,
+        cols: usize,
+        rows: usize
+    ) {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+
+        if cols == self.cols && rows == self.rows {
+            return;
+        }
+
+        if rows < self.lines.len() {
+            let excess = self.lines.len() - rows;
+            let pushed = excess.min(self.cursor.1);
+
+            for line in self.lines.drain(..pushed) {
+                if self.main_screen.is_none() {
+                    self.scrollback.push_back(line);
+                }
+            }
+
+            self.lines.truncate(rows);
+            self.cursor.1 -= pushed;
+        }
+
+        while self.lines.len() < rows {
+            self.lines.push(vec![EditorVtCell::blank(); cols]);
+        }
+
+        for line in &mut self.lines {
+            line.resize(cols, EditorVtCell::blank());
+        }
+
+        if let Some((screen, cursor)) = &mut self.main_screen {
+            screen.resize(rows, vec![EditorVtCell::blank(); cols]);
+
+            for line in screen.iter_mut() {
+                line.resize(cols, EditorVtCell::blank());
+            }
+
+            cursor.0 = cursor.0.min(cols - 1);
+            cursor.1 = cursor.1.min(rows - 1);
+        }
+
+        self.trim_scrollback();
+
+        self.cols = cols;
+        self.rows = rows;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.cursor.0 = self.cursor.0.min(cols - 1);
+        self.cursor.1 = self.cursor.1.min(rows - 1);
+        self.saved.0 = (self.saved.0.0.min(cols - 1), self.saved.0.1.min(rows - 1));
+        self.wrap_pending = false;
+    }
+
+    /// Parse the program's output
+    pub fn feed(
+        &mut self,
+        bytes: &[u8]
+    ) {
+        for &byte in bytes {
+            self.feed_byte(byte);
+        }
+    }
+
+    fn feed_byte(
+        &mut self,
+        byte: u8
+    ) {
+        match self.state {
+            EditorVtState::Ground => self.ground(byte),
+
+            EditorVtState::Escape => self.escape(byte),
+
+            // Only G0 is ever shown, line drawing is '0', ASCII 'B'
+            EditorVtState::Charset(b'(') => {
+                self.line_drawing = byte == b'0';
+                self.state = EditorVtState::Ground;
+            }
+
+            EditorVtState::Charset(_) => self.state = EditorVtState::Ground,
+
+            EditorVtState::Csi => match byte {
+                0x1B => self.start_escape(),
+                0x18 | 0x1A => self.state = EditorVtState::Ground,
+                0x00..=0x1F => self.control(byte),
+                0x20..=0x3F => self.sequence.push(byte as char),
+                0x40..=0x7E => {
+                    self.state = EditorVtState::Ground;
+                    let sequence = std::mem::take(&mut self.sequence);
+                    self.csi(&sequence, byte as char);
+                }
+                _ => self.state = EditorVtState::Ground,
+            },
+
+            EditorVtState::Osc => match byte {
+                0x07 => self.osc_end(),
+                0x1B => self.state = EditorVtState::OscEscape,
+                _ => {
+                    if self.osc.len() < VT_OSC_LIMIT {
+                        self.osc.push(byte);
+                    }
+                }
+            },
+
+            EditorVtState::OscEscape => {
+                self.osc_end();
+
+                if byte != b'\\' {
+                    self.escape(byte);
+                }
+            }
+        }
+    }
+
+    fn start_escape(&mut self) {
+        self.utf8.clear();
+        self.sequence.clear();
+        self.state = EditorVtState::Escape;
+    }
+
+    fn ground(
+        &mut self,
+        byte: u8
+    ) {
+        if byte < 0x80 {
+            self.utf8.clear();
+
+            match byte {
+                0x1B => self.start_escape(),
+                0x20..=0x7E => self.print(byte as char),
+                _ => self.control(byte),
+            }
+
+            return;
+        }
+
+        // Continuation bytes of a character being read
+        if !self.utf8.is_empty() && byte & 0xC0 == 0x80 {
+            self.utf8.push(byte);
+        } else {
+            self.utf8.clear();
+            self.utf8.push(byte);
+        }
+
+        let expected = match self.utf8[0] {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+
+        if self.utf8.len() < expected {
+            return;
+        }
+
+        let c = std::str::from_utf8(&self.utf8)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or('\u{FFFD}');
+
+        self.utf8.clear();
+        self.print(c);
+    }
+
+    fn control(
+        &mut self,
+        byte: u8
+    ) {
+        match byte {
+            // Backspace
+            0x08 => {
+                self.cursor.0 = self.cursor.0.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+
+            0x09 => {
+                let next = (self.cursor.0 / VT_TAB_WIDTH + 1) * VT_TAB_WIDTH;
+                self.cursor.0 = next.min(self.cols - 1);
+            }
+
+            0x0A..=0x0C => self.linefeed(),
+
+            0x0D => {
+                self.cursor.0 = 0;
+                self.wrap_pending = false;
+            }
+
+            _ => {}
+        }
+    }
+
+    fn escape(
+        &mut self,
+        byte: u8
+    ) {
+        self.state = EditorVtState::Ground;
+
+        match byte {
+            b'[' => self.state = EditorVtState::Csi,
+
+            b']' => {
+                self.osc.clear();
+                self.state = EditorVtState::Osc;
+            }
+
+            b'(' | b')' | b'*' | b'+' | b'#' => self.state = EditorVtState::Charset(byte),
+
+            b'7' => self.saved = (self.cursor, self.pen),
+
+            b'8' => self.restore_cursor(),
+
+            b'D' => self.linefeed(),
+
+            b'E' => {
+                self.cursor.0 = 0;
+                self.linefeed();
+            }
+
+            b'M' => self.reverse_index(),
+
+            b'c' => {
+                let (cols, rows) = (self.cols, self.rows);
+                let scrollback = std::mem::take(&mut self.scrollback);
+
+                *self = EditorVtScreen::new(cols, rows);
+                self.scrollback = scrollback;
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Back to the cursor and pen of ESC 7, CSI s or mode 1049, kept on the grid
+    fn restore_cursor(&mut self) {
+        let ((x, y), pen) = self.saved;
+
+        self.cursor = (x.min(self.cols - 1), y.min(self.rows - 1));
+        self.pen = pen;
+        self.wrap_pending = false;
+    }
+
+    fn osc_end(&mut self) {
+        self.state = EditorVtState::Ground;
+
+        let osc = String::from_utf8_lossy(&self.osc).to_string();
+
+        // 0 and 2 set the window title
+        if let Some((kind, title)) = osc.split_once(';') && (kind == "0" || kind == "2") {
+            self.title = title.to_string();
+        }
+
+        self.osc.clear();
+    }
+
+    fn print(
+        &mut self,
+        c: char
+    ) {
+        let c = if self.line_drawing { line_drawing_char(c) } else { c };
+
+        if self.wrap_pending {
+            self.wrap_pending = false;
+
+            if self.autowrap {
+                self.cursor.0 = 0;
+                self.linefeed();
+            }
+        }
+
+        let (x, y) = self.cursor;
+
+        if self.insert_mode {
+            let line = &mut self.lines[y];
+            line.insert(x, EditorVtCell::blank());
+            line.truncate(self.cols);
+        }
+
+        self.lines[y][x] = EditorVtCell { c, ..self.pen };
+        self.last_char = c;
+
+        if x + 1 < self.cols {
+            self.cursor.0 += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn linefeed(&mut self) {
+        self.wrap_pending = false;
+
+        if self.cursor.1 == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.1 + 1 < self.rows {
+            self.cursor.1 += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+
+        if self.cursor.1 == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.cursor.1 = self.cursor.1.saturating_sub(1);
+        }
+    }
+
+    /// A blank line, in the pen's background as xterm erases
+    fn blank_line(&self) -> Vec<EditorVtCell> {
+        vec![self.blank_cell(); self.cols]
+    }
+
+    fn blank_cell(&self) -> EditorVtCell {
+        EditorVtCell { bg: self.pen.bg, ..EditorVtCell::blank() }
+    }
+
+    /// Scroll the region up, the main screen's top lines go to the scrollback
+    fn scroll_up(
+        &mut self,
+        n: usize
+    ) {
+        for _ in 0..n.min(self.scroll_bottom + 1 - self.scroll_top) {
+            let line = self.lines.remove(self.scroll_top);
+            self.lines.insert(self.scroll_bottom, self.blank_line());
+
+            if self.scroll_top == 0 && self.main_screen.is_none() {
+                self.scrollback.push_back(line);
+            }
+        }
+
+        self.trim_scrollback();
+    }
+
+    fn scroll_down(
+        &mut self,
+        n: usize
+    ) {
+        for _ in 0..n.min(self.scroll_bottom + 1 - self.scroll_top) {
+            self.lines.remove(self.scroll_bottom);
+            self.lines.insert(self.scroll_top, self.blank_line());
+        }
+    }
+
+    fn trim_scrollback(&mut self) {
+        while self.scrollback.len() > VT_SCROLLBACK_LIMIT {
+            self.scrollback.pop_front();
+        }
+    }
+
+    fn erase(
+        &mut self,
+        row: usize,
+        from: usize,
+        to: usize
+    ) {
+        let blank = self.blank_cell();
+        let to = to.min(self.cols);
+
+        if from < to {
+            self.lines[row][from..to].fill(blank);
+        }
+    }
+
+    fn csi(
+        &mut self,
+        sequence: &str,
+        action: char
+    ) {
+        let private = sequence.chars().next().filter(|c| "?<=>".contains(*c));
+        let body = sequence.trim_start_matches(['?', '<', '=', '>']);
+
+        // Intermediate bytes, e.g. the space of "CSI 2 SP q", pick variants we don't draw
+        if body.contains(|c: char| (' '..='/').contains(&c)) {
+            return;
+        }
+
+        let params: Vec<usize> = body.split([';', ':'])
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+        let count = |i: usize| param(i).max(1);
+
+        let (x, y) = self.cursor;
+        let (cols, rows) = (self.cols, self.rows);
+
+        if action != 'b' {
+            self.wrap_pending = false;
+        }
+
+        match (private, action) {
+            (None, '@') => {
+                let blank = self.blank_cell();
+                let line = &mut self.lines[y];
+
+                for _ in 0..count(0).min(cols - x) {
+                    line.insert(x, blank);
+                }
+
+                line.truncate(cols);
+            }
+
+            (None, 'A') => {
+                let top = if y >= self.scroll_top { self.scroll_top } else { 0 };
+                self.cursor.1 = y.saturating_sub(count(0)).max(top);
+            }
+
+            (None, 'B' | 'e') => {
+                let bottom = if y <= self.scroll_bottom { self.scroll_bottom } else { rows - 1 };
+                self.cursor.1 = y.saturating_add(count(0)).min(bottom);
+            }
+
+            (None, 'C' | 'a') => self.cursor.0 = x.saturating_add(count(0)).min(cols - 1),
+
+            (None, 'D') => self.cursor.0 = x.saturating_sub(count(0)),
+
+            (None, 'E') => self.cursor = (0, y.saturating_add(count(0)).min(rows - 1)),
+
+            (None, 'F') => self.cursor = (0, y.saturating_sub(count(0))),
+
+            (None, 'G' | '`') => self.cursor.0 = (count(0) - 1).min(cols - 1),
+
+            (None, 'H' | 'f') => {
+                self.cursor = ((count(1) - 1).min(cols - 1), (count(0) - 1).min(rows - 1));
+            }
+
+            (None, 'd') => self.cursor.1 = (count(0) - 1).min(rows - 1),
+
+            (None, 'J') => match param(0) {
+                0 => {
+                    self.erase(y, x, cols);
+                    (y + 1..rows).for_each(|row| self.erase(row, 0, cols));
+                }
+                1 => {
+                    (0..y).for_each(|row| self.erase(row, 0, cols));
+                    self.erase(y, 0, x + 1);
+                }
+                2 => (0..rows).for_each(|row| self.erase(row, 0, cols)),
+                3 => self.scrollback.clear(),
+                _ => {}
+            },
+
+            (None, 'K') => match param(0) {
+                0 => self.erase(y, x, cols),
+                1 => self.erase(y, 0, x + 1),
+                2 => self.erase(y, 0, cols),
+                _ => {}
+            },
+
+            (None, 'L') if (self.scroll_top..=self.scroll_bottom).contains(&y) => {
+                for _ in 0..count(0).min(self.scroll_bottom + 1 - y) {
+                    self.lines.remove(self.scroll_bottom);
+                    self.lines.insert(y, self.blank_line());
+                }
+
+                self.cursor.0 = 0;
+            }
+
+            (None, 'M') if (self.scroll_top..=self.scroll_bottom).contains(&y) => {
+                for _ in 0..count(0).min(self.scroll_bottom + 1 - y) {
+                    self.lines.remove(y);
+                    self.lines.insert(self.scroll_bottom, self.blank_line());
+                }
+
+                self.cursor.0 = 0;
+            }
+
+            (None, 'P') => {
+                let blank = self.blank_cell();
+                let line = &mut self.lines[y];
+
+                for _ in 0..count(0).min(cols - x) {
+                    line.remove(x);
+                    line.push(blank);
+                }
+            }
+
+            (None, 'X') => self.erase(y, x, x.saturating_add(count(0))),
+
+            (None, 'S') => self.scroll_up(count(0)),
+
+            (None, 'T') => self.scroll_down(count(0)),
+
+            // Repeat the last character
+            (None, 'b') => {
+                for _ in 0..count(0).min(cols * rows) {
+                    self.print(self.last_char);
+                }
+            }
+
+            (None, 'r') => {
+                let top = count(0) - 1;
+                let bottom = if param(1) == 0 { rows } else { param(1).min(rows) } - 1;
+
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.cursor = (0, 0);
+                }
+            }
+
+            (None, 'm') => self.sgr(&params),
+
+            (None, 's') => self.saved = (self.cursor, self.pen),
+
+            (None, 'u') => self.restore_cursor(),
+
+            (None, 'n') => match param(0) {
+                5 => self.responses.extend_from_slice(b"\x1b[0n"),
+                6 => self.responses.extend(format!("\x1b[{};{}R", y + 1, x + 1).bytes()),
+                _ => {}
+            },
+
+            (None, 'c') if param(0) == 0 => self.responses.extend_from_slice(b"\x1b[?1;2c"),
+
+            (Some('>'), 'c') => self.responses.extend_from_slice(b"\x1b[>0;0;0c"),
+
+            (None, 'h' | 'l') if params.contains(&4) => self.insert_mode = action == 'h',
+
+            (Some('?'), 'h' | 'l') => {
+                let set = action == 'h';
+
+                for mode in &params {
+                    self.private_mode(*mode, set);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn private_mode(
+        &mut self,
+        mode: usize,
+        set: bool
+    ) {
+        match mode {
+            1 => self.application_cursor = set,
+            7 => self.autowrap = set,
+            25 => self.cursor_visible = set,
+
+            47 | 1047 | 1049 => {
+                if set && self.main_screen.is_none() {
+                    if mode == 1049 {
+                        self.saved = (self.cursor, self.pen);
+                    }
+
+                    let blank = vec![vec![EditorVtCell::blank(); self.cols]; self.rows];
+                    let main = std::mem::replace(&mut self.lines, blank);
+                    self.main_screen = Some((main, self.cursor));
+                } else if !set && let Some((main, cursor)) = self.main_screen.take() {
+                    self.lines = main;
+                    self.cursor = cursor;
+
+                    if mode == 1049 {
+                        self.restore_cursor();
+                    }
+                }
+
+                self.scroll_top = 0;
+                self.scroll_bottom = self.rows - 1;
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Select Graphic Rendition, the pen's colours and attributes
+    fn sgr(
+        &mut self,
+        params: &[usize]
+    ) {
+        let mut i = 0;
+
+        while i < params.len() {
+            match params[i] {
+                0 => self.pen = EditorVtCell::blank(),
+                1 => self.pen.bold = true,
+                4 => self.pen.underline = true,
+                7 => self.pen.reverse = true,
+                22 => self.pen.bold = false,
+                24 => self.pen.underline = false,
+                27 => self.pen.reverse = false,
+                n @ 30..=37 => self.pen.fg = EditorVtColor::Indexed((n - 30) as u8),
+                39 => self.pen.fg = EditorVtColor::Default,
+                n @ 40..=47 => self.pen.bg = EditorVtColor::Indexed((n - 40) as u8),
+                49 => self.pen.bg = EditorVtColor::Default,
+                n @ 90..=97 => self.pen.fg = EditorVtColor::Indexed((n - 90 + 8) as u8),
+                n @ 100..=107 => self.pen.bg = EditorVtColor::Indexed((n - 100 + 8) as u8),
+
+                // 38;5;n or 38;2;r;g;b, 48 for the background
+                n @ (38 | 48) => {
+                    let color = match params.get(i + 1) {
+                        Some(5) => {
+                            let index = params.get(i + 2).copied().unwrap_or(0);
+                            i += 2;
+                            EditorVtColor::Indexed(index.min(255) as u8)
+                        }
+                        Some(2) => {
+                            let channel = |j: usize| params.get(i + j).copied().unwrap_or(0).min(255) as u8;
+                            let rgb = EditorVtColor::Rgb(channel(2), channel(3), channel(4));
+                            i += 4;
+                            rgb
+                        }
+                        _ => EditorVtColor::Default,
+                    };
+
+                    if n == 38 {
+                        self.pen.fg = color;
+                    } else {
+                        self.pen.bg = color;
+                    }
+                }
+
+                _ => {}
+            }
+
+            i += 1;
+        }
+
+        if params.is_empty() {
+            self.pen = EditorVtCell::blank();
+        }
+    }
+}
+
+/// DEC special graphics, the box drawing set of ESC ( 0
+fn line_drawing_char(
+    c: char
+) -> char {
+    match c {
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'q' => '─',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'a' => '▒',
+        '`' => '◆',
+        'f' => '°',
+        'g' => '±',
+        '~' => '·',
+        _ => c,
+    }
+}
+
+/// xterm's colours, the first 16 are the normal and bright ANSI ones
+pub fn vt_color(
+    color: EditorVtColor,
+    bold: bool,
+    default: Color
+) -> Color {
+    const ANSI: [u32; 16] = [
+        0x000000, 0xCD0000, 0x00CD00, 0xCDCD00, 0x0000EE, 0xCD00CD, 0x00CDCD, 0xE5E5E5,
+        0x7F7F7F, 0xFF0000, 0x00FF00, 0xFFFF00, 0x5C5CFF, 0xFF00FF, 0x00FFFF, 0xFFFFFF,
+    ];
+
+    match color {
+        EditorVtColor::Default => default,
+        EditorVtColor::Indexed(n) if n < 8 && bold => Color::from_hex(ANSI[n as usize + 8]),
+        EditorVtColor::Indexed(n) if n < 16 => Color::from_hex(ANSI[n as usize]),
+
+        // 6x6x6 cube, then 24 grays
+        EditorVtColor::Indexed(n) if n < 232 => {
+            let n = n - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            Color::from_rgba(level(n / 36), level(n / 6 % 6), level(n % 6), 255)
+        }
+
+        EditorVtColor::Indexed(n) => {
+            let gray = 8 + (n - 232) * 10;
+            Color::from_rgba(gray, gray, gray, 255)
+        }
+
+        EditorVtColor::Rgb(r, g, b) => Color::from_rgba(r, g, b, 255),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The characters of a row, trailing blanks left out
+    fn row(
+        screen: &EditorVtScreen,
+        y: usize
+    ) -> String {
+        screen.lines[y].iter().map(|cell| cell.c).collect::<String>().trim_end().to_string()
+    }
+
+    fn screen_with(
+        cols: usize,
+        rows: usize,
+        output: &str
+    ) -> EditorVtScreen {
+        let mut screen = EditorVtScreen::new(cols, rows);
+        screen.feed(output.as_bytes());
+        screen
+    }
+
+    #[test]
+    fn saved_cursor_stays_on_the_grid_after_a_resize() {
+        // ESC 7 / ESC 8, then CSI s / CSI u, then a 1049 round trip
+        for (save, restore) in [("\x1b7", "\x1b8"), ("\x1b[s", "\x1b[u"), ("\x1b[?1049h", "\x1b[?1049l")] {
+            let mut screen = screen_with(80, 24, &format!("\x1b[20;70H{}", save));
+
+            screen.resize(40, 10);
+            screen.feed(format!("{}X\x1b[@\x1b[P\x1b[K\x1b[1J", restore).as_bytes());
+
+            assert!(screen.cursor.0 < 40 && screen.cursor.1 < 10);
+        }
+
+        let mut screen = screen_with(80, 24, "\x1b[20;70H\x1b7");
+        screen.resize(40, 10);
+        screen.feed(b"\x1b8Z");
+
+        assert_eq!(row(&screen, 9), format!("{}Z", " ".repeat(39)));
+    }
+
+    #[test]
+    fn huge_counts_stop_at_the_edges() {
+        for action in ['B', 'e', 'C', 'a', 'E', 'X'] {
+            let mut screen = screen_with(10, 5, "ab\x1b[2;3H");
+            screen.feed(format!("\x1b[18446744073709551615{}Z", action).as_bytes());
+
+            assert!(screen.cursor.0 < 10 && screen.cursor.1 < 5, "{}", action);
+        }
+
+        let screen = screen_with(10, 5, "abcdef\x1b[1;3H\x1b[18446744073709551615X");
+        assert_eq!(row(&screen, 0), "ab");
+    }
+
+    #[test]
+    fn text_wraps_at_the_last_column() {
+        let screen = screen_with(5, 3, "abcdefg");
+
+        assert_eq!(row(&screen, 0), "abcde");
+        assert_eq!(row(&screen, 1), "fg");
+        assert_eq!(screen.cursor, (2, 1));
+
+        // Filling the last column waits for the next character to wrap
+        let screen = screen_with(5, 3, "abcde\r\nx");
+        assert_eq!(row(&screen, 1), "x");
+
+        let screen = screen_with(5, 3, "\x1b[?7labcdefg");
+        assert_eq!(row(&screen, 0), "abcdg");
+        assert_eq!(row(&screen, 1), "");
+    }
+
+    #[test]
+    fn scroll_regions_keep_the_lines_around_them() {
+        let mut screen = screen_with(10, 5, "1\r\n2\r\n3\r\n4\r\n5");
+
+        screen.feed(b"\x1b[2;4r\x1b[4;1H\n");
+
+        let rows: Vec<String> = (0..5).map(|y| row(&screen, y)).collect();
+        assert_eq!(rows, ["1", "3", "4", "", "5"]);
+        assert!(screen.scrollback.is_empty());
+
+        screen.feed(b"\x1b[2;1H\x1bM");
+
+        let rows: Vec<String> = (0..5).map(|y| row(&screen, y)).collect();
+        assert_eq!(rows, ["1", "", "3", "4", "5"]);
+
+        // The whole screen scrolls into the scrollback
+        let screen = screen_with(10, 2, "1\r\n2\r\n3");
+        assert_eq!(screen.scrollback.len(), 1);
+        assert_eq!(row(&screen, 0), "2");
+    }
+
+    #[test]
+    fn alternate_screen_round_trips() {
+        let mut screen = screen_with(10, 3, "main");
+
+        screen.feed(b"\x1b[?1049h");
+        assert!(screen.is_alternate());
+        assert_eq!(row(&screen, 0), "");
+
+        screen.feed(b"alt\r\n\n\n\n");
+        assert!(screen.scrollback.is_empty());
+
+        screen.feed(b"\x1b[?1049l");
+        assert!(!screen.is_alternate());
+        assert_eq!(row(&screen, 0), "main");
+        assert_eq!(screen.cursor, (4, 0));
+    }
+
+    #[test]
+    fn sgr_sets_indexed_and_rgb_colours() {
+        let screen = screen_with(10, 1, "\x1b[38;5;196mA\x1b[48;2;1;2;300mB\x1b[0mC\x1b[1;31mD\x1b[mE");
+        let cells = &screen.lines[0];
+
+        assert_eq!(cells[0].fg, EditorVtColor::Indexed(196));
+        assert_eq!(cells[0].bg, EditorVtColor::Default);
+        assert_eq!(cells[1].fg, EditorVtColor::Indexed(196));
+        assert_eq!(cells[1].bg, EditorVtColor::Rgb(1, 2, 255));
+        assert!(cells[2].same_style(&EditorVtCell::blank()));
+        assert!(cells[3].bold);
+        assert_eq!(cells[3].fg, EditorVtColor::Indexed(1));
+        assert!(cells[4].same_style(&EditorVtCell::blank()));
+    }
+
+    #[test]
+    fn utf8_split_across_reads() {
+        let mut screen = EditorVtScreen::new(10, 1);
+
+        for part in [&b"\xC3"[..], b"\xA9", b"\xF0\x9F", b"\x98", b"\x80", b"\xC3A"] {
+            screen.feed(part);
+        }
+
+        assert_eq!(row(&screen, 0), "é😀A");
+    }
+}
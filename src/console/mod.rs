@@ -1,5 +1,18 @@
 pub mod editor_file_system;
 pub mod editor_directives;
 pub mod editor_terminal;
+pub mod editor_pty;
+pub mod editor_vt;
 pub mod editor_console;
 pub mod editor_console_cursor;
+pub mod editor_console_list;
+pub mod editor_file_finder;
+pub mod editor_git;
+pub mod editor_grep;
+pub mod editor_symbol_index;
+pub mod editor_build;
+#[cfg(test)]
+pub mod editor_test_dir;
+pub mod editor_json;
+pub mod editor_lsp_client;
+pub mod editor_lsp;
@@ -10,42 +10,50 @@ mod win;
 
 use std::vec;
 use macroquad::prelude::*;
+use once_cell::sync::Lazy;
 
 use crate::audio::editor_audio::EditorAudio;
 use crate::camera::editor_camera::EditorCamera;
-use crate::console::editor_console::{EditorConsole, console_message};
+use crate::console::editor_console::{CONSOLE_MARGINS, EditorConsole, console_message, draw_name_completions};
 use crate::console::editor_file_system::{EditorFileSystem, draw_dir_contents, path_buffer_file_to_string, path_buffer_to_string};
-use crate::options::editor_options::EditorOptions;
-use crate::options::editor_pallete::{BACKGROUND_COLOR, COMPOSITE_TYPE_COLOR, CONSOLE_TEXT_COLOR, FILE_COLOR, FOLDER_COLOR, PUNCTUATION_COLOR};
+use crate::console::editor_lsp::apply_lsp_event;
+use crate::options::editor_options::{EditorOptions, PREF_FILE};
+use crate::options::editor_paths::EDITOR_HOME;
+use crate::options::editor_fonts::{list_fonts, load_fallback_fonts, load_font};
+use crate::options::editor_pallete::{list_palletes, load_pallete, pallete, set_pallete};
+use crate::text::editor_buffer::{BUFFER_TAB_SPACING, EditorBuffers};
 use crate::text::editor_cursor::{CURSOR_WORD_OFFSET, EditorCursor};
+use crate::text::editor_history::EditorHistory;
+use crate::text::editor_search::{EditorSearch, parse_search_directive};
+use crate::text::editor_autocomplete::EditorAutocomplete;
 use crate::text::editor_input::record_keyboard_to_file_text;
-use crate::text::editor_language_manager::{EditorLanguageKeywords ,load_keywords_for_extension};
+use crate::text::editor_language_manager::{EditorLanguageKeywords ,language_errors, list_language_names, load_keywords_for_extension};
+use crate::text::editor_lexer::EditorLexCache;
 use crate::text::editor_text::{CURRENT_FILE_TOP_BAR_OFFSET, MODE_FONT_SIZE, MODE_Y_MARGIN, MODE_Y_OFFSET, draw_file_text};
 use crate::text::editor_text_stylizer::EditorGeneralTextStylizer;
 use crate::win::editor_win_config::window_conf;
 
 // TODO: Finish all the directives.
-// TODO: Add Ctrl + z to undo last change.
-// TODO: Add Ctrl + c to copy selected text.
-// TODO: Add Ctrl + v to paste copied text. V++
 // TODO: Add the palletes.
 // TODO: Add more fonts.
 
-// IDEA: Add selection mode
-// IDEA: Add a list of user defined functions to make it easier to traverse files. V++
-// IDEA: Add a list of user defined identifiers that will pop up as an autocomplete thing.
-// IDEA: Add a cmd/terminal wrapper maybe, for compiling/executing code and git commands.
-// IDEA: Add file markings in specific file indeces for faster traversal <:mark>.
-// IDEA: Add file markings finder <:spot>, moves by one in each directive return, won't clear inside the console so the user can keep moving.
-// IDEA: Add file markings finder <:spot N>, moves to the N-th marked spot inside the file. 
-// IDEA: Add multiple open files support
-
 pub const VERSION: &str = "Muse-v01.05.03";
 
 #[macroquad::main(window_conf())]
 async fn main() {
-    // Editor options
-    let mut ops = EditorOptions::new();    
+    // Pin the editor's data directory before any :cd
+    Lazy::force(&EDITOR_HOME);
+    // Editor options, saved in user.pref
+    let mut ops = EditorOptions::new();
+    let mut pref_errors = ops.load();
+    pref_errors.extend(language_errors());
+
+    if !ops.pallete.is_empty() {
+        match load_pallete(&ops.pallete) {
+            Ok(loaded) => set_pallete(loaded),
+            Err(e) => pref_errors.push(format!("{} '{}' <{}>", e, ops.pallete, PREF_FILE)),
+        }
+    }
     // Editor camera
     let mut ec = EditorCamera::new();
     // File system
@@ -56,91 +64,275 @@ async fn main() {
     let mut file_gts = EditorGeneralTextStylizer::new().await;
     // Editor general text stylizer for console, font already preloaded.
     let mut console_gts = EditorGeneralTextStylizer::new().await;
-    console_gts.color = CONSOLE_TEXT_COLOR;
+    console_gts.color = pallete().console_text;
     console_gts.font_size = 30;
+    file_gts.font_size = ops.font_size;
     // Editor Cursor
     let mut file_cursor = EditorCursor::new();
     // Console
     let mut console = EditorConsole::new();
+    console.width = ops.console_width;
+    console.target_w = ops.console_width;
+
+    if ops.fullscreen {
+        set_fullscreen(true);
+    }
+
+    // Bad preferences and language definitions are reported, the rest of them still apply,
+    // several errors are listed in the manual view
+    if !pref_errors.is_empty() {
+        console.showing_manual = pref_errors.len() > 1;
+        console.message = if console.showing_manual {
+            format!("\n\n{}", pref_errors.join("\n"))
+        } else {
+            pref_errors[0].clone()
+        };
+        console.showing_message = true;
+    }
     // Actual file text
     let mut file_text = vec![];
     // Language support based on file, default no higlighting
     let mut elk: EditorLanguageKeywords = load_keywords_for_extension("txt"); 
+    // Undo/redo history, per file
+    let mut history = EditorHistory::new();
+    // Open buffers, the active one is the state above
+    let mut buffers = EditorBuffers::new();
+    // Infile search
+    let mut search = EditorSearch::new();
+    // Lexed lines of the active buffer, for highlighting
+    let mut lex_cache = EditorLexCache::new();
+    // Identifier completion popup
+    let mut completion = EditorAutocomplete::new();
+
+    // Font and fallback chain the stylizers use, the default font is preloaded
+    let mut loaded_font = String::new();
+    let mut loaded_fallback: Vec<String> = Vec::new();
 
-    let insert_word_w = measure_text("INSERT MODE", Some(&console_gts.font), MODE_FONT_SIZE as u16, 1.0).width;
-    let select_word_w = measure_text("SELECTION MODE", Some(&console_gts.font), MODE_FONT_SIZE as u16, 1.0).width;
-    let console_word_w = measure_text("CONSOLE MODE", Some(&console_gts.font), MODE_FONT_SIZE as u16, 1.0).width;
+    let mut insert_word_w = console_gts.measure("INSERT MODE");
+    let mut select_word_w = console_gts.measure("SELECTION MODE");
+    let mut console_word_w = console_gts.measure("CONSOLE MODE");
 
     loop {
-        clear_background(BACKGROUND_COLOR);
+        clear_background(pallete().background);
+
+        // Hot-swap the font after :efn or from user.pref
+        if ops.font != loaded_font || ops.font_fallback != loaded_fallback {
+            if ops.font != loaded_font {
+                match load_font(&ops.font) {
+                    Ok(font) => {
+                        file_gts.font = font.clone();
+                        console_gts.font = font;
+                    }
+                    Err(e) => {
+                        console.message = format!("{} '{}' <{}>", e, ops.font, PREF_FILE);
+                        console.showing_message = true;
+                    }
+                }
 
-        draw_file_text(&mut file_text, &mut file_cursor, &mut file_gts, &console, &mut ec, &elk);
+                // A bad font is reported once, the current one stays
+                loaded_font = ops.font.clone();
+            }
+
+            if ops.font_fallback != loaded_fallback {
+                file_gts.fallbacks = load_fallback_fonts(&ops.font_fallback);
+                console_gts.fallbacks = file_gts.fallbacks.clone();
+                loaded_fallback = ops.font_fallback.clone();
+            }
+
+            insert_word_w = console_gts.measure("INSERT MODE");
+            select_word_w = console_gts.measure("SELECTION MODE");
+            console_word_w = console_gts.measure("CONSOLE MODE");
+        }
+
+        // Restore the camera of a newly switched buffer
+        buffers.sync_camera(&mut ec);
+
+        // Take in the background symbol index's progress
+        efs.symbols.update(&efs.current_dir);
+
+        // Keep the buffer's language server in step, and act on its answers
+        let current_path = efs.current_file_path();
+
+        for event in efs.lsp.update(ops.lsp, current_path.as_deref(), &file_text, efs.unsaved_changes) {
+            apply_lsp_event(event, &mut efs, &mut file_text, &mut file_cursor, &mut elk, &mut history, &mut buffers, &mut console, &mut completion);
+        }
+
+        // Build and language server diagnostics, the worst of each line
+        let current_path = efs.current_file_path();
+        let mut underlines = efs.quickfix.underlines(current_path.as_deref());
+        underlines.extend(efs.lsp.underlines(current_path.as_deref()));
+        underlines.sort();
+        underlines.dedup_by_key(|(line, _)| *line);
+
+        // Diff the buffer with HEAD for the gutter, only if it changed
+        efs.changes.update(current_path, &file_text);
+
+        // Open panels cover the text, the cursor is kept in what is left of it
+        let (pty_w, pty_h) = efs.pty.occupied();
+        ec.resize(pty_w, efs.terminal.occupied_height().max(pty_h));
+
+        draw_file_text(&mut file_text, &mut file_cursor, &mut file_gts, &console, &mut ec, &elk, &search, &mut lex_cache, &ops, &efs.changes, &underlines);
+
+        // Stream the background command's output into its panel
+        efs.terminal.update();
+
+        // Parse a finished build's diagnostics
+        if let Some(message) = efs.quickfix.update(&efs.terminal) {
+            console.message = message;
+            console.showing_message = true;
+        }
+        efs.terminal.record_mouse();
+        efs.terminal.draw(&mut console_gts);
+
+        // Interactive shell, F12 moves the keyboard to/from it
+        if is_key_pressed(KeyCode::F12) {
+            let dir = efs.current_dir.clone().unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+
+            if let Err(e) = efs.pty.toggle_focus(&dir) {
+                console.message = format!("{} <:pty>", e);
+                console.showing_message = true;
+            }
+        }
+
+        if efs.pty.focused {
+            console.mode = false;
+        }
+
+        efs.pty.update();
+        efs.pty.record_mouse();
+        efs.pty.draw(&mut console_gts);
 
         if !console.mode {
-            record_keyboard_to_file_text(&mut file_cursor, &mut file_text, &audio, &mut console,  &mut file_gts, &mut efs, &mut ops, &mut elk);
+            if efs.pty.focused {
+                efs.pty.record_keyboard(&mut file_cursor);
+            } else {
+                record_keyboard_to_file_text(&mut file_cursor, &mut file_text, &audio, &mut console,  &mut file_gts, &mut efs, &mut ops, &mut elk, &mut history, &mut buffers, &mut search, &mut completion);
+
+                // Ask the language server to complete the identifier being typed, once per identifier
+                if let Some(start) = completion.take_server_request() && let Some(file) = efs.current_file_path() {
+                    let _ = efs.lsp.completion(&file, &file_text, start, file_cursor.xy);
+                }
+            }
+
+            completion.draw(&file_cursor, &ec, &mut file_gts);
 
             let mut fname = path_buffer_file_to_string(&efs.current_file);
             if efs.unsaved_changes {
                 fname = format!("*{}", path_buffer_file_to_string(&efs.current_file));
             }
 
-            console_gts.color = COMPOSITE_TYPE_COLOR;
+            console_gts.color = pallete().composite_type;
             
             if file_cursor.select_mode {
                 console_gts.draw("SELECTION MODE", MODE_Y_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
-                console_gts.color = FOLDER_COLOR;
+                console_gts.color = pallete().folder;
                 console_gts.draw(&path_buffer_to_string(&efs.current_dir), select_word_w + 25.0, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
-                console_gts.color = FILE_COLOR;
+                console_gts.color = pallete().file;
                 console_gts.draw(&fname, select_word_w + CURRENT_FILE_TOP_BAR_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN + 15.0);
-                console_gts.color = BLUE;
+                console_gts.color = pallete().cursor_word;
                 console_gts.draw(&file_cursor.word, select_word_w + CURRENT_FILE_TOP_BAR_OFFSET + CURSOR_WORD_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN + 15.0);
             } else {
                 console_gts.draw("INSERT MODE", MODE_Y_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
-                console_gts.color = FOLDER_COLOR;
+                console_gts.color = pallete().folder;
                 console_gts.draw(&path_buffer_to_string(&efs.current_dir), insert_word_w + 25.0, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
-                console_gts.color = FILE_COLOR;
+                console_gts.color = pallete().file;
                 console_gts.draw(&fname, insert_word_w + CURRENT_FILE_TOP_BAR_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN + 15.0);
-                console_gts.color = BLUE;
+                console_gts.color = pallete().cursor_word;
                 console_gts.draw(&file_cursor.word, insert_word_w + CURRENT_FILE_TOP_BAR_OFFSET + CURSOR_WORD_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN + 15.0);
             }
-            
-                
+
+            // Open buffers, next to the directory
+            if buffers.buffers.len() > 1 {
+                let mode_w = if file_cursor.select_mode { select_word_w } else { insert_word_w };
+                let dir_w = console_gts.measure(&path_buffer_to_string(&efs.current_dir));
+                buffers.draw_tabs(&efs, &mut console_gts, mode_w + 25.0 + dir_w + BUFFER_TAB_SPACING * 2.0, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
+            }
         } else {
-            console.record_keyboard_to_console_text(&audio, &mut efs, &mut file_text, &mut file_cursor, &mut ops, &mut elk);
+            console.record_keyboard_to_console_text(&audio, &mut efs, &mut file_text, &mut file_cursor, &mut ops, &mut elk, &mut history, &mut buffers, &mut search);
             
             let mut fname = path_buffer_file_to_string(&efs.current_file);
             if efs.unsaved_changes {
                 fname = format!("*{}", path_buffer_file_to_string(&efs.current_file));
             }
             
-            console_gts.color = FILE_COLOR;
+            console_gts.color = pallete().file;
             console_gts.draw(&fname, console_word_w + CURRENT_FILE_TOP_BAR_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
-            console_gts.color = COMPOSITE_TYPE_COLOR;
+            console_gts.color = pallete().composite_type;
             console_gts.draw("CONSOLE MODE", MODE_Y_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
-            console_gts.color = FOLDER_COLOR;
+            console_gts.color = pallete().folder;
             console_gts.draw(&path_buffer_to_string(&efs.current_dir), MODE_Y_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN + 15.0);
+
+            // Open buffers, next to the directory
+            if buffers.buffers.len() > 1 {
+                let dir_w = console_gts.measure(&path_buffer_to_string(&efs.current_dir));
+                buffers.draw_tabs(&efs, &mut console_gts, MODE_Y_OFFSET + dir_w + BUFFER_TAB_SPACING * 2.0, MODE_FONT_SIZE + MODE_Y_MARGIN + 15.0);
+            }
         }
 
+        // Running command, at the right of the top bar
+        efs.terminal.draw_status(&mut console_gts, MODE_FONT_SIZE + MODE_Y_MARGIN - 15.0);
+
         if console.mode {
-            console_gts.color = PUNCTUATION_COLOR;
+            console_gts.color = pallete().punctuation;
             console.draw(&console_gts);
+
+            // Incremental search, highlight matches as the query is typed
+            if let Some((flags, query)) = parse_search_directive(&console.directive) {
+                search.set_query(query, flags, &file_text);
+
+                let counter = if search.error { "InvalidRegex".to_string() } else { search.counter() };
+                let counter_w = measure_text(&counter, None, 24, 1.0).width;
+                draw_text(&counter, screen_width() - counter_w - CONSOLE_MARGINS, CONSOLE_MARGINS + 50.0, 24.0, pallete().selected_file);
+            }
         
-            let is_cd = console.directive.starts_with(":cd ");
-            let auto = draw_dir_contents(
-                &efs.current_file,
-                &efs.current_dir,
-                &console.directive,
-                &console,
-                is_cd
-            );
+            // Fuzzy find files in the whole tree for switch-to-file directives
+            let finder_query = if console.directive.starts_with(':') { "" } else { console.directive.as_str() };
+            console.finder.update(&efs.current_dir, finder_query, &buffers.recent);
+
+            // List results replace the directory contents until something is typed
+            if console.list.active && console.directive.is_empty() {
+                console.list.draw(console.width);
+            } else if console.directive.to_lowercase().starts_with(":epa ") {
+                if let Some(completed) = draw_name_completions(&list_palletes(), ":epa ", &console.directive, console.width) {
+                    console.directive = completed;
+                    console.cursor.x = console.directive.chars().count();
+                }
+            } else if console.directive.to_lowercase().starts_with(":efn ") {
+                if let Some(completed) = draw_name_completions(&list_fonts(), ":efn ", &console.directive, console.width) {
+                    console.directive = completed;
+                    console.cursor.x = console.directive.chars().count();
+                }
+            } else if console.directive.to_lowercase().starts_with(":ela ") {
+                if let Some(completed) = draw_name_completions(&list_language_names(), ":ela ", &console.directive, console.width) {
+                    console.directive = completed;
+                    console.cursor.x = console.directive.chars().count();
+                }
+            } else if console.finder.candidates.active {
+                // TAB completes the selected candidate
+                console.finder.candidates.draw(console.width);
+
+                if is_key_pressed(KeyCode::Tab) && let Some(selected) = console.finder.selected() {
+                    console.directive = selected.to_string();
+                    console.cursor.x = console.directive.chars().count();
+                }
+            } else {
+                let is_cd = console.directive.starts_with(":cd ");
+                let auto = draw_dir_contents(
+                    &efs.current_file,
+                    &efs.current_dir,
+                    &console.directive,
+                    &console,
+                    is_cd
+                );
         
-            if auto != "" {
-                if is_cd {
-                    console.directive = format!(":cd {}", auto);
-                } else {
-                    console.directive = auto;
+                if auto != "" {
+                    if is_cd {
+                        console.directive = format!(":cd {}", auto);
+                    } else {
+                        console.directive = auto;
+                    }
+                    console.cursor.x = console.directive.len();
                 }
-                console.cursor.x = console.directive.len();
             }
         }
 
@@ -150,15 +342,28 @@ async fn main() {
         }
 
         // Nullify message
-        if is_key_pressed(KeyCode::Escape) {
+        if is_key_pressed(KeyCode::Escape) && !efs.pty.focused {
             console.showing_message = false;
             console.showing_manual = false;
             console.message.clear();
+            console.list.close();
+            search.clear();
         }
 
         // Update resize animation
         console.animate_width();
 
+        // Font size and console width are changed by shortcuts, not directives
+        if ops.font_size != file_gts.font_size || ops.console_width != console.target_w {
+            ops.font_size = file_gts.font_size;
+            ops.console_width = console.target_w;
+
+            if ops.save().is_err() {
+                console.message = "PrefsNotSaved <user.pref>".to_string();
+                console.showing_message = true;
+            }
+        }
+
         muse_next_frame().await;
     }
 }
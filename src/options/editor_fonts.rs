@@ -0,0 +1,190 @@
+// Editor fonts
+//
+// Fonts are found by name, the file name without its extension,
+// in order of precedence:
+//      assets/font             : Fonts shipped with the editor
+//      fonts                   : User fonts, next to user.pref
+//      ~/.local/share/fonts, ~/.fonts, /usr/local/share/fonts, /usr/share/fonts
+// The list is scanned once and rescanned by <:efn> without a name,
+// or when a name isn't found.
+//
+// Glyphs missing from the editor font are drawn with the first font
+// of the fallback chain that has them, set as font_fallback in user.pref.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use macroquad::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::options::editor_paths::editor_data_file;
+
+pub const DEFAULT_FONT: &str = "UbuntuMono-R";
+pub const ASSET_FONTS_DIR: &str = "assets/font";
+pub const USER_FONTS_DIR: &str = "fonts";
+/// Collections (.ttc) load their first font
+pub const FONT_EXTENSIONS: [&str; 3] = ["ttf", "otf", "ttc"];
+
+/// Used when user.pref has no font_fallback, fonts that aren't installed are skipped
+pub const DEFAULT_FONT_FALLBACK: [&str; 4] = ["DejaVuSansMono", "DejaVuSans", "NotoSansCJK-Regular", "NotoSansSymbols2-Regular"];
+
+/// System font directories can nest deeply, e.g. /usr/share/fonts/truetype/<family>/
+pub const FONT_SCAN_DEPTH: usize = 4;
+
+/// Font names and their files, None until the first scan
+type EditorFontList = Option<Vec<(String, PathBuf)>>;
+
+static FONTS: Lazy<RwLock<EditorFontList>> = Lazy::new(|| RwLock::new(None));
+
+/// A loaded font, its outlines are kept to tell which glyphs it has
+#[derive(Clone)]
+pub struct EditorFont {
+    pub font: Font,
+    glyphs: Arc<fontdue::Font>,
+}
+
+impl EditorFont {
+    pub fn from_bytes(
+        bytes: &[u8]
+    ) -> Result<EditorFont, String> {
+        let font = load_ttf_font_from_bytes(bytes).map_err(|_| "InvalidFont".to_string())?;
+        let glyphs = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|_| "InvalidFont".to_string())?;
+
+        Ok(EditorFont { font, glyphs: Arc::new(glyphs) })
+    }
+
+    /// Whether the font has a glyph for `c`, rather than its 'missing' box
+    pub fn has_glyph(
+        &self,
+        c: char
+    ) -> bool {
+        self.glyphs.lookup_glyph_index(c) != 0
+    }
+}
+
+/// Directories searched for fonts, in order of precedence
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![
+        editor_data_file(ASSET_FONTS_DIR),
+        editor_data_file(USER_FONTS_DIR),
+    ];
+
+    if let Some(home) = std::env::var_os("HOME").map(PathBuf::from) {
+        dirs.push(home.join(".local/share/fonts"));
+        dirs.push(home.join(".fonts"));
+    }
+
+    dirs.push(PathBuf::from("/usr/local/share/fonts"));
+    dirs.push(PathBuf::from("/usr/share/fonts"));
+
+    dirs
+}
+
+/// Collect the font files of a directory and its subdirectories
+fn scan_dir(
+    dir: &Path,
+    depth: usize,
+    fonts: &mut Vec<(String, PathBuf)>
+) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            if depth > 0 {
+                scan_dir(&path, depth - 1, fonts);
+            }
+
+            continue;
+        }
+
+        let is_font = path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| FONT_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+
+        if !is_font {
+            continue;
+        }
+
+        let Some(name) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+            continue;
+        };
+
+        // The first directory with a font of that name wins
+        if !fonts.iter().any(|(n, _)| *n == name) {
+            fonts.push((name, path));
+        }
+    }
+}
+
+/// Rescan the font directories
+pub fn refresh_fonts() {
+    let mut fonts = Vec::new();
+
+    for dir in font_dirs() {
+        scan_dir(&dir, FONT_SCAN_DEPTH, &mut fonts);
+    }
+
+    *FONTS.write().unwrap_or_else(|e| e.into_inner()) = Some(fonts);
+}
+
+/// Names of all found fonts, scanning on first use
+pub fn list_fonts() -> Vec<String> {
+    if FONTS.read().unwrap_or_else(|e| e.into_inner()).is_none() {
+        refresh_fonts();
+    }
+
+    FONTS.read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .flatten()
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Path of a font by name, rescanning if it isn't known yet
+fn find_font(
+    name: &str
+) -> Option<PathBuf> {
+    let lookup = || {
+        FONTS.read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .flatten()
+            .find(|(n, _)| n == name)
+            .map(|(_, path)| path.clone())
+    };
+
+    lookup().or_else(|| {
+        refresh_fonts();
+        lookup()
+    })
+}
+
+/// Load a font by name, an empty name is the default font,
+/// returns the error name if it is missing or not a valid font
+pub fn load_font(
+    name: &str
+) -> Result<EditorFont, String> {
+    let name = if name.is_empty() { DEFAULT_FONT } else { name };
+
+    let path = find_font(name).ok_or("FontNotFound".to_string())?;
+    let bytes = fs::read(&path).map_err(|_| "FontNotReadable".to_string())?;
+
+    EditorFont::from_bytes(&bytes)
+}
+
+/// Load the fallback chain in order, skipping fonts that can't be loaded
+pub fn load_fallback_fonts(
+    names: &[String]
+) -> Vec<EditorFont> {
+    names.iter()
+        .filter_map(|name| load_font(name).ok())
+        .collect()
+}
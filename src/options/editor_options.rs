@@ -1,14 +1,55 @@
 // Editor options structure and constructor
+//
+// Options are saved in user.pref, one <key> = <value> per line,
+// lines starting with '#' are comments, values may contain '#':
+//      palette = <name>
+//      font = <name>
+//      font_fallback = <name>, <name>, ...
+//      audio = true|false
+//      smart = true|false
+//      fullscreen = true|false
+//      highlight = true|false
+//      highlight_off = <class>, <class>, ...
+//      font_size = <N>
+//      console_width = <N>
+//      build = <command>, empty picks one by the project's type
+//      lsp = true|false
+
+use std::fs;
 
 use macroquad::prelude::*;
 
+use crate::console::editor_console::CONSOLE_INITIAL_WIDTH;
+use crate::options::editor_fonts::DEFAULT_FONT_FALLBACK;
+use crate::options::editor_paths::editor_data_file;
+
+pub const PREF_FILE: &str = "user.pref";
+
+pub const DEFAULT_FONT_SIZE: u16 = 18;
+pub const MIN_FONT_SIZE: u16 = 12;
+pub const MAX_FONT_SIZE: u16 = 45;
+
+/// Token classes whose highlighting can be turned off on their own
+pub const HIGHLIGHT_CLASSES: [&str; 6] = ["comments", "strings", "keywords", "punctuation", "numbers", "macros"];
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct EditorOptions {
       pub pallete: String,
       pub font: String,
+      /// Fonts tried in order for glyphs the font lacks
+      pub font_fallback: Vec<String>,
       pub audio: bool,
       pub smart: bool,
       pub fullscreen: bool,
       pub highlight: bool,
+      /// Token classes drawn without highlighting, from HIGHLIGHT_CLASSES
+      pub highlight_off: Vec<String>,
+      pub font_size: u16,
+      pub console_width: f32,
+      /// Command of <:mk>, empty picks one by the project's type
+      pub build: String,
+      /// Language servers, see editor_lsp.rs
+      pub lsp: bool,
 }
 
 impl EditorOptions {
@@ -17,41 +58,255 @@ impl EditorOptions {
             EditorOptions {
                   pallete: "".to_string(),
                   font: "".to_string(),
+                  font_fallback: DEFAULT_FONT_FALLBACK.iter().map(|f| f.to_string()).collect(),
                   audio: true,
                   smart: true,
                   fullscreen: true,
                   highlight: true,
+                  highlight_off: vec![],
+                  font_size: DEFAULT_FONT_SIZE,
+                  console_width: CONSOLE_INITIAL_WIDTH,
+                  build: "".to_string(),
+                  lsp: true,
             }
       }
-      
+
+      /// Load the options saved in user.pref, a missing file keeps the defaults,
+      /// returns an error message for every line that couldn't be applied
+      pub fn load(
+          &mut self
+      ) -> Vec<String> {
+          let content = match fs::read_to_string(editor_data_file(PREF_FILE)) {
+              Ok(content) => content,
+              Err(e) if e.kind() == std::io::ErrorKind::NotFound => return vec![],
+              Err(_) => return vec![format!("PrefsNotReadable <{}>", PREF_FILE)],
+          };
+
+          self.apply(&content)
+      }
+
+      /// Apply every <key> = <value> line of a user.pref, returns the errors of load
+      fn apply(
+          &mut self,
+          content: &str
+      ) -> Vec<String> {
+          let mut errors = Vec::new();
+
+          for (i, line) in content.lines().enumerate() {
+              let line = line.trim();
+
+              if line.is_empty() || line.starts_with('#') {
+                  continue;
+              }
+
+              let Some((key, value)) = line.split_once('=') else {
+                  errors.push(format!("MalformedPref, expected <key> = <value> at line {} <{}>", i + 1, PREF_FILE));
+                  continue;
+              };
+
+              if let Err(e) = self.set(key.trim(), value.trim()) {
+                  errors.push(format!("{} at line {} <{}>", e, i + 1, PREF_FILE));
+              }
+          }
+
+          errors
+      }
+
+      /// Apply a single option, returns the error name if the key or value is invalid
+      fn set(
+          &mut self,
+          key: &str,
+          value: &str
+      ) -> Result<(), String> {
+          let parse_bool = |value: &str| match value.to_lowercase().as_str() {
+              "true" | "on" | "1" => Ok(true),
+              "false" | "off" | "0" => Ok(false),
+              _ => Err(format!("InvalidPrefValue '{}' for '{}'", value, key)),
+          };
+
+          match key {
+              "palette" => self.pallete = value.to_string(),
+              "font" => self.font = value.to_string(),
+
+              "font_fallback" => {
+                  self.font_fallback = value.split(',')
+                      .map(|name| name.trim().to_string())
+                      .filter(|name| !name.is_empty())
+                      .collect();
+              }
+
+              "audio" => self.audio = parse_bool(value)?,
+              "smart" => self.smart = parse_bool(value)?,
+              "fullscreen" => self.fullscreen = parse_bool(value)?,
+              "highlight" => self.highlight = parse_bool(value)?,
+
+              "highlight_off" => {
+                  let classes: Vec<String> = value.split(',')
+                      .map(|class| class.trim().to_lowercase())
+                      .filter(|class| !class.is_empty())
+                      .collect();
+
+                  if let Some(class) = classes.iter().find(|c| !HIGHLIGHT_CLASSES.contains(&c.as_str())) {
+                      return Err(format!("InvalidPrefValue '{}' for '{}', expected {}", class, key, HIGHLIGHT_CLASSES.join(", ")));
+                  }
+
+                  self.highlight_off = classes;
+              }
+
+              "font_size" => {
+                  self.font_size = value.parse::<u16>()
+                      .ok()
+                      .filter(|size| (MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(size))
+                      .ok_or(format!("InvalidPrefValue '{}' for '{}', expected {} to {}", value, key, MIN_FONT_SIZE, MAX_FONT_SIZE))?;
+              }
+
+              "console_width" => {
+                  self.console_width = value.parse::<f32>()
+                      .ok()
+                      .filter(|width| width.is_finite() && *width > 0.0)
+                      .ok_or(format!("InvalidPrefValue '{}' for '{}'", value, key))?;
+              }
+
+              "build" => self.build = value.to_string(),
+              "lsp" => self.lsp = parse_bool(value)?,
+
+              _ => return Err(format!("UnknownPrefKey '{}'", key)),
+          }
+
+          Ok(())
+      }
+
+      /// Write the options to user.pref
+      pub fn save(
+          &self
+      ) -> std::io::Result<()> {
+          let content = format!(
+              "palette = {}\nfont = {}\nfont_fallback = {}\naudio = {}\nsmart = {}\nfullscreen = {}\nhighlight = {}\nhighlight_off = {}\nfont_size = {}\nconsole_width = {}\nbuild = {}\nlsp = {}\n",
+              self.pallete,
+              self.font,
+              self.font_fallback.join(", "),
+              self.audio,
+              self.smart,
+              self.fullscreen,
+              self.highlight,
+              self.highlight_off.join(", "),
+              self.font_size,
+              self.console_width,
+              self.build,
+              self.lsp,
+          );
+
+          fs::write(editor_data_file(PREF_FILE), content)
+      }
+
       /// Toggle typing sounds on and off
       pub fn toggle_audio(
           &mut self
       ) {
-          self.audio = !self.smart;
+          self.audio = !self.audio;
+      }
+
+      /// Toggle language servers on and off
+      pub fn toggle_lsp(
+          &mut self
+      ) {
+          self.lsp = !self.lsp;
       }
-      
+
       /// Toggle on and off smart identation
       pub fn toggle_smart(
           &mut self
       ) {
           self.smart = !self.smart;
       }
-     
+
       /// Toggle on and off fullscreen
       pub fn toggle_fullscreen(
         &mut self
       ) {
          self.fullscreen = !self.fullscreen;
-         
+
          // Actual toggle.
          set_fullscreen(self.fullscreen);
       }
-      
+
       /// Toggle on and off highlighting
       pub fn toggle_highlight(
           &mut self
       ) {
           self.highlight = !self.highlight;
       }
+
+      /// Toggle on and off highlighting of one token class,
+      /// returns false if it isn't one of HIGHLIGHT_CLASSES
+      pub fn toggle_highlight_class(
+          &mut self,
+          class: &str
+      ) -> bool {
+          let class = class.to_lowercase();
+
+          if !HIGHLIGHT_CLASSES.contains(&class.as_str()) {
+              return false;
+          }
+
+          if self.highlight_off.contains(&class) {
+              self.highlight_off.retain(|c| *c != class);
+          } else {
+              self.highlight_off.push(class);
+          }
+
+          true
+      }
+
+      /// Whether a token class is drawn highlighted
+      pub fn is_highlighted(
+          &self,
+          class: &str
+      ) -> bool {
+          self.highlight && !self.highlight_off.iter().any(|c| c == class)
+      }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_only_start_comments_at_the_line_start() {
+        let mut options = EditorOptions::new();
+        let errors = options.apply("# comment\n  # indented comment\npalette = night #2 # dim\naudio = off\n");
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(options.pallete, "night #2 # dim");
+        assert!(!options.audio);
+    }
+
+    #[test]
+    fn saved_options_load_back() {
+        let mut saved = EditorOptions::new();
+        saved.pallete = "night #2".to_string();
+        saved.fullscreen = false;
+        saved.font_size = MAX_FONT_SIZE;
+
+        let content = format!(
+            "palette = {}\nfullscreen = {}\nfont_size = {}\n",
+            saved.pallete, saved.fullscreen, saved.font_size
+        );
+        let mut loaded = EditorOptions::new();
+
+        assert!(loaded.apply(&content).is_empty());
+        assert_eq!(loaded, saved);
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        let mut options = EditorOptions::new();
+        let errors = options.apply("font_size\nfont_size = 3\ncolour = red\n");
+
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("MalformedPref"));
+        assert!(errors[1].starts_with("InvalidPrefValue"));
+        assert!(errors[2].starts_with("UnknownPrefKey"));
+        assert_eq!(options.font_size, DEFAULT_FONT_SIZE);
+    }
 }
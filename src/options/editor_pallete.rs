@@ -1,26 +1,367 @@
+// Editor palletes
+//
+// Every colour the editor draws with is a role of the live pallete,
+// read through pallete() and switched with set_pallete() by <:epa>.
+// Built-in palletes are listed in BUILTIN_PALLETES, user palletes are
+// files in the themes directory, themes/<name>.pal, one role per line:
+//      <role> = #RRGGBB or #RRGGBBAA
+// '#' after a value starts a comment, roles left out keep the muse colours.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
 use macroquad::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::options::editor_paths::editor_data_file;
+
+pub const THEMES_DIR: &str = "themes";
+pub const THEME_EXTENSION: &str = "pal";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EditorPallete {
+    // Console
+    pub console_container: Color,
+    pub console_cursor: Color,
+    pub console_text: Color,
+    pub console_frame: Color,
+    pub selected_file: Color,
+    pub folder: Color,
+    pub file: Color,
+
+    // Text editor
+    pub background: Color,
+    pub composite_type: Color,
+    pub storage_class: Color,
+    pub misc: Color,
+    pub type_qualifier: Color,
+    pub control_flow: Color,
+    pub punctuation: Color,
+    pub data_type: Color,
+    pub number_literal: Color,
+    pub string_literal: Color,
+    pub cursor: Color,
+    pub cursor_line: Color,
+    pub cursor_word: Color,
+    pub macros: Color,
+    pub comment: Color,
+    pub identifier: Color,
+    pub mark: Color,
+    pub selection: Color,
+    pub search_match: Color,
+    pub search_current: Color,
+    pub git_added: Color,
+    pub git_modified: Color,
+    pub git_deleted: Color,
+    pub diagnostic_error: Color,
+    pub diagnostic_warning: Color,
+}
+
+pub const MUSE_PALLETE: EditorPallete = EditorPallete {
+    console_container: Color::from_hex(0x1B0B2A),  // darker purple background
+    console_cursor: Color::from_hex(0xFFFFFF),     // keep white for visibility
+    console_text: Color::from_hex(0xFFB6F1),       // brighter pink
+    console_frame: Color::from_hex(0xFF00FF),      // full magenta for contrast
+    selected_file: Color::from_hex(0xFFD700),      // brighter yellow-gold
+    folder: Color::from_hex(0x00FFFF),             // cyan, more vibrant
+    file: Color::from_hex(0xFF6F00),               // bright orange
+
+    background: Color::from_hex(0x200A30),         // slightly richer purple
+    composite_type: Color::from_hex(0xFF00FF),     // vivid magenta
+    storage_class: Color::from_hex(0xFF3399),      // hot pink
+    misc: Color::from_hex(0xFFFFAA),               // soft yellow, higher contrast
+    type_qualifier: Color::from_hex(0x00FFFF),     // neon cyan
+    control_flow: Color::from_hex(0xFF3399),       // same hot pink as storage class
+    punctuation: Color::from_hex(0xFFFF00),        // bright yellow
+    data_type: Color::from_hex(0xFF6F00),          // bright orange
+    number_literal: Color::from_hex(0x00CCFF),     // more neon blue
+    string_literal: Color::from_hex(0x00FFFF),
+    cursor: Color::from_hex(0xFFFFFF),             // white
+    cursor_line: Color::new(1.0, 1.0, 1.0, 0.05),  // barely visible white
+    cursor_word: Color::new(0.0, 0.47, 0.95, 1.0), // blue
+    macros: Color::from_hex(0xFF66FF),             // neon pink
+    comment: Color::from_hex(0x00FF66),            // bright lime green
+    identifier: Color::from_hex(0xFF33CE),         // vibrant pink-purple
+    mark: Color::from_hex(0xFFD700),               // same yellow-gold as the selected file
+    selection: Color::new(0.0, 1.0, 1.0, 0.25),    // translucent cyan
+    search_match: Color::new(1.0, 1.0, 0.0, 0.2),  // translucent yellow
+    search_current: Color::new(1.0, 0.84, 0.0, 0.5), // stronger yellow-gold
+    git_added: Color::from_hex(0x00FF66),          // same lime green as comments
+    git_modified: Color::from_hex(0x00CCFF),       // neon blue
+    git_deleted: Color::from_hex(0xFF3399),        // hot pink
+    diagnostic_error: Color::from_hex(0xFF3355),   // neon red
+    diagnostic_warning: Color::from_hex(0xFFCC00), // amber
+};
+
+pub const LIGHT_PALLETE: EditorPallete = EditorPallete {
+    console_container: Color::from_hex(0xEDE7F6),
+    console_cursor: Color::from_hex(0x000000),
+    console_text: Color::from_hex(0x4A148C),
+    console_frame: Color::from_hex(0x8E24AA),
+    selected_file: Color::from_hex(0xC62828),
+    folder: Color::from_hex(0x00838F),
+    file: Color::from_hex(0xE65100),
+
+    background: Color::from_hex(0xFAFAFA),
+    composite_type: Color::from_hex(0x8E24AA),
+    storage_class: Color::from_hex(0xAD1457),
+    misc: Color::from_hex(0x6D4C41),
+    type_qualifier: Color::from_hex(0x00838F),
+    control_flow: Color::from_hex(0xAD1457),
+    punctuation: Color::from_hex(0x37474F),
+    data_type: Color::from_hex(0xE65100),
+    number_literal: Color::from_hex(0x1565C0),
+    string_literal: Color::from_hex(0x2E7D32),
+    cursor: Color::from_hex(0x000000),
+    cursor_line: Color::new(0.0, 0.0, 0.0, 0.06),
+    cursor_word: Color::from_hex(0x1565C0),
+    macros: Color::from_hex(0x6A1B9A),
+    comment: Color::from_hex(0x757575),
+    identifier: Color::from_hex(0x212121),
+    mark: Color::from_hex(0xC62828),
+    selection: Color::new(0.1, 0.4, 0.9, 0.25),
+    search_match: Color::new(1.0, 0.8, 0.0, 0.35),
+    search_current: Color::new(1.0, 0.5, 0.0, 0.5),
+    git_added: Color::from_hex(0x2E7D32),
+    git_modified: Color::from_hex(0x1565C0),
+    git_deleted: Color::from_hex(0xC62828),
+    diagnostic_error: Color::from_hex(0xD32F2F),
+    diagnostic_warning: Color::from_hex(0xF9A825),
+};
+
+pub const HIGH_CONTRAST_PALLETE: EditorPallete = EditorPallete {
+    console_container: Color::from_hex(0x000000),
+    console_cursor: Color::from_hex(0xFFFF00),
+    console_text: Color::from_hex(0xFFFFFF),
+    console_frame: Color::from_hex(0xFFFFFF),
+    selected_file: Color::from_hex(0xFFFF00),
+    folder: Color::from_hex(0x00FFFF),
+    file: Color::from_hex(0xFFFFFF),
+
+    background: Color::from_hex(0x000000),
+    composite_type: Color::from_hex(0x00FF00),
+    storage_class: Color::from_hex(0xFF80FF),
+    misc: Color::from_hex(0xFFFF80),
+    type_qualifier: Color::from_hex(0x00FFFF),
+    control_flow: Color::from_hex(0xFFFF00),
+    punctuation: Color::from_hex(0xFFFFFF),
+    data_type: Color::from_hex(0x00FF00),
+    number_literal: Color::from_hex(0x80C0FF),
+    string_literal: Color::from_hex(0x00FFFF),
+    cursor: Color::from_hex(0xFFFF00),
+    cursor_line: Color::new(1.0, 1.0, 1.0, 0.12),
+    cursor_word: Color::from_hex(0x00FFFF),
+    macros: Color::from_hex(0xFF80FF),
+    comment: Color::from_hex(0xC0C0C0),
+    identifier: Color::from_hex(0xFFFFFF),
+    mark: Color::from_hex(0xFFFF00),
+    selection: Color::new(0.0, 0.5, 1.0, 0.45),
+    search_match: Color::new(1.0, 1.0, 0.0, 0.35),
+    search_current: Color::new(1.0, 0.5, 0.0, 0.6),
+    git_added: Color::from_hex(0x00FF00),
+    git_modified: Color::from_hex(0x00FFFF),
+    git_deleted: Color::from_hex(0xFF0000),
+    diagnostic_error: Color::from_hex(0xFF0000),
+    diagnostic_warning: Color::from_hex(0xFFFF00),
+};
+
+pub const MIDNIGHT_PALLETE: EditorPallete = EditorPallete {
+    console_container: Color::from_hex(0x0B0E14),
+    console_cursor: Color::from_hex(0xE6E1CF),
+    console_text: Color::from_hex(0xB3B1AD),
+    console_frame: Color::from_hex(0x3D4F6B),
+    selected_file: Color::from_hex(0xFFB454),
+    folder: Color::from_hex(0x59C2FF),
+    file: Color::from_hex(0xE6B450),
+
+    background: Color::from_hex(0x0F1419),
+    composite_type: Color::from_hex(0x59C2FF),
+    storage_class: Color::from_hex(0xFF8F40),
+    misc: Color::from_hex(0xE6B673),
+    type_qualifier: Color::from_hex(0x95E6CB),
+    control_flow: Color::from_hex(0xFF8F40),
+    punctuation: Color::from_hex(0xB3B1AD),
+    data_type: Color::from_hex(0x39BAE6),
+    number_literal: Color::from_hex(0xF07178),
+    string_literal: Color::from_hex(0xC2D94C),
+    cursor: Color::from_hex(0xE6E1CF),
+    cursor_line: Color::new(1.0, 1.0, 1.0, 0.04),
+    cursor_word: Color::from_hex(0x59C2FF),
+    macros: Color::from_hex(0xD2A6FF),
+    comment: Color::from_hex(0x5C6773),
+    identifier: Color::from_hex(0xE6E1CF),
+    mark: Color::from_hex(0xFFB454),
+    selection: Color::new(0.35, 0.55, 0.8, 0.25),
+    search_match: Color::new(1.0, 0.8, 0.3, 0.2),
+    search_current: Color::new(1.0, 0.7, 0.3, 0.5),
+    git_added: Color::from_hex(0xC2D94C),
+    git_modified: Color::from_hex(0x59C2FF),
+    git_deleted: Color::from_hex(0xF07178),
+    diagnostic_error: Color::from_hex(0xFF3333),
+    diagnostic_warning: Color::from_hex(0xFFB454),
+};
+
+pub const DEFAULT_PALLETE: &str = "muse";
+
+pub const BUILTIN_PALLETES: [(&str, EditorPallete); 4] = [
+    ("muse", MUSE_PALLETE),
+    ("light", LIGHT_PALLETE),
+    ("high-contrast", HIGH_CONTRAST_PALLETE),
+    ("midnight", MIDNIGHT_PALLETE),
+];
+
+static PALLETE: Lazy<RwLock<EditorPallete>> = Lazy::new(|| RwLock::new(MUSE_PALLETE));
+
+/// The live pallete
+pub fn pallete() -> EditorPallete {
+    *PALLETE.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Switch the live pallete
+pub fn set_pallete(
+    pallete: EditorPallete
+) {
+    *PALLETE.write().unwrap_or_else(|e| e.into_inner()) = pallete;
+}
+
+impl EditorPallete {
+    /// The colour of a role by its name in a theme file
+    fn role_mut(
+        &mut self,
+        role: &str
+    ) -> Option<&mut Color> {
+        let color = match role {
+            "console_container" => &mut self.console_container,
+            "console_cursor" => &mut self.console_cursor,
+            "console_text" => &mut self.console_text,
+            "console_frame" => &mut self.console_frame,
+            "selected_file" => &mut self.selected_file,
+            "folder" => &mut self.folder,
+            "file" => &mut self.file,
+            "background" => &mut self.background,
+            "composite_type" => &mut self.composite_type,
+            "storage_class" => &mut self.storage_class,
+            "misc" => &mut self.misc,
+            "type_qualifier" => &mut self.type_qualifier,
+            "control_flow" => &mut self.control_flow,
+            "punctuation" => &mut self.punctuation,
+            "data_type" => &mut self.data_type,
+            "number_literal" => &mut self.number_literal,
+            "string_literal" => &mut self.string_literal,
+            "cursor" => &mut self.cursor,
+            "cursor_line" => &mut self.cursor_line,
+            "cursor_word" => &mut self.cursor_word,
+            "macros" => &mut self.macros,
+            "comment" => &mut self.comment,
+            "identifier" => &mut self.identifier,
+            "mark" => &mut self.mark,
+            "selection" => &mut self.selection,
+            "search_match" => &mut self.search_match,
+            "search_current" => &mut self.search_current,
+            "git_added" => &mut self.git_added,
+            "git_modified" => &mut self.git_modified,
+            "git_deleted" => &mut self.git_deleted,
+            "diagnostic_error" => &mut self.diagnostic_error,
+            "diagnostic_warning" => &mut self.diagnostic_warning,
+            _ => return None,
+        };
+
+        Some(color)
+    }
+}
+
+/// Directory of the user palletes
+fn themes_dir() -> PathBuf {
+    editor_data_file(THEMES_DIR)
+}
+
+/// Names of all palletes, built-in ones first
+pub fn list_palletes() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN_PALLETES.iter().map(|(name, _)| name.to_string()).collect();
+
+    let mut user: Vec<String> = fs::read_dir(themes_dir())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == THEME_EXTENSION))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .filter(|name| !names.contains(name))
+        .collect();
+
+    user.sort();
+    names.append(&mut user);
+
+    names
+}
+
+/// Find a pallete by name, user themes override built-in ones,
+/// returns the error name if it is missing or malformed
+pub fn load_pallete(
+    name: &str
+) -> Result<EditorPallete, String> {
+    let path = themes_dir().join(format!("{}.{}", name, THEME_EXTENSION));
+
+    if let Ok(content) = fs::read_to_string(&path) {
+        return parse_pallete(&content);
+    }
+
+    BUILTIN_PALLETES.iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, pallete)| *pallete)
+        .ok_or("PalleteNotFound".to_string())
+}
+
+/// Parse a theme file on top of the muse pallete
+fn parse_pallete(
+    content: &str
+) -> Result<EditorPallete, String> {
+    let mut pallete = MUSE_PALLETE;
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((role, value)) = line.split_once('=') else {
+            return Err(format!("MalformedPallete at line {}", i + 1));
+        };
+
+        let Some(color) = pallete.role_mut(role.trim()) else {
+            return Err(format!("UnknownPalleteRole '{}' at line {}", role.trim(), i + 1));
+        };
+
+        // The value's own '#' is kept, a later one starts a comment
+        let value = value.trim();
+        let value = match value.get(1..).and_then(|rest| rest.find('#')) {
+            Some(comment) => value[..comment + 1].trim(),
+            None => value,
+        };
+
+        *color = parse_color(value).ok_or(format!("InvalidColor '{}' at line {}", value, i + 1))?;
+    }
+
+    Ok(pallete)
+}
+
+/// Parse #RRGGBB or #RRGGBBAA, the '#' may also be written as 0x
+fn parse_color(
+    value: &str
+) -> Option<Color> {
+    let hex = value.strip_prefix('#').or_else(|| value.strip_prefix("0x"))?;
+    let rgba = u32::from_str_radix(hex, 16).ok()?;
 
-// Console
-pub const CONSOLE_CONTAINER_COLOR: Color    = Color::from_hex(0x1B0B2A); // darker purple background
-pub const CONSOLE_CURSOR_COLOR: Color       = Color::from_hex(0xFFFFFF); // keep white for visibility
-pub const CONSOLE_TEXT_COLOR: Color         = Color::from_hex(0xFFB6F1); // brighter pink
-pub const CONSOLE_FRAME_COLOR: Color        = Color::from_hex(0xFF00FF); // full magenta for contrast
-pub const SELECTED_FILE_COLOR: Color        = Color::from_hex(0xFFD700); // brighter yellow-gold
-pub const FOLDER_COLOR: Color               = Color::from_hex(0x00FFFF); // cyan, more vibrant
-pub const FILE_COLOR: Color                 = Color::from_hex(0xFF6F00); // bright orange
-
-// Text editor
-pub const BACKGROUND_COLOR: Color           = Color::from_hex(0x200A30); // slightly richer purple
-pub const COMPOSITE_TYPE_COLOR: Color       = Color::from_hex(0xFF00FF); // vivid magenta
-pub const STORAGE_CLASS_COLOR: Color        = Color::from_hex(0xFF3399); // hot pink
-pub const MISC_COLOR: Color                 = Color::from_hex(0xFFFFAA); // soft yellow, higher contrast
-pub const TYPE_QUALIFIER_COLOR: Color       = Color::from_hex(0x00FFFF); // neon cyan
-pub const CONTROL_FLOW_COLOR: Color         = Color::from_hex(0xFF3399); // same hot pink as storage class
-pub const PUNCTUATION_COLOR: Color          = Color::from_hex(0xFFFF00); // bright yellow
-pub const DATA_TYPE_COLOR: Color            = Color::from_hex(0xFF6F00); // bright orange
-pub const NUMBER_LITERAL_COLOR: Color       = Color::from_hex(0x00CCFF); // more neon blue
-pub const STRING_LITERAL_COLOR: Color       = Color::from_hex(0x00FFFF);
-pub const CURSOR_COLOR: Color               = Color::from_hex(0xFFFFFF); // white
-pub const MACRO_COLOR: Color                = Color::from_hex(0xFF66FF); // neon pink
-pub const COMMENT_COLOR: Color              = Color::from_hex(0x00FF66); // bright lime green
-pub const IDENTIFIER_COLOR: Color           = Color::from_hex(0xFF33CE); // vibrant pink-purple
+    match hex.len() {
+        6 => Some(Color::from_hex(rgba)),
+        8 => Some(Color::from_rgba(
+            (rgba >> 24) as u8,
+            (rgba >> 16) as u8,
+            (rgba >> 8) as u8,
+            rgba as u8,
+        )),
+        _ => None,
+    }
+}
@@ -0,0 +1,21 @@
+// Editor data paths
+//
+// The editor changes its working directory with :cd,
+// so files it owns are resolved against the directory
+// it was started from.
+
+use std::path::PathBuf;
+
+use once_cell::sync::Lazy;
+
+/// Directory the editor was started from, forced in main
+pub static EDITOR_HOME: Lazy<PathBuf> = Lazy::new(|| {
+    std::env::current_dir().unwrap_or_default()
+});
+
+/// Path of an editor owned file, e.g. user.pref
+pub fn editor_data_file(
+    name: &str
+) -> PathBuf {
+    EDITOR_HOME.join(name)
+}
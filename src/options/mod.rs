@@ -1,2 +1,4 @@
+pub mod editor_fonts;
 pub mod editor_options;
-pub mod editor_pallete;
\ No newline at end of file
+pub mod editor_pallete;
+pub mod editor_paths;
\ No newline at end of file
@@ -0,0 +1,377 @@
+// Identifier autocomplete module
+//
+// Typing an identifier in insert mode shows a popup under the cursor
+// with identifiers of the current buffer, the other open buffers and
+// the language's keywords that start with it, and the completions of
+// the buffer's language server once they arrive, see editor_lsp.rs.
+// Case matching prefixes rank first, then the server's completions,
+// then the most frequent identifiers. Up/Down select a
+// candidate, Tab or Enter accept it and Escape closes the popup.
+// The current buffer's identifiers are counted again only for the
+// lines that changed since the last keystroke.
+
+use std::collections::{HashMap, HashSet};
+
+use macroquad::prelude::*;
+
+use crate::audio::editor_audio::EditorAudio;
+use crate::camera::editor_camera::EditorCamera;
+use crate::console::editor_file_system::EditorFileSystem;
+use crate::options::editor_pallete::*;
+use crate::text::editor_buffer::EditorBuffers;
+use crate::text::editor_cursor::*;
+use crate::text::editor_history::{EditorEditKind, EditorHistory};
+use crate::text::editor_language_manager::{EditorLanguageKeywords, identifiers};
+use crate::text::editor_text_stylizer::EditorGeneralTextStylizer;
+
+pub const AUTOCOMPLETE_MIN_PREFIX: usize = 2;
+pub const AUTOCOMPLETE_MAX_CANDIDATES: usize = 8;
+pub const AUTOCOMPLETE_PADDING: f32 = 6.0;
+
+/// Identifier counts of a text, kept in step with it line by line
+pub struct EditorIdentifierIndex {
+    lines: Vec<String>,
+    counts: HashMap<String, usize>,
+}
+
+impl EditorIdentifierIndex {
+    pub fn new() -> EditorIdentifierIndex {
+        EditorIdentifierIndex {
+            lines: Vec::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Recount the lines between the unchanged start and end of the text
+    pub fn update(
+        &mut self,
+        text: &[String]
+    ) {
+        let head = self.lines.iter()
+            .zip(text)
+            .take_while(|(old, new)| old == new)
+            .count();
+
+        let tail = self.lines[head..].iter().rev()
+            .zip(text[head..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+
+        let removed = head..self.lines.len() - tail;
+        let added = head..text.len() - tail;
+
+        for line in &self.lines[removed.clone()] {
+            for word in identifiers(line) {
+                if let Some(count) = self.counts.get_mut(word) {
+                    *count -= 1;
+
+                    if *count == 0 {
+                        self.counts.remove(word);
+                    }
+                }
+            }
+        }
+
+        for line in &text[added.clone()] {
+            for word in identifiers(line) {
+                *self.counts.entry(word.to_string()).or_default() += 1;
+            }
+        }
+
+        self.lines.splice(removed, text[added].iter().cloned());
+    }
+
+    pub fn counts(&self) -> &HashMap<String, usize> {
+        &self.counts
+    }
+}
+
+pub struct EditorAutocomplete {
+    index: EditorIdentifierIndex,
+    /// Identifier counts of the stashed buffers, and the buffers generation they were taken at
+    others: HashMap<String, usize>,
+    others_key: Option<usize>,
+    prefix: String,
+    /// Where the identifier being completed starts, (column, line)
+    start: Option<(usize, usize)>,
+    /// Completions of the language server, for the identifier starting at `server_start`
+    server: Vec<String>,
+    server_start: Option<(usize, usize)>,
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    pub active: bool,
+}
+
+impl EditorAutocomplete {
+    pub fn new() -> EditorAutocomplete {
+        EditorAutocomplete {
+            index: EditorIdentifierIndex::new(),
+            others: HashMap::new(),
+            others_key: None,
+            prefix: String::new(),
+            start: None,
+            server: Vec::new(),
+            server_start: None,
+            candidates: Vec::new(),
+            selected: 0,
+            active: false,
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.active = false;
+        self.start = None;
+        self.candidates.clear();
+    }
+
+    /// The start of the identifier to ask the language server about, once per identifier
+    pub fn take_server_request(&mut self) -> Option<(usize, usize)> {
+        let start = self.start.filter(|start| self.server_start != Some(*start))?;
+
+        self.server_start = Some(start);
+        self.server.clear();
+
+        Some(start)
+    }
+
+    /// Take in the server's completions for the identifier at `start`,
+    /// returns true if it is still being completed
+    pub fn set_server(
+        &mut self,
+        start: (usize, usize),
+        words: Vec<String>
+    ) -> bool {
+        if self.server_start != Some(start) {
+            return false;
+        }
+
+        self.server = words;
+        self.start == Some(start)
+    }
+
+    /// Rank the candidates for the identifier before the cursor,
+    /// the popup closes if it is too short or nothing matches
+    pub fn update(
+        &mut self,
+        text: &[String],
+        cursor: &EditorCursor,
+        elk: &EditorLanguageKeywords,
+        buffers: &EditorBuffers
+    ) {
+        let prefix = text.get(cursor.xy.1).map_or(String::new(), |line| word_before(line, cursor.xy.0));
+
+        if prefix.chars().count() < AUTOCOMPLETE_MIN_PREFIX || prefix.starts_with(|c: char| c.is_ascii_digit()) {
+            self.close();
+            return;
+        }
+
+        let start = (cursor.xy.0 - prefix.chars().count(), cursor.xy.1);
+        self.start = Some(start);
+
+        self.index.update(text);
+
+        // Stashed buffers only change when the buffers do,
+        // the active buffer's text is the live one counted above
+        if self.others_key != Some(buffers.generation) {
+            self.others.clear();
+
+            for (i, buffer) in buffers.buffers.iter().enumerate() {
+                if i == buffers.active {
+                    continue;
+                }
+
+                for word in buffer.text.iter().flat_map(|line| identifiers(line)) {
+                    *self.others.entry(word.to_string()).or_default() += 1;
+                }
+            }
+
+            self.others_key = Some(buffers.generation);
+        }
+
+        let keywords = [
+            &elk.control_flow, &elk.storage_class, &elk.type_qualifiers,
+            &elk.composite_types, &elk.misc, &elk.data_types,
+        ];
+
+        let mut frequency: HashMap<&str, usize> = HashMap::new();
+
+        for word in keywords.into_iter().flatten() {
+            frequency.entry(word).or_default();
+        }
+
+        for (word, count) in self.index.counts().iter().chain(&self.others) {
+            *frequency.entry(word).or_default() += count;
+        }
+
+        // The server's completions are kept only for the identifier they were asked for
+        let server: HashSet<&str> = if self.server_start == Some(start) {
+            self.server.iter().map(String::as_str).collect()
+        } else {
+            HashSet::new()
+        };
+
+        for &word in &server {
+            frequency.entry(word).or_default();
+        }
+
+        let lower = prefix.to_lowercase();
+
+        let mut ranked: Vec<(bool, bool, usize, &str)> = frequency.into_iter()
+            .filter(|(word, _)| *word != prefix && word.to_lowercase().starts_with(&lower))
+            .map(|(word, count)| (word.starts_with(&prefix), server.contains(word), count, word))
+            .collect();
+
+        ranked.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(b.1.cmp(&a.1))
+                .then(b.2.cmp(&a.2))
+                .then(a.3.len().cmp(&b.3.len()))
+                .then(a.3.cmp(b.3))
+        });
+
+        let candidates: Vec<String> = ranked.into_iter()
+            .take(AUTOCOMPLETE_MAX_CANDIDATES)
+            .map(|(_, _, _, word)| word.to_string())
+            .collect();
+
+        // Keep the selection while the same candidates narrow down
+        let selected = self.candidates.get(self.selected)
+            .and_then(|word| candidates.iter().position(|c| c == word))
+            .unwrap_or(0);
+
+        self.prefix = prefix;
+        self.candidates = candidates;
+        self.selected = selected;
+        self.active = !self.candidates.is_empty();
+    }
+
+    /// Up/Down, Tab/Enter and Escape while the popup is shown,
+    /// returns true if a key was used
+    pub fn record_keys(
+        &mut self,
+        cursor: &mut EditorCursor,
+        text: &mut [String],
+        audio: &EditorAudio,
+        efs: &mut EditorFileSystem,
+        history: &mut EditorHistory
+    ) -> bool {
+        if !self.active {
+            return false;
+        }
+
+        if cursor.is_combo_active(KeyCode::Up, None) {
+            self.selected = self.selected.checked_sub(1).unwrap_or(self.candidates.len() - 1);
+            audio.play_nav();
+            return true;
+        }
+
+        if cursor.is_combo_active(KeyCode::Down, None) {
+            self.selected = (self.selected + 1) % self.candidates.len();
+            audio.play_nav();
+            return true;
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            self.close();
+            return true;
+        }
+
+        if is_key_pressed(KeyCode::Tab) || is_key_pressed(KeyCode::Enter) {
+            self.accept(cursor, text, efs, history);
+            audio.play_insert();
+            return true;
+        }
+
+        false
+    }
+
+    /// Replace the identifier before the cursor with the selected candidate
+    fn accept(
+        &mut self,
+        cursor: &mut EditorCursor,
+        text: &mut [String],
+        efs: &mut EditorFileSystem,
+        history: &mut EditorHistory
+    ) {
+        let Some(word) = self.candidates.get(self.selected).cloned() else {
+            return;
+        };
+
+        let Some(line) = text.get(cursor.xy.1) else {
+            return;
+        };
+
+        let start = cursor.xy.0 - self.prefix.chars().count();
+        let from = char_to_byte(line, start);
+        let to = char_to_byte(line, cursor.xy.0);
+
+        history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Insert);
+        text[cursor.xy.1].replace_range(from..to, &word);
+        cursor.xy.0 = start + word.chars().count();
+        history.end(text, cursor);
+        history.seal();
+
+        efs.unsaved_changes = true;
+        self.close();
+    }
+
+    /// Draw the popup under the cursor
+    pub fn draw(
+        &self,
+        cursor: &EditorCursor,
+        camera: &EditorCamera,
+        gts: &mut EditorGeneralTextStylizer
+    ) {
+        if !self.active {
+            return;
+        }
+
+        let pal = pallete();
+        let row_height = gts.font_size as f32 + AUTOCOMPLETE_PADDING;
+        let width = self.candidates.iter()
+            .map(|c| gts.measure(c))
+            .fold(0.0, f32::max) + AUTOCOMPLETE_PADDING * 2.0;
+        let height = row_height * self.candidates.len() as f32 + AUTOCOMPLETE_PADDING;
+
+        // Start under the identifier, kept on screen and off the panels
+        let prefix_width = gts.measure(&self.prefix);
+        let (sx, sy) = camera.world_to_screen(cursor.anim_x - prefix_width, cursor.anim_y);
+        let x = sx.min(camera.camera_w - width).max(0.0);
+        let y = if sy + CURSOR_HEIGHT + height > camera.camera_h {
+            sy - gts.font_size as f32 - height
+        } else {
+            sy + CURSOR_HEIGHT
+        };
+
+        draw_rectangle(x, y, width, height, pal.console_container);
+        draw_rectangle_lines(x, y, width, height, 1.0, pal.console_frame);
+
+        let previous_color = gts.color;
+
+        for (i, candidate) in self.candidates.iter().enumerate() {
+            let row_y = y + AUTOCOMPLETE_PADDING + row_height * i as f32;
+
+            if i == self.selected {
+                draw_rectangle(x, row_y, width, row_height, pal.selection);
+            }
+
+            gts.color = if i == self.selected { pal.selected_file } else { pal.console_text };
+            gts.draw(candidate, x + AUTOCOMPLETE_PADDING, row_y + gts.font_size as f32);
+        }
+
+        gts.color = previous_color;
+    }
+}
+
+/// The identifier characters right before `column`
+fn word_before(
+    line: &str,
+    column: usize
+) -> String {
+    let before: Vec<char> = line.chars().take(column).collect();
+    let start = before.iter()
+        .rposition(|c| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |i| i + 1);
+
+    before[start..].iter().collect()
+}
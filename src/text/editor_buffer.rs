@@ -0,0 +1,397 @@
+// Open buffers module
+//
+// The active buffer lives in main's file text, cursor,
+// language keywords and the file system's current file,
+// all other open buffers are stashed here until switched to.
+// Switching swaps the live state with the stashed one, so
+// unsaved edits, the cursor and the camera survive.
+
+use std::path::{Path, PathBuf};
+
+use macroquad::prelude::*;
+
+use crate::camera::editor_camera::EditorCamera;
+use crate::console::editor_file_system::*;
+use crate::options::editor_pallete::*;
+use crate::text::editor_cursor::EditorCursor;
+use crate::text::editor_language_manager::*;
+use crate::text::editor_marks::load_marks;
+use crate::text::editor_text_stylizer::EditorGeneralTextStylizer;
+
+pub const BUFFER_TAB_SPACING: f32 = 20.0;
+pub const BUFFER_RECENT_LIMIT: usize = 20;
+
+pub struct EditorBuffer {
+    pub file: Option<PathBuf>,
+    pub text: Vec<String>,
+    pub cursor: EditorCursor,
+    pub camera_offset: (f32, f32),
+    pub unsaved_changes: bool,
+    pub elk: EditorLanguageKeywords,
+}
+
+impl EditorBuffer {
+    pub fn new() -> EditorBuffer {
+        EditorBuffer {
+            file: None,
+            text: vec![],
+            cursor: EditorCursor::new(),
+            camera_offset: (0.0, 0.0),
+            unsaved_changes: false,
+            elk: load_keywords_for_extension("txt"),
+        }
+    }
+}
+
+pub struct EditorBuffers {
+    /// The slot at `active` is empty, its contents are the live state
+    pub buffers: Vec<EditorBuffer>,
+    pub active: usize,
+    /// Recently opened files, most recent first
+    pub recent: Vec<PathBuf>,
+    /// Bumped whenever a stashed buffer changes, by a switch, open, close or reload
+    pub generation: usize,
+    live_camera: (f32, f32),
+    restore_camera: Option<(f32, f32)>,
+}
+
+impl EditorBuffers {
+    pub fn new() -> EditorBuffers {
+        EditorBuffers {
+            buffers: vec![EditorBuffer::new()],
+            active: 0,
+            recent: Vec::new(),
+            generation: 0,
+            live_camera: (0.0, 0.0),
+            restore_camera: None,
+        }
+    }
+
+    /// Swap the live state with the buffer in `slot`
+    fn swap_live(
+        &mut self,
+        slot: usize,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        elk: &mut EditorLanguageKeywords
+    ) {
+        self.generation += 1;
+
+        let buffer = &mut self.buffers[slot];
+
+        std::mem::swap(&mut buffer.file, &mut efs.current_file);
+        std::mem::swap(&mut buffer.text, text);
+        std::mem::swap(&mut buffer.cursor, cursor);
+        std::mem::swap(&mut buffer.unsaved_changes, &mut efs.unsaved_changes);
+        std::mem::swap(&mut buffer.elk, elk);
+        std::mem::swap(&mut buffer.camera_offset, &mut self.live_camera);
+    }
+
+    /// Make the buffer at `index` the live one, return false if it doesn't exist
+    pub fn switch_to(
+        &mut self,
+        index: usize,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        elk: &mut EditorLanguageKeywords
+    ) -> bool {
+        if index >= self.buffers.len() {
+            return false;
+        }
+
+        if index == self.active {
+            return true;
+        }
+
+        // Stash the live state, then bring the target in
+        self.swap_live(self.active, efs, text, cursor, elk);
+        self.swap_live(index, efs, text, cursor, elk);
+        self.active = index;
+        self.restore_camera = Some(self.live_camera);
+
+        if let Some(file) = efs.current_file.clone() {
+            self.touch_recent(file);
+        }
+
+        true
+    }
+
+    /// Move a file to the front of the recent files
+    fn touch_recent(
+        &mut self,
+        file: PathBuf
+    ) {
+        self.recent.retain(|f| *f != file);
+        self.recent.insert(0, file);
+        self.recent.truncate(BUFFER_RECENT_LIMIT);
+    }
+
+    /// Cycle to the next (true) or previous (false) buffer
+    pub fn cycle(
+        &mut self,
+        forward: bool,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        elk: &mut EditorLanguageKeywords
+    ) {
+        let len = self.buffers.len();
+        let target = if forward {
+            (self.active + 1) % len
+        } else {
+            (self.active + len - 1) % len
+        };
+
+        self.switch_to(target, efs, text, cursor, elk);
+    }
+
+    /// Find the buffer index of an open file
+    pub fn find(
+        &self,
+        file: &Path,
+        efs: &EditorFileSystem
+    ) -> Option<usize> {
+        (0..self.buffers.len()).find(|&i| self.file_of(i, efs).as_deref() == Some(file))
+    }
+
+    /// Open a file in its own buffer, or switch to it if it is already open
+    pub fn open(
+        &mut self,
+        file: PathBuf,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        elk: &mut EditorLanguageKeywords
+    ) {
+        if let Some(index) = self.find(&file, efs) {
+            self.switch_to(index, efs, text, cursor, elk);
+            return;
+        }
+
+        let loaded = efs.load_file(&file).unwrap_or_default();
+        self.open_loaded(file, loaded, efs, text, cursor, elk);
+    }
+
+    /// Open `file` as open does, but leave everything as it is if it can't be read
+    pub fn try_open(
+        &mut self,
+        file: PathBuf,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        elk: &mut EditorLanguageKeywords
+    ) -> std::io::Result<()> {
+        if let Some(index) = self.find(&file, efs) {
+            self.switch_to(index, efs, text, cursor, elk);
+            return Ok(());
+        }
+
+        let loaded = efs.load_file(&file)?;
+        self.open_loaded(file, loaded, efs, text, cursor, elk);
+
+        Ok(())
+    }
+
+    /// Make `file`, read as `loaded`, the live buffer
+    fn open_loaded(
+        &mut self,
+        file: PathBuf,
+        loaded: Vec<String>,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        elk: &mut EditorLanguageKeywords
+    ) {
+        // An untouched empty buffer gets replaced instead of kept around
+        let reuse = efs.current_file.is_none() && !efs.unsaved_changes && text.iter().all(|l| l.is_empty());
+
+        if !reuse {
+            self.swap_live(self.active, efs, text, cursor, elk);
+            self.buffers.push(EditorBuffer::new());
+            self.active = self.buffers.len() - 1;
+        }
+
+        self.touch_recent(file.clone());
+
+        efs.current_file = Some(file.clone());
+        efs.unsaved_changes = false;
+        *text = loaded;
+        *cursor = EditorCursor::new();
+        cursor.marks = load_marks(&file, text);
+        *elk = load_keywords_for_file(&efs.current_file, text);
+        self.live_camera = (0.0, 0.0);
+        self.restore_camera = Some(self.live_camera);
+    }
+
+    /// Close the live buffer and switch to its neighbour,
+    /// closing the last buffer leaves an empty one
+    pub fn close(
+        &mut self,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        elk: &mut EditorLanguageKeywords
+    ) {
+        self.buffers.remove(self.active);
+
+        if self.buffers.is_empty() {
+            self.buffers.push(EditorBuffer::new());
+        }
+
+        self.active = self.active.min(self.buffers.len() - 1);
+        self.swap_live(self.active, efs, text, cursor, elk);
+
+        // The closed buffer's state was swapped into the slot, drop it
+        self.buffers[self.active] = EditorBuffer::new();
+        self.restore_camera = Some(self.live_camera);
+    }
+
+    /// Read every open file again after something else changed them on disk,
+    /// e.g. a git branch switch, files that are gone keep their text as unsaved
+    pub fn reload_all(
+        &mut self,
+        efs: &mut EditorFileSystem,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor
+    ) {
+        self.generation += 1;
+
+        for i in 0..self.buffers.len() {
+            let Some(file) = self.file_of(i, efs) else {
+                continue;
+            };
+
+            let reloaded = efs.load_file(&file).ok();
+
+            let (buffer_text, buffer_cursor, unsaved) = if i == self.active {
+                (&mut *text, &mut *cursor, &mut efs.unsaved_changes)
+            } else {
+                let buffer = &mut self.buffers[i];
+                (&mut buffer.text, &mut buffer.cursor, &mut buffer.unsaved_changes)
+            };
+
+            match reloaded {
+                Some(lines) => {
+                    *buffer_text = lines;
+                    *unsaved = false;
+                }
+
+                None => *unsaved = true,
+            }
+
+            let line = buffer_cursor.xy.1.min(buffer_text.len().saturating_sub(1));
+            let column = buffer_cursor.xy.0.min(buffer_text.get(line).map_or(0, |l| l.chars().count()));
+            buffer_cursor.xy = (column, line);
+        }
+    }
+
+    /// File of the buffer at `index`, the live one is read from the file system
+    pub fn file_of(
+        &self,
+        index: usize,
+        efs: &EditorFileSystem
+    ) -> Option<PathBuf> {
+        if index == self.active {
+            efs.current_file.clone()
+        } else {
+            self.buffers[index].file.clone()
+        }
+    }
+
+    /// Unsaved flag of the buffer at `index`
+    pub fn is_unsaved(
+        &self,
+        index: usize,
+        efs: &EditorFileSystem
+    ) -> bool {
+        if index == self.active {
+            efs.unsaved_changes
+        } else {
+            self.buffers[index].unsaved_changes
+        }
+    }
+
+    /// Display name of the buffer at `index`, '*' marks unsaved changes
+    pub fn name_of(
+        &self,
+        index: usize,
+        efs: &EditorFileSystem
+    ) -> String {
+        let mut name = path_buffer_file_to_string(&self.file_of(index, efs));
+
+        if name.is_empty() {
+            name = "<empty>".to_string();
+        }
+
+        if self.is_unsaved(index, efs) {
+            name = format!("*{}", name);
+        }
+
+        name
+    }
+
+    /// List all open buffers, one per line
+    pub fn list(
+        &self,
+        efs: &EditorFileSystem
+    ) -> String {
+        let mut list = String::from("Open buffers:\n");
+
+        for i in 0..self.buffers.len() {
+            let marker = if i == self.active { ">" } else { " " };
+            list.push_str(&format!("{} {}: {}\n", marker, i, self.name_of(i, efs)));
+        }
+
+        list
+    }
+
+    /// Keep the live camera offset, and restore the stashed one after a switch
+    pub fn sync_camera(
+        &mut self,
+        camera: &mut EditorCamera
+    ) {
+        if let Some((x, y)) = self.restore_camera.take() {
+            camera.offset_x = x;
+            camera.offset_y = y;
+        }
+
+        self.live_camera = (camera.offset_x, camera.offset_y);
+    }
+
+    /// Draw the open buffers in a row, highlighting the live one
+    pub fn draw_tabs(
+        &self,
+        efs: &EditorFileSystem,
+        gts: &mut EditorGeneralTextStylizer,
+        x: f32,
+        y: f32
+    ) {
+        let mut x = x;
+
+        for i in 0..self.buffers.len() {
+            let name = self.name_of(i, efs);
+
+            gts.color = if i == self.active { pallete().selected_file } else { pallete().file };
+            gts.draw(&name, x, y);
+
+            x += gts.measure(&name) + BUFFER_TAB_SPACING;
+        }
+    }
+}
+
+/// Load the language keywords matching a file's name, extension or shebang
+pub fn load_keywords_for_file(
+    file: &Option<PathBuf>,
+    text: &[String]
+) -> EditorLanguageKeywords {
+    let fname = path_buffer_file_to_string(file);
+
+    let name = Path::new(&fname)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+
+    load_keywords_for_file_name(name, text.first().map(|l| l.as_str()))
+}
@@ -0,0 +1,483 @@
+// Git change markers module
+//
+// The live buffer is compared with its file as committed at HEAD,
+// read once per file through the git binary, see editor_git.rs.
+// The comparison is a line diff done in memory whenever the text
+// changes, so the gutter follows every keystroke. Each block of
+// changed lines is a hunk, drawn in the gutter as added, modified
+// or deleted, <:gn>/<:gp> go to the next/previous one and <:grv>
+// puts the one under the cursor back to its HEAD version.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::console::editor_git::git_head_text;
+
+/// Past this many differing lines the diff gives up and marks the whole range
+pub const DIFF_MAX_EDITS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorLineChange {
+    Added,
+    Modified,
+    /// Lines were removed right after this one
+    Deleted,
+}
+
+/// Lines `old_start..old_start + old_len` of HEAD became
+/// lines `new_start..new_start + new_len` of the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditorHunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+impl EditorHunk {
+    /// The line a hunk is shown on, deletions are shown on the line before them
+    pub fn anchor(&self) -> usize {
+        if self.new_len == 0 {
+            self.new_start.saturating_sub(1)
+        } else {
+            self.new_start
+        }
+    }
+
+    pub fn contains(
+        &self,
+        line: usize
+    ) -> bool {
+        if self.new_len == 0 {
+            line == self.anchor()
+        } else {
+            (self.new_start..self.new_start + self.new_len).contains(&line)
+        }
+    }
+}
+
+pub struct EditorGitChanges {
+    /// HEAD lines of every file seen, None if it isn't committed
+    heads: HashMap<PathBuf, Option<Vec<String>>>,
+    file: Option<PathBuf>,
+    text: Vec<String>,
+    pub hunks: Vec<EditorHunk>,
+}
+
+impl EditorGitChanges {
+    pub fn new() -> EditorGitChanges {
+        EditorGitChanges {
+            heads: HashMap::new(),
+            file: None,
+            text: Vec::new(),
+            hunks: Vec::new(),
+        }
+    }
+
+    /// Diff the buffer with HEAD again if it changed since the last frame
+    pub fn update(
+        &mut self,
+        file: Option<PathBuf>,
+        text: &[String]
+    ) {
+        if file == self.file && text == self.text.as_slice() {
+            return;
+        }
+
+        self.hunks.clear();
+        self.text = text.to_vec();
+        self.file = file;
+
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let head = self.heads.entry(file.clone())
+            .or_insert_with(|| git_head_text(file).ok());
+
+        if let Some(head) = head {
+            self.hunks = diff_lines(head, text);
+        }
+    }
+
+    /// Read HEAD again, e.g. after a commit or a branch switch
+    pub fn refresh(&mut self) {
+        self.heads.clear();
+        self.file = None;
+    }
+
+    /// The marker of a buffer line
+    pub fn line_change(
+        &self,
+        line: usize
+    ) -> Option<EditorLineChange> {
+        let hunk = self.hunks.iter().find(|h| h.contains(line))?;
+
+        Some(if hunk.new_len == 0 {
+            EditorLineChange::Deleted
+        } else if hunk.old_len == 0 {
+            EditorLineChange::Added
+        } else {
+            EditorLineChange::Modified
+        })
+    }
+
+    /// The first hunk after (true) or before (false) `line`, wrapping around
+    pub fn next_hunk(
+        &self,
+        line: usize,
+        forward: bool
+    ) -> Option<&EditorHunk> {
+        if forward {
+            self.hunks.iter().find(|h| h.anchor() > line).or(self.hunks.first())
+        } else {
+            self.hunks.iter().rev().find(|h| h.anchor() < line).or(self.hunks.last())
+        }
+    }
+
+    /// The hunk under `line`
+    pub fn hunk_at(
+        &self,
+        line: usize
+    ) -> Option<EditorHunk> {
+        self.hunks.iter().find(|h| h.contains(line)).copied()
+    }
+
+    /// The HEAD lines of a hunk
+    pub fn head_lines(
+        &self,
+        hunk: &EditorHunk
+    ) -> Vec<String> {
+        self.file.as_ref()
+            .and_then(|file| self.heads.get(file))
+            .and_then(|head| head.as_ref())
+            .map(|head| head[hunk.old_start..hunk.old_start + hunk.old_len].to_vec())
+            .unwrap_or_default()
+    }
+}
+
+/// The hunks turning `old` into `new`, a Myers diff between their common start and end
+pub fn diff_lines(
+    old: &[String],
+    new: &[String]
+) -> Vec<EditorHunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    if a.is_empty() && b.is_empty() {
+        return Vec::new();
+    }
+
+    // Pairs of equal lines, in order, closed by the end of both
+    let mut equal = myers_equal_lines(a, b).unwrap_or_default();
+    equal.push((a.len(), b.len()));
+
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (0, 0);
+
+    for (ex, ey) in equal {
+        if ex > x || ey > y {
+            hunks.push(EditorHunk {
+                old_start: prefix + x,
+                old_len: ex - x,
+                new_start: prefix + y,
+                new_len: ey - y,
+            });
+        }
+
+        (x, y) = (ex + 1, ey + 1);
+    }
+
+    hunks
+}
+
+/// The equal lines of the shortest edit script from `a` to `b`,
+/// None if it takes more than DIFF_MAX_EDITS edits
+fn myers_equal_lines(
+    a: &[String],
+    b: &[String]
+) -> Option<Vec<(usize, usize)>> {
+    let mut equal = Vec::new();
+
+    collect_equal_lines(a, b, (0, 0), DIFF_MAX_EDITS, &mut equal).then_some(equal)
+}
+
+/// Split the script at its middle snake and diff both halves, in linear space,
+/// false if it takes more than `max_edits` edits
+fn collect_equal_lines(
+    a: &[String],
+    b: &[String],
+    start: (usize, usize),
+    max_edits: usize,
+    equal: &mut Vec<(usize, usize)>
+) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return a.len() + b.len() <= max_edits;
+    }
+
+    let Some((edits, (x, y), (u, v))) = middle_snake(a, b, max_edits) else {
+        return false;
+    };
+
+    if edits <= 1 {
+        // The shorter side is the longer one but a line at most
+        let prefix = a.iter().zip(b).take_while(|(l, r)| l == r).count();
+        let (skip_a, skip_b) = ((a.len() > b.len()) as usize, (b.len() > a.len()) as usize);
+
+        equal.extend((0..prefix).map(|i| (start.0 + i, start.1 + i)));
+        equal.extend((prefix..a.len().min(b.len())).map(|i| (start.0 + i + skip_a, start.1 + i + skip_b)));
+
+        return true;
+    }
+
+    // Each half takes fewer edits than the whole, so neither goes past the limit
+    collect_equal_lines(&a[..x], &b[..y], start, max_edits, equal);
+    equal.extend((0..u - x).map(|i| (start.0 + x + i, start.1 + y + i)));
+    collect_equal_lines(&a[u..], &b[v..], (start.0 + u, start.1 + v), max_edits, equal);
+
+    true
+}
+
+/// Edit count of a script, with the (x, y) and (u, v) ends of its middle snake
+type EditorSnake = (usize, (usize, usize), (usize, usize));
+
+/// The number of edits from `a` to `b` and a run of equal lines, from (x, y)
+/// to (u, v), that a shortest script passes halfway, found by searching from
+/// both ends at once, None if it takes more than `max_edits` edits
+fn middle_snake(
+    a: &[String],
+    b: &[String],
+    max_edits: usize
+) -> Option<EditorSnake> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+
+    let steps = ((n + m + 1) / 2).min(max_edits.div_ceil(2) as isize);
+    let offset = steps + 1;
+
+    // The furthest x reached on each diagonal, from the start and, on the
+    // reversed lines, from the end
+    let mut forward = vec![0isize; 2 * steps as usize + 3];
+    let mut backward = forward.clone();
+
+    let found = |edits: isize, from: (isize, isize), to: (isize, isize)| {
+        (edits as usize <= max_edits).then_some((edits as usize, (from.0 as usize, from.1 as usize), (to.0 as usize, to.1 as usize)))
+    };
+
+    for d in 0..=steps {
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+
+            let mut x = if k == -d || (k != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+
+            let mut y = x - k;
+            let from = (x, y);
+
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            forward[i] = x;
+
+            // The diagonal seen from the end, searched d - 1 times so far
+            let reverse_k = delta - k;
+
+            if odd && (-(d - 1)..=d - 1).contains(&reverse_k) && x + backward[(reverse_k + offset) as usize] >= n {
+                return found(2 * d - 1, from, (x, y));
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+
+            let mut x = if k == -d || (k != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+
+            let mut y = x - k;
+            let from = (x, y);
+
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            backward[i] = x;
+
+            let forward_k = delta - k;
+
+            if !odd && (-d..=d).contains(&forward_k) && x + forward[(forward_k + offset) as usize] >= n {
+                return found(2 * d, (n - x, m - y), (n - from.0, m - from.1));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(
+        text: &str
+    ) -> Vec<String> {
+        text.split_whitespace().map(|l| l.to_string()).collect()
+    }
+
+    fn hunk(
+        old_start: usize,
+        old_len: usize,
+        new_start: usize,
+        new_len: usize
+    ) -> EditorHunk {
+        EditorHunk { old_start, old_len, new_start, new_len }
+    }
+
+    /// `old` with every hunk replaced by its lines of `new`
+    fn apply(
+        old: &[String],
+        new: &[String],
+        hunks: &[EditorHunk]
+    ) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut at = 0;
+
+        for h in hunks {
+            result.extend_from_slice(&old[at..h.old_start]);
+            result.extend_from_slice(&new[h.new_start..h.new_start + h.new_len]);
+            at = h.old_start + h.old_len;
+        }
+
+        result.extend_from_slice(&old[at..]);
+        result
+    }
+
+    #[test]
+    fn identical_text_has_no_hunks() {
+        assert!(diff_lines(&lines("a b c"), &lines("a b c")).is_empty());
+        assert!(diff_lines(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn insertions() {
+        assert_eq!(diff_lines(&lines("a b c"), &lines("a b x y c")), vec![hunk(2, 0, 2, 2)]);
+        assert_eq!(diff_lines(&lines("a b c"), &lines("x a b c")), vec![hunk(0, 0, 0, 1)]);
+        assert_eq!(diff_lines(&lines("a b c"), &lines("a b c x")), vec![hunk(3, 0, 3, 1)]);
+    }
+
+    #[test]
+    fn deletions() {
+        assert_eq!(diff_lines(&lines("a b x y c"), &lines("a b c")), vec![hunk(2, 2, 2, 0)]);
+        assert_eq!(diff_lines(&lines("x a b c"), &lines("a b c")), vec![hunk(0, 1, 0, 0)]);
+        assert_eq!(diff_lines(&lines("a b c x"), &lines("a b c")), vec![hunk(3, 1, 3, 0)]);
+    }
+
+    #[test]
+    fn modified_block_between_equal_lines() {
+        assert_eq!(diff_lines(&lines("a b c d e"), &lines("a x y z e")), vec![hunk(1, 3, 1, 3)]);
+        assert_eq!(diff_lines(&lines("a b c d"), &lines("a x d")), vec![hunk(1, 2, 1, 1)]);
+    }
+
+    #[test]
+    fn an_empty_side_is_one_hunk() {
+        assert_eq!(diff_lines(&[], &lines("a b")), vec![hunk(0, 0, 0, 2)]);
+        assert_eq!(diff_lines(&lines("a b"), &[]), vec![hunk(0, 2, 0, 0)]);
+    }
+
+    #[test]
+    fn scattered_changes_keep_the_common_lines() {
+        let cases = [
+            ("a b c d e f g", "a c d x e g h"),
+            ("x a b x c", "a x b c x"),
+            ("a a a b", "b a a a"),
+            ("a b c a b b a", "c b a b a c"),
+        ];
+
+        for (old, new) in cases {
+            let (old, new) = (lines(old), lines(new));
+            let hunks = diff_lines(&old, &new);
+
+            assert_eq!(apply(&old, &new, &hunks), new);
+
+            // Hunks are in order and apart, equal lines between them
+            for pair in hunks.windows(2) {
+                assert!(pair[0].old_start + pair[0].old_len < pair[1].old_start);
+                assert!(pair[0].new_start + pair[0].new_len < pair[1].new_start);
+            }
+        }
+
+        // A shortest edit script, the 5 equal lines of the Myers paper's example
+        let hunks = diff_lines(&lines("a b c a b b a"), &lines("c b a b a c"));
+        let edited: usize = hunks.iter().map(|h| h.old_len + h.new_len).sum();
+        assert_eq!(edited, 5);
+    }
+
+    #[test]
+    fn as_many_equal_lines_as_the_longest_common_subsequence() {
+        // Small alphabets give long, overlapping runs of equal lines
+        let mut seed = 7u32;
+        let mut random_lines = |len: usize| -> Vec<String> {
+            (0..len).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                ((seed >> 16) % 3).to_string()
+            }).collect()
+        };
+
+        for len in 0..40 {
+            let (old, new) = (random_lines(len), random_lines(len + len % 7));
+
+            let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+            for i in (0..old.len()).rev() {
+                for j in (0..new.len()).rev() {
+                    lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+                }
+            }
+
+            let hunks = diff_lines(&old, &new);
+            let kept = old.len() - hunks.iter().map(|h| h.old_len).sum::<usize>();
+
+            assert_eq!(apply(&old, &new, &hunks), new);
+            assert_eq!(kept, lcs[0][0]);
+        }
+    }
+
+    #[test]
+    fn too_many_edits_mark_the_whole_range() {
+        let mut old: Vec<String> = (0..DIFF_MAX_EDITS).map(|i| format!("old {}", i)).collect();
+        let mut new: Vec<String> = (0..DIFF_MAX_EDITS).map(|i| format!("new {}", i)).collect();
+
+        // A line both share, kept by a finished diff, is marked with the rest
+        old.insert(DIFF_MAX_EDITS / 2, "same".to_string());
+        new.insert(DIFF_MAX_EDITS / 2, "same".to_string());
+
+        new.insert(0, "old 0".to_string());
+        new.push(format!("old {}", DIFF_MAX_EDITS - 1));
+
+        assert_eq!(diff_lines(&old, &new), vec![hunk(1, DIFF_MAX_EDITS - 1, 1, DIFF_MAX_EDITS + 1)]);
+
+        // Under the limit the shared line splits the changes in two
+        let quarter = DIFF_MAX_EDITS / 4;
+        let side = |name: &str| -> Vec<String> {
+            let mut lines: Vec<String> = (0..2 * quarter).map(|i| format!("{} {}", name, i)).collect();
+            lines.insert(quarter, "same".to_string());
+            lines
+        };
+
+        assert_eq!(diff_lines(&side("old"), &side("new")), vec![hunk(0, quarter, 0, quarter), hunk(quarter + 1, quarter, quarter + 1, quarter)]);
+    }
+}
@@ -7,6 +7,7 @@ use macroquad::prelude::*;
 use miniquad::date;
 
 use crate::audio::editor_audio::*;
+use crate::options::editor_pallete::pallete;
 
 pub const CURSOR_WORD_OFFSET: f32 = 600.0;
 
@@ -14,8 +15,6 @@ pub const CURSOR_CONTINUOUS_PRESS_INITIAL_DELAY: f64 = 0.02;
 
 pub const CURSOR_CONTINUOUS_PRESS_DELAY: f64 = 0.09;
 
-pub const CURSOR_LINE_COLOR: Color = Color::new(1.0, 1.0, 1.0, 0.05);
-
 pub const CURSOR_HEIGHT: f32 = 3.5;
 
 pub struct EditorCursor {
@@ -27,7 +26,8 @@ pub struct EditorCursor {
     pub vel_x: f32,
     pub vel_y: f32,
     pub select_mode: bool,
-    pub select_xy: (usize, usize)
+    pub select_xy: (usize, usize),
+    pub marks: Vec<(usize, usize)>,
 }
 
 impl EditorCursor {
@@ -41,7 +41,8 @@ impl EditorCursor {
             vel_x: 0.0,
             vel_y: 0.0,
             select_mode: false,
-            select_xy: (0, 0)
+            select_xy: (0, 0),
+            marks: Vec::new(),
         }
     }
 
@@ -57,7 +58,7 @@ impl EditorCursor {
             y + CURSOR_HEIGHT,
             screen_width(),
              font_size,
-            CURSOR_LINE_COLOR
+            pallete().cursor_line
         );
     }
 
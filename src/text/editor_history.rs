@@ -0,0 +1,444 @@
+// Undo/redo history module
+//
+// Every buffer edit is recorded as a line range replacement:
+// the lines that were removed and the lines inserted in their place.
+// Consecutive edits of the same kind are grouped into a single undo
+// step (a typed word, a line move, a directive), and each file keeps
+// its own history for the rest of the session.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use crate::text::editor_cursor::EditorCursor;
+use crate::text::editor_marks::shift_marks;
+
+pub const HISTORY_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditorEditKind {
+    Insert,
+    Delete,
+    Line,
+    Directive,
+}
+
+/// Lines `removed` starting at `line` were replaced by `inserted`
+#[derive(Debug, Clone)]
+pub struct EditorEdit {
+    pub line: usize,
+    pub removed: Vec<String>,
+    pub inserted: Vec<String>,
+}
+
+/// A single undo step
+#[derive(Debug, Clone)]
+pub struct EditorEditGroup {
+    pub kind: EditorEditKind,
+    pub edits: Vec<EditorEdit>,
+    pub cursor_before: (usize, usize),
+    pub cursor_after: (usize, usize),
+    pub sealed: bool,
+}
+
+/// History of a file that is not currently open
+struct EditorFileHistory {
+    undo_stack: Vec<EditorEditGroup>,
+    redo_stack: Vec<EditorEditGroup>,
+    text_hash: u64,
+}
+
+/// Snapshot taken by `begin`, completed by `end`
+struct EditorPendingEdit {
+    kind: EditorEditKind,
+    line: usize,
+    removed: Vec<String>,
+    text_len: usize,
+    cursor_before: (usize, usize),
+}
+
+pub struct EditorHistory {
+    pub undo_stack: Vec<EditorEditGroup>,
+    pub redo_stack: Vec<EditorEditGroup>,
+    pub current_file: Option<PathBuf>,
+    pending: Option<EditorPendingEdit>,
+    files: HashMap<PathBuf, EditorFileHistory>,
+}
+
+impl EditorHistory {
+    pub fn new() -> EditorHistory {
+        EditorHistory {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            current_file: None,
+            pending: None,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Snapshot `count` lines from `first` before they get edited,
+    /// must be followed by `end` once the edit is done
+    pub fn begin(
+        &mut self,
+        text: &[String],
+        cursor: &EditorCursor,
+        first: usize,
+        count: usize,
+        kind: EditorEditKind
+    ) {
+        let first = first.min(text.len());
+        let last = (first + count).min(text.len());
+
+        self.pending = Some(EditorPendingEdit {
+            kind,
+            line: first,
+            removed: text[first..last].to_vec(),
+            text_len: text.len(),
+            cursor_before: cursor.xy,
+        });
+    }
+
+    /// Complete the edit started with `begin`, no-op edits are dropped,
+    /// marks below the edit follow their lines
+    pub fn end(
+        &mut self,
+        text: &[String],
+        cursor: &mut EditorCursor
+    ) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+
+        // Lines outside the snapshot are untouched, so the edited range
+        // grew or shrank by exactly the change in line count.
+        let new_count = (pending.removed.len() + text.len()).saturating_sub(pending.text_len);
+        let first = pending.line.min(text.len());
+        let last = (first + new_count).min(text.len());
+        let mut removed = pending.removed;
+        let mut inserted = text[first..last].to_vec();
+
+        // Directives snapshot the whole buffer, keep only the lines that changed
+        let prefix = removed.iter()
+            .zip(inserted.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let max_suffix = removed.len().min(inserted.len()) - prefix;
+        let suffix = removed.iter().rev()
+            .zip(inserted.iter().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        if prefix == removed.len() && prefix == inserted.len() {
+            return;
+        }
+
+        removed.truncate(removed.len() - suffix);
+        inserted.truncate(inserted.len() - suffix);
+
+        let edit = EditorEdit {
+            line: first + prefix,
+            removed: removed.split_off(prefix),
+            inserted: inserted.split_off(prefix),
+        };
+
+        shift_marks(cursor, edit.line, edit.removed.len(), edit.inserted.len());
+        self.push(edit, pending.kind, pending.cursor_before, cursor.xy);
+    }
+
+    /// Stop the current group from absorbing further edits
+    pub fn seal(&mut self) {
+        if let Some(group) = self.undo_stack.last_mut() {
+            group.sealed = true;
+        }
+    }
+
+    /// Push an edit, merging it into the last group when it continues it
+    fn push(
+        &mut self,
+        edit: EditorEdit,
+        kind: EditorEditKind,
+        cursor_before: (usize, usize),
+        cursor_after: (usize, usize)
+    ) {
+        self.redo_stack.clear();
+
+        if let Some(group) = self.undo_stack.last_mut() {
+            let mergeable = !group.sealed
+                && group.kind == kind
+                && matches!(kind, EditorEditKind::Insert | EditorEditKind::Delete)
+                && group.cursor_after == cursor_before;
+
+            if mergeable {
+                group.cursor_after = cursor_after;
+
+                // Typing on the same line keeps a single edit per line
+                if let Some(last) = group.edits.last_mut()
+                    && last.line == edit.line
+                    && last.inserted == edit.removed {
+                    last.inserted = edit.inserted;
+                    return;
+                }
+
+                group.edits.push(edit);
+                return;
+            }
+        }
+
+        self.undo_stack.push(EditorEditGroup {
+            kind,
+            edits: vec![edit],
+            cursor_before,
+            cursor_after,
+            sealed: !matches!(kind, EditorEditKind::Insert | EditorEditKind::Delete),
+        });
+
+        if self.undo_stack.len() > HISTORY_LIMIT {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    /// Revert the last group, return true if there was one
+    pub fn undo(
+        &mut self,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor
+    ) -> bool {
+        self.pending = None;
+
+        let Some(mut group) = self.undo_stack.pop() else {
+            return false;
+        };
+
+        for edit in group.edits.iter().rev() {
+            let start = edit.line.min(text.len());
+            let end = (edit.line + edit.inserted.len()).min(text.len());
+            text.splice(start..end, edit.removed.iter().cloned());
+            shift_marks(cursor, edit.line, edit.inserted.len(), edit.removed.len());
+        }
+
+        cursor.xy = group.cursor_before;
+        group.sealed = true;
+        self.redo_stack.push(group);
+
+        true
+    }
+
+    /// Reapply the last undone group, return true if there was one
+    pub fn redo(
+        &mut self,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor
+    ) -> bool {
+        self.pending = None;
+
+        let Some(group) = self.redo_stack.pop() else {
+            return false;
+        };
+
+        for edit in group.edits.iter() {
+            let start = edit.line.min(text.len());
+            let end = (edit.line + edit.removed.len()).min(text.len());
+            text.splice(start..end, edit.inserted.iter().cloned());
+            shift_marks(cursor, edit.line, edit.removed.len(), edit.inserted.len());
+        }
+
+        cursor.xy = group.cursor_after;
+        self.undo_stack.push(group);
+
+        true
+    }
+
+    /// Store the history of the file we are leaving and restore the one
+    /// of the file we switched to, if its text is still the one we left.
+    /// The text we left is taken from the whole buffer `begin` snapshot.
+    pub fn switch_file(
+        &mut self,
+        file: &Option<PathBuf>,
+        text: &[String]
+    ) {
+        let previous_text = self.pending.take().map(|p| p.removed).unwrap_or_default();
+
+        if let Some(previous) = self.current_file.take() {
+            self.files.insert(previous, EditorFileHistory {
+                undo_stack: std::mem::take(&mut self.undo_stack),
+                redo_stack: std::mem::take(&mut self.redo_stack),
+                text_hash: text_hash(&previous_text),
+            });
+        }
+
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.current_file = file.clone();
+
+        // If the file changed on disk or lost its unsaved edits,
+        // the stored edits no longer line up with the text.
+        if let Some(path) = file
+            && let Some(stored) = self.files.remove(path)
+            && stored.text_hash == text_hash(text) {
+            self.undo_stack = stored.undo_stack;
+            self.redo_stack = stored.redo_stack;
+        }
+    }
+
+    /// Keep the history of the current file after it got renamed
+    pub fn rename_file(
+        &mut self,
+        file: &Option<PathBuf>
+    ) {
+        self.current_file = file.clone();
+    }
+}
+
+/// Hash a buffer to validate stored histories
+fn text_hash(text: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(
+        text: &[&str]
+    ) -> Vec<String> {
+        text.iter().map(|l| l.to_string()).collect()
+    }
+
+    /// Type `c` at the cursor, as insert mode records it
+    fn type_char(
+        history: &mut EditorHistory,
+        text: &mut [String],
+        cursor: &mut EditorCursor,
+        c: char
+    ) {
+        history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Insert);
+        text[cursor.xy.1].insert(cursor.xy.0, c);
+        cursor.xy.0 += 1;
+        history.end(text, cursor);
+    }
+
+    /// Split the line at the cursor, as Enter does
+    fn newline(
+        history: &mut EditorHistory,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor
+    ) {
+        history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Insert);
+        let rest = text[cursor.xy.1].split_off(cursor.xy.0);
+        text.insert(cursor.xy.1 + 1, rest);
+        cursor.xy = (0, cursor.xy.1 + 1);
+        history.end(text, cursor);
+        history.seal();
+    }
+
+    #[test]
+    fn typing_merges_into_one_group() {
+        let mut history = EditorHistory::new();
+        let mut cursor = EditorCursor::new();
+        let mut text = lines(&[""]);
+
+        for c in "word".chars() {
+            type_char(&mut history, &mut text, &mut cursor, c);
+        }
+
+        assert_eq!(history.undo_stack.len(), 1);
+        assert_eq!(history.undo_stack[0].edits.len(), 1);
+
+        assert!(history.undo(&mut text, &mut cursor));
+        assert_eq!(text, lines(&[""]));
+        assert_eq!(cursor.xy, (0, 0));
+
+        assert!(history.redo(&mut text, &mut cursor));
+        assert_eq!(text, lines(&["word"]));
+        assert_eq!(cursor.xy, (4, 0));
+    }
+
+    #[test]
+    fn a_newline_seals_the_group() {
+        let mut history = EditorHistory::new();
+        let mut cursor = EditorCursor::new();
+        let mut text = lines(&[""]);
+
+        type_char(&mut history, &mut text, &mut cursor, 'a');
+        newline(&mut history, &mut text, &mut cursor);
+        type_char(&mut history, &mut text, &mut cursor, 'b');
+
+        assert_eq!(text, lines(&["a", "b"]));
+        assert_eq!(history.undo_stack.len(), 2);
+
+        history.undo(&mut text, &mut cursor);
+        assert_eq!(text, lines(&["a", ""]));
+
+        history.undo(&mut text, &mut cursor);
+        assert_eq!(text, lines(&[""]));
+        assert!(!history.undo(&mut text, &mut cursor));
+    }
+
+    #[test]
+    fn line_edits_never_merge() {
+        let mut history = EditorHistory::new();
+        let mut cursor = EditorCursor::new();
+        let mut text = lines(&["a", "b"]);
+
+        for _ in 0..2 {
+            history.begin(&text, &cursor, 0, 2, EditorEditKind::Line);
+            text.swap(0, 1);
+            history.end(&text, &mut cursor);
+        }
+
+        assert_eq!(history.undo_stack.len(), 2);
+    }
+
+    #[test]
+    fn the_oldest_groups_are_evicted() {
+        let mut history = EditorHistory::new();
+        let mut cursor = EditorCursor::new();
+        let mut text = lines(&["0"]);
+
+        for i in 1..=HISTORY_LIMIT + 1 {
+            history.begin(&text, &cursor, 0, 1, EditorEditKind::Directive);
+            text[0] = i.to_string();
+            history.end(&text, &mut cursor);
+        }
+
+        assert_eq!(history.undo_stack.len(), HISTORY_LIMIT);
+
+        while history.undo(&mut text, &mut cursor) {}
+
+        // The first edit can't be undone anymore
+        assert_eq!(text, lines(&["1"]));
+    }
+
+    #[test]
+    fn stored_histories_are_dropped_after_an_external_change() {
+        let (a, b) = (Some(PathBuf::from("a.rs")), Some(PathBuf::from("b.rs")));
+        let mut history = EditorHistory::new();
+        let mut cursor = EditorCursor::new();
+        let mut text = lines(&[""]);
+
+        history.switch_file(&a, &text);
+        type_char(&mut history, &mut text, &mut cursor, 'x');
+
+        // Directives snapshot the whole buffer before switching files
+        let leave = |history: &mut EditorHistory, text: &[String], to: &Option<PathBuf>, new_text: &[String]| {
+            history.begin(text, &EditorCursor::new(), 0, text.len(), EditorEditKind::Directive);
+            history.switch_file(to, new_text);
+        };
+
+        leave(&mut history, &text, &b, &lines(&["other"]));
+        assert!(history.undo_stack.is_empty());
+
+        leave(&mut history, &lines(&["other"]), &a, &text);
+        assert_eq!(history.undo_stack.len(), 1);
+
+        // Changed outside the editor while b was open
+        leave(&mut history, &text, &b, &lines(&["other"]));
+        leave(&mut history, &lines(&["other"]), &a, &lines(&["x", "more"]));
+        assert!(history.undo_stack.is_empty());
+    }
+}
@@ -2,16 +2,22 @@
 
 use macroquad::prelude::*;
 
-use crate::options::editor_options::EditorOptions;
+use crate::options::editor_options::{EditorOptions, MAX_FONT_SIZE, MIN_FONT_SIZE};
+use crate::text::editor_autocomplete::EditorAutocomplete;
 use crate::text::editor_language_manager::EditorLanguageKeywords;
 use crate::text::editor_text_stylizer::*;
 use crate::text::editor_cursor::*;
 use crate::text::editor_clipboard::*;
+use crate::text::editor_buffer::EditorBuffers;
+use crate::text::editor_history::*;
+use crate::text::editor_search::EditorSearch;
+use crate::text::editor_selection::*;
 
 use crate::audio::editor_audio::*;
 use crate::console::editor_console::*;
 use crate::console::editor_file_system::*;
 use crate::console::editor_directives::*;
+use crate::console::editor_terminal::TERMINAL_SCROLL_STEP;
 
 pub const TAB_SIZE: usize = 4;
 pub const TAB_PATTERN: &str = "    ";
@@ -22,15 +28,18 @@ fn lshift_shortcuts(
     _audio: &EditorAudio,
     _console: &mut EditorConsole,
     efs: &mut EditorFileSystem,
+    history: &mut EditorHistory,
 ) -> bool {
     if cursor.is_combo_active(KeyCode::Up, Some(KeyCode::LeftShift)) && cursor.xy.1 > 0 {
         let current_line = cursor.xy.1;
         let swap_with = current_line - 1;
+        history.begin(text, cursor, swap_with, 2, EditorEditKind::Line);
         text.swap(current_line, swap_with);
         efs.unsaved_changes = true;
 
         // Clamp cursor.x to new line length
         cursor.xy.0 = cursor.xy.0.min(text[swap_with].len());
+        history.end(text, cursor);
 
         return true;
     }
@@ -38,11 +47,13 @@ fn lshift_shortcuts(
     if cursor.is_combo_active(KeyCode::Down, Some(KeyCode::LeftShift)) && cursor.xy.1 + 1 < text.len() {
         let current_line = cursor.xy.1;
         let swap_with = current_line + 1;
+        history.begin(text, cursor, current_line, 2, EditorEditKind::Line);
         text.swap(current_line, swap_with);
         efs.unsaved_changes = true;
 
         // Clamp cursor.x to new line length
         cursor.xy.0 = cursor.xy.0.min(text[swap_with].len());
+        history.end(text, cursor);
 
         return true;
     }
@@ -60,26 +71,70 @@ pub fn lctrl_shortcuts(
     gts: &mut EditorGeneralTextStylizer,
     ops: &mut EditorOptions,
     elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers,
+    search: &mut EditorSearch,
 ) -> bool {
     if is_key_down(KeyCode::LeftControl) {
+        // Undo, LShift for redo
+        if cursor.is_combo_active(KeyCode::Z, None) {
+            let changed = if is_key_down(KeyCode::LeftShift) {
+                history.redo(text, cursor)
+            } else {
+                history.undo(text, cursor)
+            };
+
+            if changed {
+                audio.play_delete();
+                efs.unsaved_changes = true;
+            }
+
+            return true;
+        }
+
+        // Redo
+        if cursor.is_combo_active(KeyCode::Y, None) {
+            if history.redo(text, cursor) {
+                audio.play_insert();
+                efs.unsaved_changes = true;
+            }
+
+            return true;
+        }
+
+        // Cut the selection
+        if cursor.is_combo_active(KeyCode::X, None) && cursor.select_mode {
+            audio.play_delete();
+            efs.unsaved_changes = true;
+            cb_set(&selected_text(cursor, text));
+            begin_selection_edit(cursor, text, history, EditorEditKind::Line);
+            delete_selection(cursor, text);
+            history.end(text, cursor);
+            return true;
+        }
+
         if cursor.is_combo_active(KeyCode::X, None) && !text.is_empty() {
             audio.play_delete();
             efs.unsaved_changes = true;
+            history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Line);
             text.remove(cursor.xy.1);
+            history.end(text, cursor);
             return true;
         }
 
         if cursor.is_combo_active(KeyCode::D, None) && !text.is_empty() {
             audio.play_insert();
+            history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Line);
             let line_clone = text[cursor.xy.1].clone();
             text.insert(cursor.xy.1 + 1, line_clone);
+            history.end(text, cursor);
             return true;
         }
 
         // Save/write to file
         if is_key_pressed(KeyCode::S) {
             console.directive = ":w".to_string();
-            execute_directive(&mut console.directive, efs, text, cursor, ops, elk);
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
 
             return true;
         }
@@ -97,7 +152,7 @@ pub fn lctrl_shortcuts(
         // Open native file explorer
         if is_key_pressed(KeyCode::O) {
             console.directive = ":O".to_string();
-            execute_directive(&mut console.directive, efs, text, cursor, ops, elk);
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
 
             return true;
         }
@@ -109,6 +164,41 @@ pub fn lctrl_shortcuts(
             console.mode = true;            
         }
 
+        // Show/hide the terminal output
+        if is_key_pressed(KeyCode::T) && is_key_down(KeyCode::LeftShift) {
+            efs.terminal.visible = !efs.terminal.visible;
+
+            return true;
+        }
+
+        // Kill the running terminal command
+        if is_key_pressed(KeyCode::K) {
+            console.directive = ":tk".to_string();
+            let (message, _) = execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
+            console.directive.clear();
+
+            if !message.is_empty() {
+                console.message = message;
+                console.showing_message = true;
+            }
+
+            return true;
+        }
+
+        // Hover info of the language server
+        if is_key_pressed(KeyCode::H) {
+            console.directive = ":hv".to_string();
+            let (message, _) = execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
+            console.directive.clear();
+
+            if !message.is_empty() {
+                console.message = message;
+                console.showing_message = true;
+            }
+
+            return true;
+        }
+
         // Open terminal
         if is_key_pressed(KeyCode::T) {
             console.directive = ":t $ ".to_string();
@@ -121,7 +211,7 @@ pub fn lctrl_shortcuts(
         // Create a new file
         if is_key_pressed(KeyCode::N) {
             console.directive = ":c f".to_string();
-            execute_directive(&mut console.directive, efs, text, cursor, ops, elk);
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
             console.directive = ":b ".to_string();
             console.mode = true;
             console.cursor.x = console.directive.len();
@@ -168,6 +258,8 @@ pub fn lctrl_shortcuts(
             let left_cursor_idx = cursor_idx - left_distance;
             let word_len = cursor_idx + right_distance;
             
+            history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Line);
+
             // Actual deletion.            
             for _ in left_cursor_idx..word_len {
                 let line = &mut text[cursor.xy.1];
@@ -177,6 +269,7 @@ pub fn lctrl_shortcuts(
                 }
             }
             
+            history.end(text, cursor);
             audio.play_delete();
             efs.unsaved_changes = true;
             
@@ -186,15 +279,73 @@ pub fn lctrl_shortcuts(
         // Save and quit
         if is_key_pressed(KeyCode::Q) {
             console.directive = ":W".to_string();
-            execute_directive(&mut console.directive, efs, text, cursor, ops, elk);
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
             console.directive = ":q".to_string();
-            execute_directive(&mut console.directive, efs, text, cursor, ops, elk);
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
         }
         
         // Quit
         if is_key_pressed(KeyCode::E) {
             console.directive = ":e".to_string();
-            execute_directive(&mut console.directive, efs, text, cursor, ops, elk);
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
+        }
+
+        // Next match, LShift for the previous one
+        if is_key_pressed(KeyCode::G) {
+            console.directive = if is_key_down(KeyCode::LeftShift) { ":fp" } else { ":fn" }.to_string();
+            let (message, _) = execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
+            console.directive.clear();
+
+            if !message.is_empty() {
+                console.message = message;
+                console.showing_message = true;
+            }
+
+            return true;
+        }
+
+        // Go to the definition of the identifier under the cursor, LShift to go back
+        if is_key_pressed(KeyCode::J) {
+            console.directive = if is_key_down(KeyCode::LeftShift) { ":back" } else { ":def" }.to_string();
+            let (message, _) = execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
+            console.directive.clear();
+
+            if !message.is_empty() {
+                console.message = message;
+                console.showing_message = true;
+            }
+
+            return true;
+        }
+
+        // Scroll the terminal output
+        if is_key_down(KeyCode::LeftShift) && efs.terminal.visible {
+            if is_key_pressed(KeyCode::PageUp) {
+                efs.terminal.scroll_by(TERMINAL_SCROLL_STEP as isize * 5);
+
+                return true;
+            }
+
+            if is_key_pressed(KeyCode::PageDown) {
+                efs.terminal.scroll_by(-(TERMINAL_SCROLL_STEP as isize * 5));
+
+                return true;
+            }
+        }
+
+        // Cycle open buffers
+        if is_key_pressed(KeyCode::PageDown) {
+            console.directive = ":bn".to_string();
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
+
+            return true;
+        }
+
+        if is_key_pressed(KeyCode::PageUp) {
+            console.directive = ":bp".to_string();
+            execute_directive(&mut console.directive, efs, text, cursor, ops, elk, history, buffers, search, &mut console.list);
+
+            return true;
         }
 
         // Console switch
@@ -205,7 +356,7 @@ pub fn lctrl_shortcuts(
         }
 
         if is_key_pressed(KeyCode::Minus) {
-            if gts.font_size > 12 {
+            if gts.font_size > MIN_FONT_SIZE {
                 gts.font_size -= 2;
             }
 
@@ -213,31 +364,39 @@ pub fn lctrl_shortcuts(
         }
         
         if is_key_pressed(KeyCode::Equal) {
-            if gts.font_size < 45 {
+            if gts.font_size < MAX_FONT_SIZE {
                 gts.font_size += 2;
             }
 
             return true;
         }
         
+        // Copy the selection, or the current line outside of selection mode
         if is_key_pressed(KeyCode::C) {
             let extract = if cursor.select_mode {
-                // extract from select_xy -> cursor_xy if cursor_xy > select_xy
-                // else form cursor_xy -> select_xy
-                "SELECTED_TEXT" // replace with your extraction logic
+                selected_text(cursor, text)
             } else {
-                &text[cursor.xy.1].clone()
+                text[cursor.xy.1].clone()
             };
         
             cb_set(&extract);
 
-            println!("Setted {}", extract);
+            return true;
         }
         
-        if is_key_down(KeyCode::V) {
+        // Paste, replacing the selection if there is one
+        if is_key_pressed(KeyCode::V) {
             if let Some(paste_text) = cb_get() {
-                println!("Pasted {}", paste_text);
+                audio.play_insert();
+                efs.unsaved_changes = true;
+
+                begin_selection_edit(cursor, text, history, EditorEditKind::Line);
+                delete_selection(cursor, text);
+                insert_text_at_cursor(cursor, text, &paste_text);
+                history.end(text, cursor);
             }
+
+            return true;
         }
 
         // Select mode switch
@@ -273,6 +432,9 @@ pub fn record_special_keys(
     efs: &mut EditorFileSystem,
     ops: &mut EditorOptions,
     elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers,
+    search: &mut EditorSearch,
 ) -> bool {
     // Backspace
     if cursor.is_combo_active(KeyCode::Backspace, None) {
@@ -283,17 +445,29 @@ pub fn record_special_keys(
             return true;
         }
 
-        let line = &mut text[cursor.xy.1];
-        cursor.xy.0 = cursor.xy.0.min(line.chars().count());
+        // Delete the selection as a whole
+        if cursor.select_mode {
+            begin_selection_edit(cursor, text, history, EditorEditKind::Line);
+            delete_selection(cursor, text);
+            history.end(text, cursor);
+            return true;
+        }
+
+        cursor.xy.0 = cursor.xy.0.min(text[cursor.xy.1].chars().count());
 
         if cursor.xy.0 == 0 && cursor.xy.1 > 0 {
+            history.begin(text, cursor, cursor.xy.1 - 1, 2, EditorEditKind::Delete);
             let current_line = text.remove(cursor.xy.1);
             cursor.xy.1 -= 1;
             cursor.xy.0 = text[cursor.xy.1].chars().count();
             text[cursor.xy.1].push_str(&current_line);
+            history.end(text, cursor);
             return true;
         }
 
+        history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Delete);
+
+        let line = &mut text[cursor.xy.1];
         let cursor_pos = cursor.xy.0;
 
         if cursor_pos >= TAB_SIZE {
@@ -302,6 +476,7 @@ pub fn record_special_keys(
             if &line[start..end] == TAB_PATTERN {
                 line.replace_range(start..end, "");
                 cursor.xy.0 -= TAB_SIZE;
+                history.end(text, cursor);
                 return true;
             }
         }
@@ -314,16 +489,31 @@ pub fn record_special_keys(
             }
         }
 
+        history.end(text, cursor);
+
+        return true;
+    }
+
+    // Delete key only removes selections
+    if cursor.is_combo_active(KeyCode::Delete, None) && cursor.select_mode {
+        audio.play_delete();
+        efs.unsaved_changes = true;
+        begin_selection_edit(cursor, text, history, EditorEditKind::Line);
+        delete_selection(cursor, text);
+        history.end(text, cursor);
         return true;
     }
 
     // Tab insertion
     if cursor.is_combo_active(KeyCode::Tab, None) {
         audio.play_space();
+        history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Insert);
         let line = &mut text[cursor.xy.1];
         let idx = char_to_byte(line, cursor.xy.0);
         line.insert_str(idx, TAB_PATTERN);
         cursor.xy.0 += TAB_SIZE;
+        history.end(text, cursor);
+        history.seal();
         return true;
     }
 
@@ -331,6 +521,7 @@ pub fn record_special_keys(
     if cursor.is_combo_active(KeyCode::Enter, None) {
         audio.play_return();
         efs.unsaved_changes = true;
+        history.begin(text, cursor, cursor.xy.1, 1, EditorEditKind::Insert);
 
         let cursor_pos = cursor.xy.0;
         let mut line = text.remove(cursor.xy.1);
@@ -376,11 +567,15 @@ pub fn record_special_keys(
                 }
             }
         }
+
+        // A new line ends the word being typed
+        history.end(text, cursor);
+        history.seal();
     }
 
-    lshift_shortcuts(cursor, text, audio, console, efs);
+    lshift_shortcuts(cursor, text, audio, console, efs, history);
 
-    let is_lctrl = lctrl_shortcuts(cursor, text, audio, console, efs, gts, ops, elk);
+    let is_lctrl = lctrl_shortcuts(cursor, text, audio, console, efs, gts, ops, elk, history, buffers, search);
 
     if !is_lctrl {
         file_text_navigation(cursor, text, audio);
@@ -399,102 +594,99 @@ pub fn record_keyboard_to_file_text(
     efs: &mut EditorFileSystem,
     ops: &mut EditorOptions,
     elk: &mut EditorLanguageKeywords,
+    history: &mut EditorHistory,
+    buffers: &mut EditorBuffers,
+    search: &mut EditorSearch,
+    completion: &mut EditorAutocomplete,
 ) {
     if text.is_empty() { text.push(String::new()); }
 
-    if record_special_keys(cursor, text, audio, console, gts, efs, ops, elk) {
+    // The completion popup takes its keys before anything else
+    if completion.record_keys(cursor, text, audio, efs, history) {
+        return;
+    }
+
+    let previous_xy = cursor.xy;
+
+    let special = record_special_keys(cursor, text, audio, console, gts, efs, ops, elk, history, buffers, search);
+
+    // Typing and deleting update the popup, moving away closes it
+    if is_key_down(KeyCode::Backspace) && cursor.xy.1 == previous_xy.1 {
+        completion.update(text, cursor, elk, buffers);
+    } else if cursor.xy != previous_xy {
+        completion.close();
+    }
+
+    if special {
         return;
     }
 
     if let Some(c) = get_char_pressed() {
         if c.is_control() || c.is_ascii_control() { return; }
 
+        // Typing over a selection replaces it
+        begin_selection_edit(cursor, text, history, EditorEditKind::Insert);
+        delete_selection(cursor, text);
+
         let line = text.get_mut(cursor.xy.1).unwrap();
         efs.unsaved_changes = true;
 
         let idx = char_to_byte(line, cursor.xy.0);
             
-        // Next opener/closer autocomplete.
-        match c {
-            '(' => {
-                line.insert(idx, c);
-                let idx_next = char_to_byte(line, cursor.xy.0 + 1);
-                line.insert(idx_next, ')');
-                cursor.xy.0 += 1;
-
-                // recognize_cursor_word(cursor, &text[cursor.xy.1]);
-            }
-            
-            '[' => {
-                line.insert(idx, c);
-                let idx_next = char_to_byte(line, cursor.xy.0 + 1);
-                line.insert(idx_next, ']');
+        // Next opener/closer autocomplete, from the language's brackets and quotes
+        if let Some(&(_, close)) = elk.brackets.iter().find(|(open, _)| *open == c) {
+            line.insert(idx, c);
+            let idx_next = char_to_byte(line, cursor.xy.0 + 1);
+            line.insert(idx_next, close);
+            cursor.xy.0 += 1;
+
+            // recognize_cursor_word(cursor, &text[cursor.xy.1]);
+        } else if elk.auto_closed_quotes().any(|&q| q == c) {
+            if next_char_is(c, cursor, line) {
+                // Just move cursor over existing quote
                 cursor.xy.0 += 1;
-
-                // recognize_cursor_word(cursor, &text[cursor.xy.1]);
-            }
-            
-            '{' => {
+            } else {
                 line.insert(idx, c);
                 let idx_next = char_to_byte(line, cursor.xy.0 + 1);
-                line.insert(idx_next, '}');
+                line.insert(idx_next, c);
                 cursor.xy.0 += 1;
-
-                // recognize_cursor_word(cursor, &text[cursor.xy.1]);
             }
-            
-            
-            '"' => {
-                if next_char_is('"', cursor, line) {
-                    // Just move cursor over existing quote
-                    cursor.xy.0 += 1;
-                } else {
-                    line.insert(idx, '"');
-                    line.insert(idx + 1, '"');
-                    cursor.xy.0 += 1;
-                }
 
-                // recognize_cursor_word(cursor, &text[cursor.xy.1]);
-            }
-            
-            ')' => {
-                if next_char_is(')', cursor, line) {
-                    cursor.xy.0 += 1;
-                } else {
-                    line.insert(idx, ')');
-                    cursor.xy.0 += 1;
-                }
-            }
-        
-            ']' => {
-                if next_char_is(']', cursor, line) {
-                    cursor.xy.0 += 1;
-                } else {
-                    line.insert(idx, ']');
-                    cursor.xy.0 += 1;
-                }
-            }
-        
-            '}' => {
-                if next_char_is('}', cursor, line) {
-                    cursor.xy.0 += 1;
-                } else {
-                    line.insert(idx, '}');
-                    cursor.xy.0 += 1;
-                }
-            }
+            // recognize_cursor_word(cursor, &text[cursor.xy.1]);
+        } else if elk.brackets.iter().any(|(_, close)| *close == c) && next_char_is(c, cursor, line) {
+            cursor.xy.0 += 1;
+        } else {
+            line.insert(idx, c);
+            cursor.xy.0 += 1;
+        }
 
-            _ => {
-                line.insert(idx, c);
-                cursor.xy.0 += 1;
-            }
+        history.end(text, cursor);
 
+        // Whitespace closes the typed word as one undo step
+        if c.is_whitespace() {
+            history.seal();
         }
+
+        completion.update(text, cursor, elk, buffers);
         
         audio.play_insert();
     }
 }
 
+/// Snapshot the selected lines, or the cursor's line if
+/// there is no selection, before editing them
+fn begin_selection_edit(
+    cursor: &EditorCursor,
+    text: &[String],
+    history: &mut EditorHistory,
+    kind: EditorEditKind
+) {
+    match selection_range(cursor, text) {
+        Some((start, end)) => history.begin(text, cursor, start.1, end.1 - start.1 + 1, kind),
+        None => history.begin(text, cursor, cursor.xy.1, 1, kind),
+    }
+}
+
 /// Check the character next to the cursor, for autocomplete
 /// issues
 fn next_char_is(c: char, cursor: &EditorCursor, line: &str) -> bool {
@@ -1,175 +1,397 @@
-use std::collections::HashSet;
+// Language definitions
+//
+// Languages are defined by .lang files, shipped in assets/languages
+// and added by users in a languages directory next to user.pref,
+// a user file replaces the shipped one of the same name.
+// One <key> = <value> per line, values are whitespace separated,
+// lines starting with '#' are comments:
+//      name = <display name>
+//      extensions = rs
+//      filenames = Makefile GNUmakefile
+//      interpreters = python3 python : Shebang interpreters, #!/usr/bin/env python3
+//      control_flow, storage_class, type_qualifiers,
+//      composite_types, misc, data_types = <keywords>
+//      line_comment = //
+//      block_comment = /* */
+//      nested_comments = true|false
+//      strings = "                 : Strings with escapes
+//      chars = '                   : Character literals
+//      raw_strings = `             : Strings without escapes, spanning lines
+//      triple_strings = """ '''     : Strings spanning lines
+//      raw_string_prefixes = r br  : Rust style r"..", r#".."#
+//      escape = \
+//      multiline_strings = true|false
+//      lifetimes = true|false      : 'a is a lifetime, not a char literal
+//      macro_prefix = #            : #include, #[derive(..)]
+//      macro_suffix = !            : println!
+//      number_prefixes = 0x 0b 0o
+//      digit_separator = _
+//      brackets = () [] {}
+//      symbols = fn struct #define : Keywords naming the symbol after them, for <:ol>
+//      symbol_headers = impl       : Symbols named by their whole header, impl<T> Foo for Bar
+//      function_declarations = true|false : C style functions, int main(..) {
+// Files are matched by whole name, then extension, then shebang,
+// without a matching definition they get plain text. <:ela> forces
+// a buffer's language when that guess is wrong.
+// Definitions are read once, and again by <:ela> or when a .lang file
+// is saved, broken ones are left out and reported at startup and after <:ela>.
 
-#[derive(Debug, Clone)]
-pub struct EditorLanguageKeywords {
-    pub control_flow: Vec<&'static str>,
-    pub storage_class: Vec<&'static str>,
-    pub type_qualifiers: Vec<&'static str>,
-    pub composite_types: Vec<&'static str>,
-    pub misc: Vec<&'static str>,
-    pub data_types: Vec<&'static str>,
-    pub _file_ids: Vec<String>,
-}
-
-pub fn cpp_keywords() -> EditorLanguageKeywords {
-    EditorLanguageKeywords {
-        control_flow: vec![
-            "if", "else", "switch", "case", "default",
-            "for", "while", "do", "break", "continue",
-            "goto", "return", "try", "catch", "finally",
-        ],
-        storage_class: vec![
-            "auto", "static", "extern", "register", "typedef",
-            "mutable", "constexpr", "thread_local",
-        ],
-        type_qualifiers: vec![
-            "const", "volatile", "restrict", "constexpr",
-        ],
-        composite_types: vec![
-            "struct", "union", "enum", "class",
-        ],
-        misc: vec![
-            "sizeof", "inline", "virtual", "explicit",
-            "namespace", "using", "operator", "template",
-            "typename", "friend",
-        ],
-        data_types: vec![
-            "int", "float", "double", "char", "void",
-            "short", "long", "unsigned", "bool",
-        ],
-        _file_ids: vec![],
-    }
-}
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
-pub fn java_keywords() -> EditorLanguageKeywords {
-    EditorLanguageKeywords {
-        control_flow: vec![
-            "if", "else", "switch", "case", "default",
-            "for", "while", "do", "break", "continue",
-            "return", "try", "catch", "finally", "throw",
-            "throws",
-        ],
-        storage_class: vec![
-            "final", "abstract", "native", "static",
-            "strictfp",
-        ],
-        type_qualifiers: vec![
-            "volatile", "synchronized",
-        ],
-        composite_types: vec![
-            "class", "interface", "enum", "record",
-        ],
-        misc: vec![
-            "import", "package", "new", "instanceof",
-            "extends", "implements",
-        ],
-        data_types: vec![
-            "int", "float", "double", "boolean", "char",
-            "short", "long", "byte",
-        ],
-        _file_ids: vec![],
-    }
-}
+use once_cell::sync::Lazy;
 
-pub fn rust_keywords() -> EditorLanguageKeywords {
-    EditorLanguageKeywords {
-        control_flow: vec![
-            "if", "else", "match", "loop", "while", "for",
-            "break", "continue", "return",
-        ],
-        storage_class: vec![
-            "static", "const", "mut",
-        ],
-        type_qualifiers: vec![
-            "ref", "mut", "unsafe",  "fn"
-        ],
-        composite_types: vec![
-            "struct", "enum", "trait", "impl", "union",
-        ],
-        misc: vec![
-            "crate", "super", "self", "pub", "use",
-            "mod", "async", "await", "dyn",
-        ],
-        data_types: vec![
-            "i8","i16","i32","i64","i128","isize",
-            "u8","u16","u32","u64","u128","usize",
-            "f32","f64","bool","char","str", "String"
-        ],
-        _file_ids: vec![],
-    }
+use crate::options::editor_paths::editor_data_file;
+
+pub const ASSET_LANGUAGES_DIR: &str = "assets/languages";
+pub const USER_LANGUAGES_DIR: &str = "languages";
+pub const LANGUAGE_EXTENSION: &str = "lang";
+
+/// Valid definitions and the errors of broken ones, None until the first read
+type EditorLanguageList = Option<(Arc<Vec<EditorLanguageKeywords>>, Vec<String>)>;
+
+static LANGUAGES: Lazy<RwLock<EditorLanguageList>> = Lazy::new(|| RwLock::new(None));
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorLanguageKeywords {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    pub interpreters: Vec<String>,
+    pub control_flow: Vec<String>,
+    pub storage_class: Vec<String>,
+    pub type_qualifiers: Vec<String>,
+    pub composite_types: Vec<String>,
+    pub misc: Vec<String>,
+    pub data_types: Vec<String>,
+    pub line_comment: Vec<String>,
+    /// Opening and closing delimiter
+    pub block_comment: Option<(String, String)>,
+    pub nested_comments: bool,
+    pub strings: Vec<char>,
+    pub chars: Vec<char>,
+    pub raw_strings: Vec<char>,
+    pub triple_strings: Vec<String>,
+    pub raw_string_prefixes: Vec<String>,
+    pub escape: Option<char>,
+    pub multiline_strings: bool,
+    pub lifetimes: bool,
+    pub macro_prefix: Option<char>,
+    pub macro_suffix: Option<char>,
+    pub number_prefixes: Vec<String>,
+    pub digit_separator: Option<char>,
+    /// Opening and closing bracket, auto-paired while typing
+    pub brackets: Vec<(char, char)>,
+    /// Keywords and macros introducing an outline symbol
+    pub symbols: Vec<String>,
+    pub symbol_headers: Vec<String>,
+    pub function_declarations: bool,
 }
 
-/// Load keywords for extensions based on the upper functions
-pub fn load_keywords_for_extension(
-    ext: &str
-) -> EditorLanguageKeywords {
-    match ext {
-        "c" | "h" | "cpp" | "hpp" | "cc" => cpp_keywords(),
-        "java" => java_keywords(),
-        "rs" => rust_keywords(),
-        _ => EditorLanguageKeywords {
+impl EditorLanguageKeywords {
+    /// Plain text, no keywords or comments, the usual brackets and quotes still pair
+    pub fn plain_text() -> EditorLanguageKeywords {
+        EditorLanguageKeywords {
+            name: "Plain Text".to_string(),
+            extensions: vec![],
+            filenames: vec![],
+            interpreters: vec![],
             control_flow: vec![],
             storage_class: vec![],
             type_qualifiers: vec![],
             composite_types: vec![],
             misc: vec![],
             data_types: vec![],
-            _file_ids: vec![],
+            line_comment: vec![],
+            block_comment: None,
+            nested_comments: false,
+            strings: vec!['"'],
+            chars: vec![],
+            raw_strings: vec![],
+            triple_strings: vec![],
+            raw_string_prefixes: vec![],
+            escape: Some('\\'),
+            multiline_strings: false,
+            lifetimes: false,
+            macro_prefix: None,
+            macro_suffix: None,
+            number_prefixes: vec!["0x".to_string(), "0b".to_string(), "0o".to_string()],
+            digit_separator: None,
+            brackets: vec![('(', ')'), ('[', ']'), ('{', '}')],
+            symbols: vec![],
+            symbol_headers: vec![],
+            function_declarations: false,
         }
     }
-}
 
-/// Check if a token is a keyword
-/// return true if it is, false if not
-pub fn _is_keyword(
-    token: &str,
-    elk: &EditorLanguageKeywords
-) -> bool {
-    if 
-        elk.control_flow.contains(&token)
-        || elk.storage_class.contains(&token)
-        || elk.type_qualifiers.contains(&token)
-        || elk.composite_types.contains(&token)
-        || elk.misc.contains(&token)
-        || elk.data_types.contains(&token) {
-            return false;
+    /// Quotes closed while typing, char literals only if they can't be lifetimes
+    pub fn auto_closed_quotes(&self) -> impl Iterator<Item = &char> {
+        let chars: &[char] = if self.lifetimes { &[] } else { &self.chars };
+
+        self.strings.iter().chain(chars).chain(&self.raw_strings)
     }
-     
-    true
-}
-
-/// Tokenize text file
-/// from line-major to 
-/// word-major, return the 
-/// text file's tokens
-pub fn _tokenize_text_file(
-    text: &Vec<String>
-) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut seen = HashSet::new();
-
-    for line in text {
-        for word in line.split_whitespace() {
-            if seen.insert(word) {
-                tokens.push(word.to_string());
+}
+
+/// Parse a language definition file
+pub fn parse_language(
+    content: &str
+) -> Result<EditorLanguageKeywords, String> {
+    let mut elk = EditorLanguageKeywords::plain_text();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("MalformedLanguage at line {}", i + 1));
+        };
+
+        let value = value.trim();
+        let words = || value.split_whitespace().map(|w| w.to_string()).collect::<Vec<String>>();
+
+        match key.trim() {
+            "name" => elk.name = value.to_string(),
+            "extensions" => elk.extensions = words(),
+            "filenames" => elk.filenames = words(),
+            "interpreters" => elk.interpreters = words(),
+            "control_flow" => elk.control_flow = words(),
+            "storage_class" => elk.storage_class = words(),
+            "type_qualifiers" => elk.type_qualifiers = words(),
+            "composite_types" => elk.composite_types = words(),
+            "misc" => elk.misc = words(),
+            "data_types" => elk.data_types = words(),
+            "line_comment" => elk.line_comment = words(),
+
+            "block_comment" => {
+                elk.block_comment = match words().as_slice() {
+                    [] => None,
+                    [open, close] => Some((open.clone(), close.clone())),
+                    _ => return Err(format!("InvalidBlockComment, expected <open> <close> at line {}", i + 1)),
+                };
+            }
+
+            "nested_comments" => elk.nested_comments = parse_bool(value, i)?,
+            "strings" => elk.strings = parse_chars(value, i)?,
+            "chars" => elk.chars = parse_chars(value, i)?,
+            "raw_strings" => elk.raw_strings = parse_chars(value, i)?,
+            "triple_strings" => elk.triple_strings = words(),
+            "raw_string_prefixes" => elk.raw_string_prefixes = words(),
+            "escape" => elk.escape = parse_chars(value, i)?.first().copied(),
+            "multiline_strings" => elk.multiline_strings = parse_bool(value, i)?,
+            "lifetimes" => elk.lifetimes = parse_bool(value, i)?,
+            "macro_prefix" => elk.macro_prefix = parse_chars(value, i)?.first().copied(),
+            "macro_suffix" => elk.macro_suffix = parse_chars(value, i)?.first().copied(),
+            "number_prefixes" => elk.number_prefixes = words(),
+            "digit_separator" => elk.digit_separator = parse_chars(value, i)?.first().copied(),
+
+            "brackets" => {
+                elk.brackets = value.split_whitespace()
+                    .map(|w| {
+                        let chars: Vec<char> = w.chars().collect();
+
+                        match chars.as_slice() {
+                            [open, close] => Ok((*open, *close)),
+                            _ => Err(format!("InvalidBracketPair '{}' at line {}", w, i + 1)),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
             }
+
+            "symbols" => elk.symbols = words(),
+            "symbol_headers" => elk.symbol_headers = words(),
+            "function_declarations" => elk.function_declarations = parse_bool(value, i)?,
+
+            key => return Err(format!("UnknownLanguageKey '{}' at line {}", key, i + 1)),
         }
     }
 
-    tokens
+    Ok(elk)
+}
+
+/// Single characters separated by whitespace
+fn parse_chars(
+    value: &str,
+    i: usize
+) -> Result<Vec<char>, String> {
+    value.split_whitespace()
+        .map(|w| {
+            let mut chars = w.chars();
+
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(format!("InvalidDelimiter '{}' at line {}", w, i + 1)),
+            }
+        })
+        .collect()
 }
 
-/// Recognize identifiers
-/// from the tokenized file text
-/// pass the result into the
-/// ELK's file identifier field. 
-pub fn _recognize_identifiers(
-    tokens: Vec<String>,
-    elk: &mut EditorLanguageKeywords
-) {
-    for s in tokens {
-        if _is_keyword(&s, elk) {
-            elk._file_ids.push(s);
+fn parse_bool(
+    value: &str,
+    i: usize
+) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" | "" => Ok(false),
+        _ => Err(format!("InvalidBool '{}' at line {}", value, i + 1)),
+    }
+}
+
+/// Read the definitions again, user files replace shipped files of the same name,
+/// returns an error message for every broken one
+pub fn refresh_languages() -> Vec<String> {
+    let mut files: Vec<(String, &str, PathBuf)> = Vec::new();
+
+    for shown in [USER_LANGUAGES_DIR, ASSET_LANGUAGES_DIR] {
+        let Ok(entries) = fs::read_dir(editor_data_file(shown)) else {
+            continue;
+        };
+
+        let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+        paths.sort();
+
+        for path in paths {
+            if path.extension().and_then(|e| e.to_str()) != Some(LANGUAGE_EXTENSION) {
+                continue;
+            }
+
+            let Some(stem) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+                continue;
+            };
+
+            if !files.iter().any(|(name, _, _)| *name == stem) {
+                files.push((stem, shown, path));
+            }
         }
     }
+
+    let mut languages = Vec::new();
+    let mut errors = Vec::new();
+
+    // Broken definitions are left out, their files fall back to plain text
+    for (stem, shown, path) in files {
+        let file = format!("{}/{}.{}", shown, stem, LANGUAGE_EXTENSION);
+
+        match fs::read_to_string(&path) {
+            Ok(content) => match parse_language(&content) {
+                Ok(elk) => languages.push(elk),
+                Err(e) => errors.push(format!("{} <{}>", e, file)),
+            },
+            Err(_) => errors.push(format!("LanguageNotReadable <{}>", file)),
+        }
+    }
+
+    *LANGUAGES.write().unwrap_or_else(|e| e.into_inner()) = Some((Arc::new(languages), errors.clone()));
+
+    errors
+}
+
+/// The read definitions and their errors, reading them on first use
+fn languages() -> (Arc<Vec<EditorLanguageKeywords>>, Vec<String>) {
+    if LANGUAGES.read().unwrap_or_else(|e| e.into_inner()).is_none() {
+        refresh_languages();
+    }
+
+    LANGUAGES.read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Every valid definition
+pub fn list_languages() -> Arc<Vec<EditorLanguageKeywords>> {
+    languages().0
+}
+
+/// Names of the valid definitions, for <:ela> completions
+pub fn list_language_names() -> Vec<String> {
+    list_languages().iter().map(|l| l.name.clone()).collect()
+}
+
+/// Errors of the broken definitions, as of the last read
+pub fn language_errors() -> Vec<String> {
+    languages().1
+}
+
+/// Load the definition for a file, matched by whole name first,
+/// then extension, then the interpreter of a shebang first line
+pub fn load_keywords_for_file_name(
+    file_name: &str,
+    first_line: Option<&str>
+) -> EditorLanguageKeywords {
+    load_keywords_for_file_name_in(file_name, first_line, &list_languages())
+}
+
+/// `load_keywords_for_file_name` among already listed definitions
+pub fn load_keywords_for_file_name_in(
+    file_name: &str,
+    first_line: Option<&str>,
+    languages: &[EditorLanguageKeywords]
+) -> EditorLanguageKeywords {
+    if let Some(elk) = languages.iter().find(|l| l.filenames.iter().any(|f| f == file_name)) {
+        return elk.clone();
+    }
+
+    let ext = file_name.rsplit_once('.').map_or("", |(_, ext)| ext);
+
+    if let Some(elk) = languages.iter().find(|l| l.extensions.iter().any(|e| e == ext)) {
+        return elk.clone();
+    }
+
+    let interpreter = first_line.and_then(shebang_interpreter);
+
+    interpreter
+        .and_then(|interpreter| languages.iter().find(|l| l.interpreters.contains(&interpreter)))
+        .cloned()
+        .unwrap_or_else(EditorLanguageKeywords::plain_text)
+}
+
+/// The interpreter of a shebang line, #!/bin/sh and #!/usr/bin/env -S python3 -u give sh and python3
+fn shebang_interpreter(
+    line: &str
+) -> Option<String> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-'))?;
+    }
+
+    Some(program.to_string())
+}
+
+/// Find a definition by name or extension, case insensitive, e.g. "python" or "py"
+pub fn find_language(
+    name: &str
+) -> Option<EditorLanguageKeywords> {
+    let name = name.to_lowercase();
+
+    list_languages().iter()
+        .find(|l| l.name.to_lowercase() == name || l.extensions.iter().any(|e| e.to_lowercase() == name))
+        .cloned()
+}
+
+/// Load the definition for an extension
+pub fn load_keywords_for_extension(
+    ext: &str
+) -> EditorLanguageKeywords {
+    if ext.is_empty() {
+        return EditorLanguageKeywords::plain_text();
+    }
+
+    list_languages().iter()
+        .find(|l| l.extensions.iter().any(|e| e == ext))
+        .cloned()
+        .unwrap_or_else(EditorLanguageKeywords::plain_text)
+}
+
+/// Identifiers of a line, words of letters, digits and '_' not starting with a digit
+pub fn identifiers(
+    line: &str
+) -> impl Iterator<Item = &str> {
+    line.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.starts_with(|c: char| !c.is_ascii_digit()))
 }
@@ -0,0 +1,591 @@
+// Lexer module
+//
+// Splits a line into tokens by the rules of its language definition,
+// comments, string kinds, escapes, nested comments and number formats.
+// Lines are lexed one at a time, the state a line ends in (an open block
+// comment or string) is where the next line starts. Nothing here draws,
+// the renderer picks a colour per token kind.
+//
+// EditorLexCache keeps every line's tokens and end state, so lines are
+// only lexed again from the first one that changed, and a line in the
+// middle of a block comment is coloured right at any scroll position.
+
+use crate::text::editor_language_manager::EditorLanguageKeywords;
+
+/// Keyword classes of a language definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorKeywordClass {
+    ControlFlow,
+    StorageClass,
+    TypeQualifier,
+    CompositeType,
+    Misc,
+    DataType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorTokenKind {
+    Whitespace,
+    Identifier,
+    Keyword(EditorKeywordClass),
+    Number,
+    String,
+    Comment,
+    Macro,
+    Lifetime,
+    Punctuation,
+}
+
+/// A token of a line, start and end are char indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditorToken {
+    pub kind: EditorTokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What a line starts inside of
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum EditorLexState {
+    #[default]
+    Code,
+    BlockComment {
+        depth: usize,
+    },
+    String {
+        close: String,
+        escapes: bool,
+        multiline: bool,
+    },
+}
+
+/// Lex a line starting in `state`, returns its tokens and the state the next line starts in
+pub fn lex_line(
+    line: &str,
+    state: &EditorLexState,
+    elk: &EditorLanguageKeywords
+) -> (Vec<EditorToken>, EditorLexState) {
+    let chars: Vec<char> = line.chars().collect();
+    let mut lexer = EditorLexer { chars: &chars, i: 0, state: state.clone(), elk, continued: false };
+    let mut tokens = Vec::new();
+
+    while lexer.i < chars.len() {
+        let start = lexer.i;
+
+        let kind = match lexer.state {
+            EditorLexState::Code => lexer.code(),
+            EditorLexState::BlockComment { .. } => lexer.block_comment(),
+            EditorLexState::String { .. } => lexer.string(),
+        };
+
+        tokens.push(EditorToken { kind, start, end: lexer.i });
+    }
+
+    // Single line strings end with the line, unless it ends in an escape
+    let mut state = lexer.state;
+
+    if let EditorLexState::String { multiline: false, .. } = state && !lexer.continued {
+        state = EditorLexState::Code;
+    }
+
+    (tokens, state)
+}
+
+/// A lexed line, kept while its text and start state stay the same
+#[derive(Debug, Clone)]
+struct EditorLexedLine {
+    text: String,
+    tokens: Vec<EditorToken>,
+    end: EditorLexState,
+}
+
+/// Tokens and end states of a file's lines, from the first line down
+#[derive(Debug, Clone, Default)]
+pub struct EditorLexCache {
+    language: Option<EditorLanguageKeywords>,
+    lines: Vec<EditorLexedLine>,
+}
+
+impl EditorLexCache {
+    pub fn new() -> EditorLexCache {
+        EditorLexCache::default()
+    }
+
+    /// Drop every line from `line` downward
+    pub fn invalidate_from(
+        &mut self,
+        line: usize
+    ) {
+        self.lines.truncate(line);
+    }
+
+    /// Bring lines up to `last` up to date, the first line whose text changed
+    /// and every line below it are lexed again, lines past `last` are left for later
+    pub fn update(
+        &mut self,
+        text: &[String],
+        last: usize,
+        elk: &EditorLanguageKeywords
+    ) {
+        if self.language.as_ref() != Some(elk) {
+            self.language = Some(elk.clone());
+            self.lines.clear();
+        }
+
+        let last = last.min(text.len().saturating_sub(1));
+
+        let changed = self.lines.iter()
+            .zip(text)
+            .position(|(cached, line)| cached.text != *line)
+            .unwrap_or(self.lines.len().min(text.len()));
+
+        self.invalidate_from(changed);
+
+        while self.lines.len() <= last && self.lines.len() < text.len() {
+            let index = self.lines.len();
+            let state = self.lines.last().map(|l| l.end.clone()).unwrap_or_default();
+            let (tokens, end) = lex_line(&text[index], &state, elk);
+
+            self.lines.push(EditorLexedLine { text: text[index].clone(), tokens, end });
+        }
+    }
+
+    /// Tokens of a line brought up to date by `update`
+    pub fn tokens(
+        &self,
+        line: usize
+    ) -> &[EditorToken] {
+        self.lines.get(line).map_or(&[], |l| l.tokens.as_slice())
+    }
+}
+
+/// Classify an identifier by the keyword classes of the language
+pub fn keyword_class(
+    word: &str,
+    elk: &EditorLanguageKeywords
+) -> Option<EditorKeywordClass> {
+    let classes = [
+        (&elk.control_flow, EditorKeywordClass::ControlFlow),
+        (&elk.type_qualifiers, EditorKeywordClass::TypeQualifier),
+        (&elk.composite_types, EditorKeywordClass::CompositeType),
+        (&elk.storage_class, EditorKeywordClass::StorageClass),
+        (&elk.misc, EditorKeywordClass::Misc),
+        (&elk.data_types, EditorKeywordClass::DataType),
+    ];
+
+    classes.into_iter()
+        .find(|(words, _)| words.iter().any(|w| w == word))
+        .map(|(_, class)| class)
+}
+
+fn is_identifier_char(
+    c: char
+) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+struct EditorLexer<'a> {
+    chars: &'a [char],
+    i: usize,
+    state: EditorLexState,
+    elk: &'a EditorLanguageKeywords,
+    /// The line ends in an escape inside a string, "abc\ but not "abc\\
+    continued: bool,
+}
+
+impl EditorLexer<'_> {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.i + offset).copied()
+    }
+
+    fn starts_with(&self, pattern: &str) -> bool {
+        !pattern.is_empty()
+            && pattern.chars().enumerate().all(|(k, p)| self.peek(k) == Some(p))
+    }
+
+    fn take_while(&mut self, keep: impl Fn(char) -> bool) {
+        while self.peek(0).is_some_and(&keep) {
+            self.i += 1;
+        }
+    }
+
+    /// A token starting outside of comments and strings
+    fn code(&mut self) -> EditorTokenKind {
+        let elk = self.elk;
+        let c = self.chars[self.i];
+        let previous = self.i.checked_sub(1).map(|p| self.chars[p]);
+
+        // Block comments first, their opener may start with a line comment, e.g. --[[
+        if let Some((open, _)) = &elk.block_comment && self.starts_with(open) {
+            self.i += open.chars().count();
+            self.state = EditorLexState::BlockComment { depth: 1 };
+            return self.block_comment();
+        }
+
+        if elk.line_comment.iter().any(|p| self.starts_with(p)) {
+            self.i = self.chars.len();
+            return EditorTokenKind::Comment;
+        }
+
+        if let Some(quotes) = elk.triple_strings.iter().find(|q| self.starts_with(q)) {
+            self.i += quotes.chars().count();
+            return self.open_string(quotes.clone(), elk.escape.is_some(), true);
+        }
+
+        if !previous.is_some_and(is_identifier_char) && let Some(hashes) = self.raw_string_start() {
+            return self.open_string(format!("\"{}", "#".repeat(hashes)), false, true);
+        }
+
+        if elk.raw_strings.contains(&c) {
+            self.i += 1;
+            return self.open_string(c.to_string(), false, true);
+        }
+
+        if elk.strings.contains(&c) {
+            self.i += 1;
+            return self.open_string(c.to_string(), elk.escape.is_some(), elk.multiline_strings);
+        }
+
+        if elk.chars.contains(&c) {
+            // 'a is a lifetime or label, unless it closes like 'a'
+            if elk.lifetimes && self.peek(1).is_some_and(is_identifier_char) {
+                let mut end = self.i + 1;
+
+                while self.chars.get(end).is_some_and(|&n| is_identifier_char(n)) {
+                    end += 1;
+                }
+
+                if self.chars.get(end) != Some(&c) {
+                    self.i = end;
+                    return EditorTokenKind::Lifetime;
+                }
+            }
+
+            self.i += 1;
+            return self.open_string(c.to_string(), elk.escape.is_some(), false);
+        }
+
+        if elk.macro_prefix == Some(c) {
+            self.take_while(|ch| !ch.is_whitespace());
+            return EditorTokenKind::Macro;
+        }
+
+        if c.is_whitespace() {
+            self.take_while(|ch| ch.is_whitespace());
+            return EditorTokenKind::Whitespace;
+        }
+
+        let fraction_start = c == '.'
+            && self.peek(1).is_some_and(|n| n.is_ascii_digit())
+            && !previous.is_some_and(|p| is_identifier_char(p) || p == '.');
+
+        if c.is_ascii_digit() || fraction_start {
+            self.number();
+            return EditorTokenKind::Number;
+        }
+
+        if is_identifier_char(c) {
+            let start = self.i;
+            self.take_while(is_identifier_char);
+
+            // println!, but not a != b
+            if let Some(suffix) = elk.macro_suffix && self.peek(0) == Some(suffix) && self.peek(1) != Some('=') {
+                self.i += 1;
+                return EditorTokenKind::Macro;
+            }
+
+            let word: String = self.chars[start..self.i].iter().collect();
+
+            return keyword_class(&word, elk)
+                .map_or(EditorTokenKind::Identifier, EditorTokenKind::Keyword);
+        }
+
+        self.i += 1;
+        EditorTokenKind::Punctuation
+    }
+
+    /// r"..", r#".."#, br".." at the cursor, moves past the opening quote
+    /// and returns the number of '#'s the string closes with
+    fn raw_string_start(&mut self) -> Option<usize> {
+        for prefix in &self.elk.raw_string_prefixes {
+            if !self.starts_with(prefix) {
+                continue;
+            }
+
+            let after = self.i + prefix.chars().count();
+            let hashes = self.chars[after..].iter().take_while(|&&c| c == '#').count();
+
+            if self.chars.get(after + hashes) == Some(&'"') {
+                self.i = after + hashes + 1;
+                return Some(hashes);
+            }
+        }
+
+        None
+    }
+
+    fn open_string(
+        &mut self,
+        close: String,
+        escapes: bool,
+        multiline: bool
+    ) -> EditorTokenKind {
+        self.state = EditorLexState::String { close, escapes, multiline };
+        self.string()
+    }
+
+    /// The rest of a string, up to and including its closing delimiter
+    fn string(&mut self) -> EditorTokenKind {
+        let EditorLexState::String { close, escapes, .. } = &self.state else {
+            return EditorTokenKind::String;
+        };
+
+        let (close, escapes) = (close.clone(), *escapes);
+
+        while self.i < self.chars.len() {
+            if escapes && Some(self.chars[self.i]) == self.elk.escape {
+                self.continued = self.i + 1 == self.chars.len();
+                self.i = (self.i + 2).min(self.chars.len());
+                continue;
+            }
+
+            if self.starts_with(&close) {
+                self.i += close.chars().count();
+                self.state = EditorLexState::Code;
+                break;
+            }
+
+            self.i += 1;
+        }
+
+        EditorTokenKind::String
+    }
+
+    /// The rest of a block comment, nested ones only close with their outermost closer
+    fn block_comment(&mut self) -> EditorTokenKind {
+        let Some((open, close)) = &self.elk.block_comment else {
+            self.state = EditorLexState::Code;
+            return EditorTokenKind::Comment;
+        };
+
+        let EditorLexState::BlockComment { mut depth } = self.state else {
+            return EditorTokenKind::Comment;
+        };
+
+        while self.i < self.chars.len() {
+            if self.starts_with(close) {
+                self.i += close.chars().count();
+                depth -= 1;
+
+                if depth == 0 {
+                    self.state = EditorLexState::Code;
+                    return EditorTokenKind::Comment;
+                }
+            } else if self.elk.nested_comments && self.starts_with(open) {
+                self.i += open.chars().count();
+                depth += 1;
+            } else {
+                self.i += 1;
+            }
+        }
+
+        self.state = EditorLexState::BlockComment { depth };
+        EditorTokenKind::Comment
+    }
+
+    /// 0xFF, 0b1010, 1_000, 3.14, 1e-3, .5f, 10u32
+    fn number(&mut self) {
+        let separator = self.elk.digit_separator;
+
+        if let Some(prefix) = self.elk.number_prefixes.iter().find(|p| self.starts_with(p)) {
+            self.i += prefix.chars().count();
+            self.take_while(|c| c.is_ascii_alphanumeric() || Some(c) == separator);
+            return;
+        }
+
+        self.take_while(|c| c.is_ascii_digit() || Some(c) == separator);
+
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.i += 1;
+            self.take_while(|c| c.is_ascii_digit() || Some(c) == separator);
+        }
+
+        if matches!(self.peek(0), Some('e' | 'E')) {
+            let digits_at = if matches!(self.peek(1), Some('+' | '-')) { 2 } else { 1 };
+
+            if self.peek(digits_at).is_some_and(|c| c.is_ascii_digit()) {
+                self.i += digits_at;
+                self.take_while(|c| c.is_ascii_digit() || Some(c) == separator);
+            }
+        }
+
+        // Type suffixes, 1.0f, 10ULL, 7u8
+        self.take_while(is_identifier_char);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::editor_language_manager::parse_language;
+
+    fn language(
+        definition: &str
+    ) -> EditorLanguageKeywords {
+        parse_language(definition).unwrap()
+    }
+
+    fn rust() -> EditorLanguageKeywords {
+        language(include_str!("../../assets/languages/rust.lang"))
+    }
+
+    fn cpp() -> EditorLanguageKeywords {
+        language(include_str!("../../assets/languages/cpp.lang"))
+    }
+
+    fn python() -> EditorLanguageKeywords {
+        language(include_str!("../../assets/languages/python.lang"))
+    }
+
+    /// Tokens of a line as their kind and text, whitespace left out
+    fn lex(
+        line: &str,
+        state: &EditorLexState,
+        elk: &EditorLanguageKeywords
+    ) -> (Vec<(EditorTokenKind, String)>, EditorLexState) {
+        let chars: Vec<char> = line.chars().collect();
+        let (tokens, end) = lex_line(line, state, elk);
+
+        let tokens = tokens.into_iter()
+            .filter(|t| t.kind != EditorTokenKind::Whitespace)
+            .map(|t| (t.kind, chars[t.start..t.end].iter().collect()))
+            .collect();
+
+        (tokens, end)
+    }
+
+    fn token(
+        kind: EditorTokenKind,
+        text: &str
+    ) -> (EditorTokenKind, String) {
+        (kind, text.to_string())
+    }
+
+    #[test]
+    fn python_comments_start_with_a_hash() {
+        let (tokens, end) = lex("x = \"#\"  # note", &EditorLexState::Code, &python());
+
+        assert_eq!(tokens, vec![
+            token(EditorTokenKind::Identifier, "x"),
+            token(EditorTokenKind::Punctuation, "="),
+            token(EditorTokenKind::String, "\"#\""),
+            token(EditorTokenKind::Comment, "# note"),
+        ]);
+        assert_eq!(end, EditorLexState::Code);
+    }
+
+    #[test]
+    fn block_comments_nest_only_where_the_language_says() {
+        let line = "/* a /* b */ c */ x";
+
+        let (tokens, end) = lex(line, &EditorLexState::Code, &rust());
+        assert_eq!(tokens, vec![
+            token(EditorTokenKind::Comment, "/* a /* b */ c */"),
+            token(EditorTokenKind::Identifier, "x"),
+        ]);
+        assert_eq!(end, EditorLexState::Code);
+
+        let (tokens, _) = lex(line, &EditorLexState::Code, &cpp());
+        assert_eq!(tokens[0], token(EditorTokenKind::Comment, "/* a /* b */"));
+        assert_eq!(tokens[1], token(EditorTokenKind::Identifier, "c"));
+    }
+
+    #[test]
+    fn raw_strings_close_with_their_hashes() {
+        let (tokens, end) = lex("let s = r#\"say \"hi\" \\\"#; br\"\\d\"", &EditorLexState::Code, &rust());
+
+        assert_eq!(tokens, vec![
+            token(EditorTokenKind::Identifier, "let"),
+            token(EditorTokenKind::Identifier, "s"),
+            token(EditorTokenKind::Punctuation, "="),
+            token(EditorTokenKind::String, "r#\"say \"hi\" \\\"#"),
+            token(EditorTokenKind::Punctuation, ";"),
+            token(EditorTokenKind::String, "br\"\\d\""),
+        ]);
+        assert_eq!(end, EditorLexState::Code);
+    }
+
+    #[test]
+    fn char_literals_and_lifetimes() {
+        let (tokens, _) = lex("fn f<'a>(x: &'a str) -> char { 'x' } '\\n' 'outer: loop", &EditorLexState::Code, &rust());
+
+        let of_kind = |kind| tokens.iter().filter(|t| t.0 == kind).map(|t| t.1.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(of_kind(EditorTokenKind::Lifetime), vec!["'a", "'a", "'outer"]);
+        assert_eq!(of_kind(EditorTokenKind::String), vec!["'x'", "'\\n'"]);
+
+        // Without lifetimes every quote opens a char literal
+        let (tokens, _) = lex("'a' 'b", &EditorLexState::Code, &cpp());
+        assert_eq!(tokens, vec![
+            token(EditorTokenKind::String, "'a'"),
+            token(EditorTokenKind::String, "'b"),
+        ]);
+    }
+
+    #[test]
+    fn numbers_keep_prefixes_exponents_and_suffixes() {
+        let (tokens, _) = lex("0xFF 1e-3 1_000u32 3.14 .5 x1", &EditorLexState::Code, &rust());
+
+        assert_eq!(tokens, vec![
+            token(EditorTokenKind::Number, "0xFF"),
+            token(EditorTokenKind::Number, "1e-3"),
+            token(EditorTokenKind::Number, "1_000u32"),
+            token(EditorTokenKind::Number, "3.14"),
+            token(EditorTokenKind::Number, ".5"),
+            token(EditorTokenKind::Identifier, "x1"),
+        ]);
+    }
+
+    #[test]
+    fn block_comments_and_strings_carry_over_lines() {
+        let elk = rust();
+
+        let (_, end) = lex("let a = /* one /* two */", &EditorLexState::Code, &elk);
+        assert_eq!(end, EditorLexState::BlockComment { depth: 1 });
+
+        let (tokens, end) = lex("still */ b", &end, &elk);
+        assert_eq!(tokens, vec![
+            token(EditorTokenKind::Comment, "still */"),
+            token(EditorTokenKind::Identifier, "b"),
+        ]);
+        assert_eq!(end, EditorLexState::Code);
+
+        let elk = python();
+
+        let (_, end) = lex("s = \"\"\"doc", &EditorLexState::Code, &elk);
+        assert!(matches!(&end, EditorLexState::String { close, multiline: true, .. } if close == "\"\"\""));
+
+        let (tokens, end) = lex("more\"\"\" + x", &end, &elk);
+        assert_eq!(tokens[0], token(EditorTokenKind::String, "more\"\"\""));
+        assert_eq!(end, EditorLexState::Code);
+    }
+
+    #[test]
+    fn single_line_strings_continue_only_after_an_unescaped_escape() {
+        let elk = cpp();
+
+        let (_, end) = lex("s = \"abc\\", &EditorLexState::Code, &elk);
+        assert!(matches!(end, EditorLexState::String { multiline: false, .. }));
+
+        let (tokens, end) = lex("def\"; x", &end, &elk);
+        assert_eq!(tokens[0], token(EditorTokenKind::String, "def\""));
+        assert_eq!(end, EditorLexState::Code);
+
+        // The backslash is escaped, the string is just left open
+        let (_, end) = lex("s = \"abc\\\\", &EditorLexState::Code, &elk);
+        assert_eq!(end, EditorLexState::Code);
+
+        let (_, end) = lex("s = \"abc", &EditorLexState::Code, &elk);
+        assert_eq!(end, EditorLexState::Code);
+    }
+}
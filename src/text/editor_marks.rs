@@ -0,0 +1,152 @@
+// File markings module
+//
+// Marks are spots inside a file (<:mark>) the cursor can
+// step through (<:spot>) or jump to (<:spot N>).
+// They live in the cursor, so every buffer keeps its own,
+// follow the lines they were put on as lines get inserted
+// or deleted above them, and are saved per file in user.marks.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::options::editor_paths::editor_data_file;
+use crate::text::editor_cursor::EditorCursor;
+
+pub const MARKS_FILE: &str = "user.marks";
+
+/// Toggle a mark on the cursor's line,
+/// returns true if a mark was added, false if one was removed
+pub fn toggle_mark(
+    cursor: &mut EditorCursor
+) -> bool {
+    if let Some(i) = cursor.marks.iter().position(|m| m.1 == cursor.xy.1) {
+        cursor.marks.remove(i);
+        return false;
+    }
+
+    cursor.marks.push(cursor.xy);
+    cursor.marks.sort_by_key(|m| (m.1, m.0));
+
+    true
+}
+
+/// Move the cursor to the first mark below its line, wrapping around,
+/// returns the 1-based number of the mark, None if there are no marks
+pub fn next_spot(
+    cursor: &mut EditorCursor
+) -> Option<usize> {
+    if cursor.marks.is_empty() {
+        return None;
+    }
+
+    // Relative to the cursor's line, so moving around between
+    // :spot directives continues from where the cursor is
+    let next = cursor.marks.iter()
+        .position(|m| m.1 > cursor.xy.1)
+        .unwrap_or(0);
+
+    cursor.xy = cursor.marks[next];
+
+    Some(next + 1)
+}
+
+/// Move the cursor to the 1-based N-th mark, returns false if there is no such mark
+pub fn goto_spot(
+    cursor: &mut EditorCursor,
+    n: usize
+) -> bool {
+    if n == 0 || n > cursor.marks.len() {
+        return false;
+    }
+
+    cursor.xy = cursor.marks[n - 1];
+
+    true
+}
+
+/// Keep marks on their lines after `removed` lines at `line` were replaced
+/// by `inserted` lines, marks on deleted lines move to the line that followed
+pub fn shift_marks(
+    cursor: &mut EditorCursor,
+    line: usize,
+    removed: usize,
+    inserted: usize
+) {
+    if cursor.marks.is_empty() {
+        return;
+    }
+
+    for mark in cursor.marks.iter_mut() {
+        if mark.1 >= line + removed {
+            mark.1 = mark.1 - removed + inserted;
+        } else if mark.1 >= line + inserted {
+            mark.1 = line + inserted;
+            mark.0 = 0;
+        }
+    }
+
+    // Deleted lines may have merged two marks into one
+    cursor.marks.sort_by_key(|m| (m.1, m.0));
+    cursor.marks.dedup_by_key(|m| m.1);
+}
+
+/// Read all stored marks, one file per line:
+/// <path>\t<x>,<y>;<x>,<y>...
+fn read_marks_file() -> Vec<(PathBuf, Vec<(usize, usize)>)> {
+    let Ok(content) = fs::read_to_string(editor_data_file(MARKS_FILE)) else {
+        return vec![];
+    };
+
+    content.lines()
+        .filter_map(|line| {
+            let (path, spots) = line.split_once('\t')?;
+            let marks = spots.split(';')
+                .filter_map(|spot| {
+                    let (x, y) = spot.split_once(',')?;
+                    Some((x.parse().ok()?, y.parse().ok()?))
+                })
+                .collect();
+
+            Some((PathBuf::from(path), marks))
+        })
+        .collect()
+}
+
+/// Load the stored marks of a file, the file may have changed outside
+/// the editor, so marks past its end are dropped and the rest clamped to `text`
+pub fn load_marks(
+    file: &Path,
+    text: &[String]
+) -> Vec<(usize, usize)> {
+    read_marks_file()
+        .into_iter()
+        .find(|(path, _)| path == file)
+        .map(|(_, marks)| marks)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|m| m.1 < text.len())
+        .map(|(x, y)| (x.min(text[y].chars().count()), y))
+        .collect()
+}
+
+/// Store the marks of a file, replacing the old ones
+pub fn save_marks(
+    file: &Path,
+    marks: &[(usize, usize)]
+) -> std::io::Result<()> {
+    let mut stored = read_marks_file();
+    stored.retain(|(path, _)| path != file);
+
+    if !marks.is_empty() {
+        stored.push((file.to_path_buf(), marks.to_vec()));
+    }
+
+    let content: String = stored.iter()
+        .map(|(path, marks)| {
+            let spots: Vec<String> = marks.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+            format!("{}\t{}\n", path.display(), spots.join(";"))
+        })
+        .collect();
+
+    fs::write(editor_data_file(MARKS_FILE), content)
+}
@@ -0,0 +1,308 @@
+// Symbol outline module, used by the <:ol> directive.
+//
+// Finds the functions, types, impls and macros of a text from its
+// tokens, so nothing inside comments or strings is picked up.
+// What counts as a symbol comes from the language definition:
+//      symbols               : A keyword naming the identifier after it, fn main, #define MAX
+//      symbol_headers        : A keyword whose whole header is the name, impl<T> Foo for Bar
+//      function_declarations : A name and parameters after a type, int main(void) { .. }
+// Function declarations are only looked for outside function bodies,
+// calls inside them look just the same.
+
+use crate::text::editor_language_manager::EditorLanguageKeywords;
+use crate::text::editor_lexer::*;
+
+/// A symbol of a text, line and column are 0-based
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorSymbol {
+    /// The keyword it was found by, "fn" for function declarations
+    pub kind: String,
+    pub name: String,
+    pub line: usize,
+    pub column: usize,
+    /// Braces it is nested in
+    pub depth: usize,
+}
+
+impl EditorSymbol {
+    /// The kind and name, indented by depth, e.g. "  fn new"
+    pub fn label(&self) -> String {
+        let separator = if self.name.starts_with('<') { "" } else { " " };
+
+        format!("{}{}{}{}", "  ".repeat(self.depth), self.kind, separator, self.name)
+    }
+}
+
+/// A token with its text and line, comments and whitespace left out
+struct EditorOutlineToken {
+    kind: EditorTokenKind,
+    text: String,
+    line: usize,
+    start: usize,
+}
+
+impl EditorOutlineToken {
+    fn is(&self, punctuation: &str) -> bool {
+        self.kind == EditorTokenKind::Punctuation && self.text == punctuation
+    }
+}
+
+/// Every symbol of a text, in order
+pub fn outline_symbols(
+    text: &[String],
+    elk: &EditorLanguageKeywords
+) -> Vec<EditorSymbol> {
+    let tokens = outline_tokens(text, elk);
+    let mut symbols = Vec::new();
+
+    // Open braces, true for function bodies
+    let mut scopes: Vec<bool> = Vec::new();
+    let mut body_start = None;
+
+    // Tokens up to here belong to the last symbol, its name or parameters
+    let mut resume = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is("{") {
+            scopes.push(body_start == Some(i));
+            continue;
+        }
+
+        if token.is("}") {
+            scopes.pop();
+            continue;
+        }
+
+        if i < resume {
+            continue;
+        }
+
+        let depth = scopes.len();
+
+        if elk.symbol_headers.contains(&token.text) && is_keyword_like(token) {
+            if let Some(symbol) = header_symbol(&tokens, i, text, depth) {
+                symbols.push(symbol);
+            }
+
+            continue;
+        }
+
+        if elk.symbols.contains(&token.text) && is_keyword_like(token) {
+            if let Some(symbol) = keyword_symbol(&tokens, i, depth) {
+                symbols.push(symbol);
+                resume = i + 2;
+            }
+
+            continue;
+        }
+
+        if elk.function_declarations && !scopes.contains(&true)
+            && let Some((symbol, end, body)) = function_declaration(&tokens, i, depth) {
+            symbols.push(symbol);
+
+            if body {
+                body_start = Some(end);
+            }
+
+            resume = end;
+        }
+    }
+
+    symbols
+}
+
+/// Lex the whole text, keeping the tokens that matter to the outline
+fn outline_tokens(
+    text: &[String],
+    elk: &EditorLanguageKeywords
+) -> Vec<EditorOutlineToken> {
+    let mut tokens = Vec::new();
+    let mut state = EditorLexState::Code;
+
+    for (line_index, line) in text.iter().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let (line_tokens, end) = lex_line(line, &state, elk);
+
+        for token in line_tokens {
+            if matches!(token.kind, EditorTokenKind::Whitespace | EditorTokenKind::Comment) {
+                continue;
+            }
+
+            tokens.push(EditorOutlineToken {
+                kind: token.kind,
+                text: chars[token.start..token.end].iter().collect(),
+                line: line_index,
+                start: token.start,
+            });
+        }
+
+        state = end;
+    }
+
+    tokens
+}
+
+/// Keywords, macros and plain words can name symbols, strings and numbers can't
+fn is_keyword_like(
+    token: &EditorOutlineToken
+) -> bool {
+    matches!(token.kind, EditorTokenKind::Keyword(_) | EditorTokenKind::Macro | EditorTokenKind::Identifier)
+}
+
+/// fn name, struct Name, #define NAME, but not the type of a declaration, struct Foo *p;
+fn keyword_symbol(
+    tokens: &[EditorOutlineToken],
+    i: usize,
+    depth: usize
+) -> Option<EditorSymbol> {
+    let keyword = &tokens[i];
+    let name = tokens.get(i + 1).filter(|t| t.kind == EditorTokenKind::Identifier)?;
+
+    if keyword.kind != EditorTokenKind::Macro
+        && let Some(next) = tokens.get(i + 2)
+        && (next.kind == EditorTokenKind::Identifier || next.is("*") || next.is("&")) {
+        return None;
+    }
+
+    Some(EditorSymbol {
+        kind: keyword.text.clone(),
+        name: name.text.clone(),
+        line: name.line,
+        column: name.start,
+        depth,
+    })
+}
+
+/// impl<T> Display for Foo<T>, the header up to its brace, but not impl Trait in a type
+fn header_symbol(
+    tokens: &[EditorOutlineToken],
+    i: usize,
+    text: &[String],
+    depth: usize
+) -> Option<EditorSymbol> {
+    let keyword = &tokens[i];
+
+    if let Some(previous) = i.checked_sub(1).map(|p| &tokens[p])
+        && previous.kind == EditorTokenKind::Punctuation
+        && !["{", "}", ";", "]"].contains(&previous.text.as_str()) {
+        return None;
+    }
+
+    let header: String = text[keyword.line].chars().skip(keyword.start + keyword.text.chars().count()).collect();
+    let header = header.split('{').next().unwrap_or("");
+    let name = header.split(" where").next().unwrap_or("").trim();
+
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(EditorSymbol {
+        kind: keyword.text.clone(),
+        name: name.to_string(),
+        line: keyword.line,
+        column: keyword.start,
+        depth,
+    })
+}
+
+/// A function declared at `i`, `int main(..) {` or a prototype `int main(..);`,
+/// returns it, the index of the brace or ';' ending its header and whether it has a body
+fn function_declaration(
+    tokens: &[EditorOutlineToken],
+    i: usize,
+    depth: usize
+) -> Option<(EditorSymbol, usize, bool)> {
+    let name = &tokens[i];
+
+    if name.kind != EditorTokenKind::Identifier || !tokens.get(i + 1)?.is("(") {
+        return None;
+    }
+
+    // Step back over a qualified name, Foo::bar, Foo::~Foo
+    let mut before = i;
+
+    if before > 0 && tokens[before - 1].is("~") {
+        before -= 1;
+    }
+
+    let destructor = before < i;
+
+    while before >= 3
+        && tokens[before - 1].is(":") && tokens[before - 2].is(":")
+        && tokens[before - 3].kind == EditorTokenKind::Identifier {
+        before -= 3;
+    }
+
+    let previous = before.checked_sub(1).map(|p| &tokens[p]);
+
+    // A return type before the name, or the start of a statement for constructors
+    let after_type = destructor || previous.is_some_and(|p| match p.kind {
+        EditorTokenKind::Identifier => true,
+        EditorTokenKind::Keyword(class) => !matches!(class, EditorKeywordClass::ControlFlow | EditorKeywordClass::Misc),
+        EditorTokenKind::Punctuation => ["*", "&", ">"].contains(&p.text.as_str()),
+        _ => false,
+    });
+
+    let at_start = previous.is_none_or(|p| {
+        p.kind == EditorTokenKind::Punctuation && [";", "{", "}", ":"].contains(&p.text.as_str())
+    });
+
+    if !after_type && !at_start {
+        return None;
+    }
+
+    // Past the parameters and qualifiers, const, noexcept, throws E, -> T
+    let mut j = i + 1;
+    let mut parens = 0;
+
+    loop {
+        let token = tokens.get(j)?;
+
+        if token.is("(") {
+            parens += 1;
+        } else if token.is(")") {
+            parens -= 1;
+
+            if parens == 0 {
+                break;
+            }
+        }
+
+        j += 1;
+    }
+
+    j += 1;
+
+    while let Some(token) = tokens.get(j)
+        && (matches!(token.kind, EditorTokenKind::Identifier | EditorTokenKind::Keyword(_))
+            || [",", ".", "-", ">", "<", "&", "*"].iter().any(|p| token.is(p))) {
+        j += 1;
+    }
+
+    let token = tokens.get(j)?;
+
+    let end = if token.is("{") || (token.is(";") && after_type) {
+        j
+    } else if token.is(":") {
+        // Constructor initializer lists, Foo() : a(0) { .. }
+        let end = tokens[j..].iter().position(|t| t.is("{") || t.is(";"))? + j;
+
+        if !tokens[end].is("{") {
+            return None;
+        }
+
+        end
+    } else {
+        return None;
+    };
+
+    let symbol = EditorSymbol {
+        kind: "fn".to_string(),
+        name: tokens[before..=i].iter().map(|t| t.text.as_str()).collect(),
+        line: tokens[before].line,
+        column: tokens[before].start,
+        depth,
+    };
+
+    Some((symbol, end, tokens[end].is("{")))
+}
@@ -0,0 +1,232 @@
+// Infile search module, used by the <:f> directive.
+//
+// The query is matched as plain text by default, or as a
+// regex, case insensitive unless asked otherwise, optionally
+// only on whole words. The flags follow the directive:
+//      :f <q>      : Plain, case insensitive search
+//      :fc <q>     : Case sensitive
+//      :fw <q>     : Whole words only
+//      :fr <q>     : Regex search
+// Flags combine, e.g. :fcwr <q>.
+
+use regex::{Regex, RegexBuilder};
+
+use crate::text::editor_cursor::*;
+use crate::text::editor_substitute::EditorSubstitute;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EditorSearchFlags {
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+}
+
+/// A match, line and character range [start, end)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EditorSearchMatch {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub struct EditorSearch {
+    pub query: String,
+    pub flags: EditorSearchFlags,
+    pub regex: Option<Regex>,
+    pub matches: Vec<EditorSearchMatch>,
+    pub current: Option<usize>,
+    pub active: bool,
+    pub error: bool,
+    /// A confirm-each <:s> waiting on y/n/a/q
+    pub substitute: Option<EditorSubstitute>,
+}
+
+impl EditorSearch {
+    pub fn new() -> EditorSearch {
+        EditorSearch {
+            query: String::new(),
+            flags: EditorSearchFlags::default(),
+            regex: None,
+            matches: Vec::new(),
+            current: None,
+            active: false,
+            error: false,
+            substitute: None,
+        }
+    }
+
+    /// Set the query and flags, recompiling and recollecting matches only if they changed,
+    /// returns false if the query is an invalid regex
+    pub fn set_query(
+        &mut self,
+        query: &str,
+        flags: EditorSearchFlags,
+        text: &[String]
+    ) -> bool {
+        if self.active && query == self.query && flags == self.flags {
+            return !self.error;
+        }
+
+        self.query = query.to_string();
+        self.flags = flags;
+        self.active = !query.is_empty();
+        self.current = None;
+        self.regex = build_search_regex(query, flags);
+        self.error = self.active && self.regex.is_none();
+
+        self.refresh(text);
+
+        !self.error
+    }
+
+    /// Recollect all matches, the text may have changed since the last search
+    pub fn refresh(
+        &mut self,
+        text: &[String]
+    ) {
+        self.matches.clear();
+
+        let Some(regex) = &self.regex else {
+            self.current = None;
+            return;
+        };
+
+        for (line_index, line) in text.iter().enumerate() {
+            self.matches.extend(line_matches(regex, line, line_index));
+        }
+
+        if let Some(current) = self.current && current >= self.matches.len() {
+            self.current = None;
+        }
+    }
+
+    /// Move the cursor to the first match at or after it (forward), or before it,
+    /// wrapping around the file, returns false if there are no matches
+    pub fn goto_match(
+        &mut self,
+        cursor: &mut EditorCursor,
+        text: &[String],
+        forward: bool,
+        inclusive: bool
+    ) -> bool {
+        self.refresh(text);
+
+        if self.matches.is_empty() {
+            self.current = None;
+            return false;
+        }
+
+        let at = (cursor.xy.1, cursor.xy.0);
+
+        let index = if forward {
+            self.matches.iter()
+                .position(|m| if inclusive { (m.line, m.start) >= at } else { (m.line, m.start) > at })
+                .unwrap_or(0)
+        } else {
+            self.matches.iter()
+                .rposition(|m| (m.line, m.start) < at)
+                .unwrap_or(self.matches.len() - 1)
+        };
+
+        self.current = Some(index);
+        let found = self.matches[index];
+        cursor.xy = (found.start, found.line);
+
+        true
+    }
+
+    /// Match counter, e.g. "3/17", "-/17" before moving to a match, "-/0" when nothing matches
+    pub fn counter(&self) -> String {
+        match self.current {
+            Some(i) => format!("{}/{}", i + 1, self.matches.len()),
+            None => format!("-/{}", self.matches.len()),
+        }
+    }
+
+    /// Stop highlighting
+    pub fn clear(&mut self) {
+        self.active = false;
+        self.query.clear();
+        self.regex = None;
+        self.matches.clear();
+        self.current = None;
+        self.error = false;
+    }
+}
+
+/// Parse a search command token, e.g. "fcw", into its flags,
+/// returns None if it is not a search command
+pub fn parse_search_command(
+    command: &str
+) -> Option<EditorSearchFlags> {
+    let mut chars = command.chars();
+
+    if !matches!(chars.next(), Some('f') | Some('F')) {
+        return None;
+    }
+
+    let mut flags = EditorSearchFlags::default();
+
+    for c in chars {
+        match c.to_ascii_lowercase() {
+            'c' => flags.case_sensitive = true,
+            'w' => flags.whole_word = true,
+            'r' => flags.regex = true,
+            _ => return None,
+        }
+    }
+
+    Some(flags)
+}
+
+/// Split a console directive into the search flags and the query,
+/// the query is everything after the command, spaces included
+pub fn parse_search_directive(
+    directive: &str
+) -> Option<(EditorSearchFlags, &str)> {
+    let rest = directive.strip_prefix(':')?;
+    let (command, query) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    parse_search_command(command).map(|flags| (flags, query))
+}
+
+/// Compile a query, plain queries are escaped
+fn build_search_regex(
+    query: &str,
+    flags: EditorSearchFlags
+) -> Option<Regex> {
+    if query.is_empty() {
+        return None;
+    }
+
+    let mut pattern = if flags.regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+
+    if flags.whole_word {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!flags.case_sensitive)
+        .build()
+        .ok()
+}
+
+/// All non-empty matches of a line, in character indices
+pub fn line_matches(
+    regex: &Regex,
+    line: &str,
+    line_index: usize
+) -> Vec<EditorSearchMatch> {
+    regex.find_iter(line)
+        .filter(|m| !m.is_empty())
+        .map(|m| EditorSearchMatch {
+            line: line_index,
+            start: line[..m.start()].chars().count(),
+            end: line[..m.end()].chars().count(),
+        })
+        .collect()
+}
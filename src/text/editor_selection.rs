@@ -0,0 +1,145 @@
+// Selection module, the region between the
+// cursor's select_xy anchor and its current xy.
+//
+// All x indices are character indices, converted
+// to byte indices through char_to_byte.
+
+use crate::text::editor_cursor::*;
+
+/// Get the ordered (start, end) of the selection, clamped to the text,
+/// returns None if selection mode is off
+pub fn selection_range(
+    cursor: &EditorCursor,
+    text: &[String]
+) -> Option<((usize, usize), (usize, usize))> {
+    if !cursor.select_mode || text.is_empty() {
+        return None;
+    }
+
+    let clamp = |(x, y): (usize, usize)| {
+        let y = y.min(text.len() - 1);
+        (x.min(text[y].chars().count()), y)
+    };
+
+    let a = clamp(cursor.select_xy);
+    let b = clamp(cursor.xy);
+
+    // Order by line first, then by column
+    if (a.1, a.0) <= (b.1, b.0) {
+        Some((a, b))
+    } else {
+        Some((b, a))
+    }
+}
+
+/// Get the selected columns of a line as a (start, end) character range,
+/// end is None when the selection continues past the end of the line
+pub fn selection_columns_in_line(
+    range: ((usize, usize), (usize, usize)),
+    line_index: usize
+) -> Option<(usize, Option<usize>)> {
+    let (start, end) = range;
+
+    if line_index < start.1 || line_index > end.1 {
+        return None;
+    }
+
+    let from = if line_index == start.1 { start.0 } else { 0 };
+    let to = if line_index == end.1 { Some(end.0) } else { None };
+
+    Some((from, to))
+}
+
+/// Extract the selected text, lines are joined with '\n'
+pub fn selected_text(
+    cursor: &EditorCursor,
+    text: &[String]
+) -> String {
+    let Some((start, end)) = selection_range(cursor, text) else {
+        return String::new();
+    };
+
+    if start.1 == end.1 {
+        let line = &text[start.1];
+        return line[char_to_byte(line, start.0)..char_to_byte(line, end.0)].to_string();
+    }
+
+    let first = &text[start.1];
+    let last = &text[end.1];
+
+    let mut extract = first[char_to_byte(first, start.0)..].to_string();
+
+    for line in &text[start.1 + 1..end.1] {
+        extract.push('\n');
+        extract.push_str(line);
+    }
+
+    extract.push('\n');
+    extract.push_str(&last[..char_to_byte(last, end.0)]);
+
+    extract
+}
+
+/// Remove the selected text, move the cursor to where it started
+/// and leave selection mode, returns true if anything was selected
+pub fn delete_selection(
+    cursor: &mut EditorCursor,
+    text: &mut Vec<String>
+) -> bool {
+    let Some((start, end)) = selection_range(cursor, text) else {
+        return false;
+    };
+
+    let tail = {
+        let last = &text[end.1];
+        last[char_to_byte(last, end.0)..].to_string()
+    };
+
+    let first = &mut text[start.1];
+    let cut_idx = char_to_byte(first, start.0);
+    first.truncate(cut_idx);
+    first.push_str(&tail);
+
+    text.drain(start.1 + 1..=end.1);
+
+    cursor.xy = start;
+    cursor.select_mode = false;
+    cursor.select_xy = (0, 0);
+
+    true
+}
+
+/// Insert possibly multi-line text at the cursor, splitting lines
+/// on '\n', and move the cursor to the end of the inserted text
+pub fn insert_text_at_cursor(
+    cursor: &mut EditorCursor,
+    text: &mut Vec<String>,
+    insert: &str
+) {
+    if text.is_empty() {
+        text.push(String::new());
+    }
+
+    cursor.xy.1 = cursor.xy.1.min(text.len() - 1);
+
+    let line = &mut text[cursor.xy.1];
+    cursor.xy.0 = cursor.xy.0.min(line.chars().count());
+
+    let split_idx = char_to_byte(line, cursor.xy.0);
+    let tail = line.split_off(split_idx);
+
+    let mut parts = insert.split('\n').map(|p| p.strip_suffix('\r').unwrap_or(p));
+
+    // First part continues the cursor's line
+    line.push_str(parts.next().unwrap_or(""));
+    let mut last_len = line.chars().count();
+
+    for part in parts {
+        cursor.xy.1 += 1;
+        text.insert(cursor.xy.1, part.to_string());
+        last_len = part.chars().count();
+    }
+
+    text[cursor.xy.1].push_str(&tail);
+    cursor.xy.0 = last_len;
+}
@@ -0,0 +1,424 @@
+// Substitute module, used by the <:s> directive.
+//
+//      :s/<p>/<r>/<flags>      : Replace regex 'p' with 'r' in the selection, or the whole file without one
+//      :s%/<p>/<r>/<flags>     : Replace in the whole file
+//      :sN/<p>/<r>/<flags>     : Replace in line N
+//      :sN,M/<p>/<r>/<flags>   : Replace in lines N to M
+//
+// Flags:
+//      g : Replace every match of a line, not only the first
+//      i : Case insensitive
+//      c : Confirm each replacement, y/n/a/q in the console
+//
+// 'r' may use the capture groups of 'p' as $1 or \1, '\\' is a literal
+// backslash there, and '\/' stands for a literal '/' in both.
+
+use regex::{Regex, RegexBuilder};
+
+use crate::text::editor_cursor::*;
+use crate::text::editor_history::{EditorEditKind, EditorHistory};
+use crate::text::editor_search::EditorSearchMatch;
+use crate::text::editor_selection::selection_range;
+
+pub struct EditorSubstitute {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+    pub confirm: bool,
+    first_line: usize,
+    last_line: usize,
+    /// Character the scope starts from on the first line
+    start: usize,
+    /// Characters left out at the end of the last line, None if the scope covers it all
+    end_back: Option<usize>,
+    /// Line and byte the next match is looked for from
+    at: (usize, usize),
+    /// Line and byte range of the match waiting for confirmation
+    current: Option<(usize, usize, usize)>,
+    /// Text before the first replacement, so confirming undoes as one step
+    original: Vec<String>,
+    pub count: usize,
+}
+
+impl EditorSubstitute {
+    /// Parse a substitute directive, without the ':', returns the error name if it is invalid
+    pub fn new(
+        directive: &str,
+        cursor: &EditorCursor,
+        text: &[String]
+    ) -> Result<EditorSubstitute, &'static str> {
+        let rest = &directive[1..];
+        let Some((range, body)) = rest.split_once('/') else {
+            return Err("InvalidSubstitute");
+        };
+
+        let parts = split_delimited(body);
+
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err("InvalidSubstitute");
+        }
+
+        if parts[0].is_empty() {
+            return Err("NoIdentifierProvided");
+        }
+
+        let flags = parts.get(2).map(String::as_str).unwrap_or("");
+        let mut global = false;
+        let mut case_insensitive = false;
+        let mut confirm = false;
+
+        for c in flags.chars() {
+            match c {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                'c' => confirm = true,
+                _ => return Err("InvalidSubstituteFlag"),
+            }
+        }
+
+        let Ok(regex) = RegexBuilder::new(&parts[0]).case_insensitive(case_insensitive).build() else {
+            return Err("InvalidRegex");
+        };
+
+        let last = text.len().saturating_sub(1);
+
+        // Whole lines unless the scope is the selection
+        let (first_line, last_line, start, end) = match range {
+            "" => match selection_range(cursor, text) {
+                Some((from, to)) => (from.1, to.1, from.0, Some(to.0)),
+                None => (0, last, 0, None),
+            },
+            "%" => (0, last, 0, None),
+            _ => {
+                let (from, to) = range.split_once(',').unwrap_or((range, range));
+
+                let (Ok(from), Ok(to)) = (from.parse::<usize>(), to.parse::<usize>()) else {
+                    return Err("InvalidRange");
+                };
+
+                if from > to || to > last {
+                    return Err("InvalidRange");
+                }
+
+                (from, to, 0, None)
+            }
+        };
+
+        let end_back = end.map(|end| text.get(last_line).map_or(0, |l| l.chars().count()).saturating_sub(end));
+
+        Ok(EditorSubstitute {
+            regex,
+            replacement: convert_replacement(&parts[1]),
+            global,
+            confirm,
+            first_line,
+            last_line,
+            start,
+            end_back,
+            at: (first_line, 0),
+            current: None,
+            original: text.to_vec(),
+            count: 0,
+        })
+    }
+
+    /// Byte range of a line inside the scope
+    fn bounds(
+        &self,
+        line_index: usize,
+        line: &str
+    ) -> (usize, usize) {
+        let lo = if line_index == self.first_line { char_to_byte(line, self.start) } else { 0 };
+
+        let hi = match self.end_back {
+            Some(back) if line_index == self.last_line => {
+                char_to_byte(line, line.chars().count().saturating_sub(back))
+            }
+            _ => line.len(),
+        };
+
+        (lo, hi)
+    }
+
+    /// Move the cursor to the next match in scope, returns false when there are no more
+    pub fn next_match(
+        &mut self,
+        text: &[String],
+        cursor: &mut EditorCursor
+    ) -> bool {
+        while self.at.0 <= self.last_line && self.at.0 < text.len() {
+            let (line_index, from) = self.at;
+            let line = &text[line_index];
+            let (lo, hi) = self.bounds(line_index, line);
+            let from = from.max(lo);
+
+            if from <= hi
+                && let Some(m) = self.regex.find_at(line, from)
+                && m.end() <= hi
+            {
+                self.current = Some((line_index, m.start(), m.end()));
+                cursor.xy = (line[..m.start()].chars().count(), line_index);
+
+                return true;
+            }
+
+            self.at = (line_index + 1, 0);
+        }
+
+        self.current = None;
+
+        false
+    }
+
+    /// Replace the match waiting for confirmation
+    pub fn replace_current(
+        &mut self,
+        text: &mut [String]
+    ) {
+        let Some((line_index, start, end)) = self.current.take() else {
+            return;
+        };
+
+        let mut replacement = String::new();
+
+        if let Some(captures) = self.regex.captures_at(&text[line_index], start) {
+            captures.expand(&self.replacement, &mut replacement);
+        }
+
+        text[line_index].replace_range(start..end, &replacement);
+        self.count += 1;
+
+        self.step_past(&text[line_index], line_index, start + replacement.len(), start == end);
+    }
+
+    /// Leave the match waiting for confirmation as is
+    pub fn skip_current(
+        &mut self,
+        text: &[String]
+    ) {
+        let Some((line_index, start, end)) = self.current.take() else {
+            return;
+        };
+
+        self.step_past(&text[line_index], line_index, end, start == end);
+    }
+
+    /// Continue after a handled match, empty matches step over a character so they aren't found again
+    fn step_past(
+        &mut self,
+        line: &str,
+        line_index: usize,
+        end: usize,
+        empty: bool
+    ) {
+        if !self.global {
+            self.at = (line_index + 1, 0);
+            return;
+        }
+
+        self.at = if !empty {
+            (line_index, end)
+        } else {
+            match line[end..].chars().next() {
+                Some(c) => (line_index, end + c.len_utf8()),
+                None => (line_index + 1, 0),
+            }
+        };
+    }
+
+    /// Replace every remaining match, the cursor is left on the last one
+    pub fn replace_all(
+        &mut self,
+        text: &mut [String],
+        cursor: &mut EditorCursor
+    ) {
+        if self.current.is_some() {
+            self.replace_current(text);
+        }
+
+        while self.next_match(text, cursor) {
+            self.replace_current(text);
+        }
+    }
+
+    /// The match waiting for confirmation, in character indices
+    pub fn current_match(
+        &self,
+        text: &[String]
+    ) -> Option<EditorSearchMatch> {
+        let (line_index, start, end) = self.current?;
+        let line = text.get(line_index)?;
+
+        Some(EditorSearchMatch {
+            line: line_index,
+            start: line[..start].chars().count(),
+            end: line[..end].chars().count(),
+        })
+    }
+
+    /// Record the confirmed replacements as one undo step, returns the replacement count
+    pub fn finish(
+        self,
+        text: &mut Vec<String>,
+        cursor: &mut EditorCursor,
+        history: &mut EditorHistory
+    ) -> usize {
+        if self.count > 0 {
+            let replaced = std::mem::replace(text, self.original);
+
+            history.begin(text, cursor, 0, text.len(), EditorEditKind::Directive);
+            *text = replaced;
+            history.end(text, cursor);
+        }
+
+        self.count
+    }
+}
+
+/// Check if a directive command is a substitute, e.g. "s/a/b/g" or "s3,8/a/b"
+pub fn is_substitute_command(
+    command: &str
+) -> bool {
+    let Some(rest) = command.strip_prefix(['s', 'S']) else {
+        return false;
+    };
+
+    let Some((range, _)) = rest.split_once('/') else {
+        return false;
+    };
+
+    range == "%" || range.chars().all(|c| c.is_ascii_digit() || c == ',')
+}
+
+/// Split on '/', '\/' is kept as a literal '/', other escapes as they are
+fn split_delimited(
+    s: &str
+) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&'/') {
+            chars.next();
+            parts.last_mut().unwrap().push('/');
+        } else if c == '\\' && let Some(escaped) = chars.next() {
+            // \\/ is an escaped backslash, then the delimiter
+            parts.last_mut().unwrap().push(c);
+            parts.last_mut().unwrap().push(escaped);
+        } else if c == '/' {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+
+    parts
+}
+
+/// Turn \1 style group references into the regex crate's ${1}, \\ into a backslash
+fn convert_replacement(
+    replacement: &str
+) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' && let Some(d) = chars.peek().copied() && d.is_ascii_digit() {
+            chars.next();
+            converted.push_str(&format!("${{{}}}", d));
+        } else if c == '\\' && chars.peek() == Some(&'\\') {
+            chars.next();
+            converted.push(c);
+        } else {
+            converted.push(c);
+        }
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(
+        text: &[&str]
+    ) -> Vec<String> {
+        text.iter().map(|l| l.to_string()).collect()
+    }
+
+    /// Run a substitute directive on `text` without confirmation
+    fn substitute(
+        directive: &str,
+        text: &[&str]
+    ) -> Result<(Vec<String>, usize), &'static str> {
+        let mut text = lines(text);
+        let mut cursor = EditorCursor::new();
+        let mut substitute = EditorSubstitute::new(directive, &cursor, &text)?;
+
+        substitute.replace_all(&mut text, &mut cursor);
+        Ok((text, substitute.count))
+    }
+
+    #[test]
+    fn ranges() {
+        let text = ["a a", "a a", "a a"];
+
+        assert_eq!(substitute("s/a/b/", &text), Ok((lines(&["b a", "b a", "b a"]), 3)));
+        assert_eq!(substitute("s%/a/b/g", &text), Ok((lines(&["b b", "b b", "b b"]), 6)));
+        assert_eq!(substitute("s1/a/b/", &text), Ok((lines(&["a a", "b a", "a a"]), 1)));
+        assert_eq!(substitute("s1,2/a/b/g", &text), Ok((lines(&["a a", "b b", "b b"]), 4)));
+
+        assert_eq!(substitute("s2,1/a/b/", &text).err(), Some("InvalidRange"));
+        assert_eq!(substitute("s1,3/a/b/", &text).err(), Some("InvalidRange"));
+        assert_eq!(substitute("sx/a/b/", &text).err(), Some("InvalidRange"));
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(substitute("s/A/b/gi", &["a A a"]), Ok((lines(&["b b b"]), 3)));
+        assert_eq!(substitute("s/A/b/", &["a A a"]), Ok((lines(&["a b a"]), 1)));
+        assert_eq!(substitute("s/a/b/x", &["a"]).err(), Some("InvalidSubstituteFlag"));
+        assert_eq!(substitute("s//b/", &["a"]).err(), Some("NoIdentifierProvided"));
+        assert_eq!(substitute("s/(/b/", &["a"]).err(), Some("InvalidRegex"));
+    }
+
+    #[test]
+    fn escaped_delimiters() {
+        assert_eq!(substitute(r"s/a\/b/c\/d/", &["a/b"]), Ok((lines(&["c/d"]), 1)));
+
+        // An escaped backslash before the delimiter ends the pattern
+        assert_eq!(substitute(r"s/a\\/b/", &[r"a\"]), Ok((lines(&["b"]), 1)));
+    }
+
+    #[test]
+    fn group_references() {
+        assert_eq!(substitute(r"s/(\w+)=(\w+)/\2=\1/", &["a=b"]), Ok((lines(&["b=a"]), 1)));
+        assert_eq!(substitute(r"s/(\w+)=(\w+)/$2=$1/", &["a=b"]), Ok((lines(&["b=a"]), 1)));
+
+        // \\1 is a backslash and a 1, not the group
+        assert_eq!(substitute(r"s/(a)/\\1/", &["a"]), Ok((lines(&[r"\1"]), 1)));
+        assert_eq!(convert_replacement(r"\1\\1\\\1"), r"${1}\1\${1}");
+    }
+
+    #[test]
+    fn confirmed_replacements_undo_as_one_step() {
+        let mut text = lines(&["a", "b", "a"]);
+        let mut cursor = EditorCursor::new();
+        let mut history = EditorHistory::new();
+        let mut substitute = EditorSubstitute::new("s/a/c/c", &cursor, &text).unwrap();
+
+        assert!(substitute.confirm);
+
+        while substitute.next_match(&text, &mut cursor) {
+            substitute.replace_current(&mut text);
+        }
+
+        assert_eq!(substitute.finish(&mut text, &mut cursor, &mut history), 2);
+        assert_eq!(text, lines(&["c", "b", "c"]));
+        assert_eq!(history.undo_stack.len(), 1);
+
+        history.undo(&mut text, &mut cursor);
+        assert_eq!(text, lines(&["a", "b", "a"]));
+    }
+}
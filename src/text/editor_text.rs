@@ -7,11 +7,17 @@
 
 use macroquad::prelude::*;
 
+use crate::console::editor_build::EditorSeverity;
 use crate::console::editor_console::*;
+use crate::options::editor_options::EditorOptions;
 use crate::options::editor_pallete::*;
+use crate::text::editor_changes::*;
 use crate::text::editor_cursor::*;
 use crate::text::editor_input::*;
 use crate::text::editor_language_manager::EditorLanguageKeywords;
+use crate::text::editor_lexer::*;
+use crate::text::editor_search::*;
+use crate::text::editor_selection::*;
 use crate::text::editor_text_stylizer::*;
 use crate::camera::editor_camera::*;
 
@@ -25,23 +31,12 @@ pub const FILE_LINE_NUMBER_X_MARGIN: f32 = 5.0;
 pub const FILE_LINE_NUMBER_Y_MARGIN: f32 = 26.0;
 
 pub const FILE_TEXT_X_MARGIN: f32 = 50.0;
-pub const FILE_TEXT_Y_MARGIN: f32 = 80.0;
 
-/// Find a word in the text 
-/// and move the cursor there
-/// return true if found, false if not
-pub fn find_word_in_text(
-    word: &str,
-    text: &Vec<String>,
-    cursor: &mut EditorCursor
-) -> bool {
-    if let Some(line_index) = text.iter().position(|line| line.contains(word)) {
-        cursor.xy.1 = line_index;
-        true
-    } else {
-        false
-    }
-}
+pub const FILE_MARK_X_MARGIN: f32 = 12.0;
+pub const FILE_CHANGE_MARKER_WIDTH: f32 = 3.0;
+pub const FILE_SQUIGGLE_STEP: f32 = 3.0;
+pub const MARK_GLYPH: &str = ">";
+pub const FILE_TEXT_Y_MARGIN: f32 = 80.0;
 
 /// All around draw function for the editor text
 pub fn draw_file_text(
@@ -50,8 +45,15 @@ pub fn draw_file_text(
     gts: &mut EditorGeneralTextStylizer,
     console: &EditorConsole,
     camera: &mut EditorCamera,
-    elk: &EditorLanguageKeywords
+    elk: &EditorLanguageKeywords,
+    search: &EditorSearch,
+    lex_cache: &mut EditorLexCache,
+    ops: &EditorOptions,
+    changes: &EditorGitChanges,
+    diagnostics: &[(usize, EditorSeverity)]
 ) {
+    let pal = pallete();
+
     let text_y_offset = 25.0;
 
     let start_x = FILE_TEXT_X_MARGIN;
@@ -62,7 +64,7 @@ pub fn draw_file_text(
     // let cam_left = camera.offset_x;
     // let cam_right = camera.offset_x + screen_width();
     let cam_top = camera.offset_y;
-    let cam_bottom = camera.offset_y + screen_height();
+    let cam_bottom = camera.offset_y + camera.camera_h;
 
     // Draw cursor
     if !console.mode && cursor.xy.1 < text.len() {
@@ -71,10 +73,10 @@ pub fn draw_file_text(
         let prefix = &line[..byte_idx];
     
         let visual_prefix = prefix.replace("\t", TAB_PATTERN);
-        let text_before_cursor = measure_text(&visual_prefix, Some(&gts.font), gts.font_size, 1.0);
+        let text_before_cursor = gts.measure(&visual_prefix);
     
         // Target location to draw
-        let logical_x = start_x + line_start_fix + text_before_cursor.width;
+        let logical_x = start_x + line_start_fix + text_before_cursor;
         let logical_y = start_y + cursor.xy.1 as f32 * line_spacing + text_y_offset;
         
         // Smooth animation step, via interpolation
@@ -97,15 +99,21 @@ pub fn draw_file_text(
         
         cursor.draw_cursor_line(cursor_line_draw_x, cursor_line_draw_y, gts.font_size as f32);
 
-        draw_rectangle(draw_x, draw_y - gts.font_size as f32 + CURSOR_HEIGHT, cursor_width, gts.font_size as f32, CURSOR_COLOR);
+        draw_rectangle(draw_x, draw_y - gts.font_size as f32 + CURSOR_HEIGHT, cursor_width, gts.font_size as f32, pal.cursor);
     }
 
     // Determine visible lines
     let first_line = ((cam_top - start_y) / line_spacing).max(0.0) as usize;
     let last_line = ((cam_bottom - start_y) / line_spacing).min(text.len() as f32 - 1.0) as usize;
 
-    let mut in_string = false;
-    let mut in_block_comment = false;
+    // Lines above the screen are lexed too, they may open a block comment or string
+    lex_cache.update(text, last_line, elk);
+
+    let selection = selection_range(cursor, text);
+
+    // Current search match, and the match a confirm-each substitute waits on
+    let current = search.current.and_then(|i| search.matches.get(i)).copied();
+    let pending = search.substitute.as_ref().and_then(|s| s.current_match(text));
 
     if !text.is_empty() {
         for line_index in first_line..=last_line {
@@ -113,130 +121,162 @@ pub fn draw_file_text(
             let y = start_y + line_index as f32 * line_spacing;
             let mut x = start_x + line_start_fix;
 
-            // CRITICAL FIX: Replace tabs BEFORE processing
-            let visual_line = line.replace("\t", TAB_PATTERN);
-            
-            let mut chars = visual_line.chars().peekable();
-            while let Some(&c) = chars.peek() {
-                let mut token = String::new();
-                let color: Color;
-
-                if in_block_comment {
-                    while let Some(ch) = chars.next() {
-                        token.push(ch);
-                        if ch == '*' && chars.peek() == Some(&'/') {
-                            token.push(chars.next().unwrap());
-                            in_block_comment = false;
-                            break;
-                        }
-                    }
-                    color = COMMENT_COLOR;
-                } else if in_string {
-                    while let Some(ch) = chars.next() {
-                        token.push(ch);
-                        if ch == '"' && !token.ends_with("\\\"") {
-                            in_string = false;
-                            break;
-                        }
+            // Selection highlight, drawn under the text
+            if let Some((from, to)) = selection.and_then(|range| selection_columns_in_line(range, line_index)) {
+                let from_prefix = line[..char_to_byte(line, from)].replace("\t", TAB_PATTERN);
+                let from_w = gts.measure(&from_prefix);
+
+                // Selections continuing to the next line also cover the line break
+                let to_w = match to {
+                    Some(to) => {
+                        let to_prefix = line[..char_to_byte(line, to)].replace("\t", TAB_PATTERN);
+                        gts.measure(&to_prefix)
                     }
-                    color = STRING_LITERAL_COLOR;
-                } else {
-                    match c {
-                        '/' => {
-                            chars.next();
-                            if chars.peek() == Some(&'/') {
-                                chars.next();
-                                token.push_str("//");
-                                token.extend(chars.by_ref());
-                                color = COMMENT_COLOR;
-                            } else if chars.peek() == Some(&'*') {
-                                chars.next();
-                                token.push_str("/*");
-                                in_block_comment = true;
-                                color = COMMENT_COLOR;
-                            } else {
-                                token.push('/');
-                                color = PUNCTUATION_COLOR;
-                            }
-                        }
-                        '"' => {
-                            chars.next();
-                            token.push('"');
-                            in_string = true;
-                            color = STRING_LITERAL_COLOR;
-                        }
-                        '#' => {
-                            while let Some(&ch) = chars.peek() {
-                                if ch.is_whitespace() { break; }
-                                token.push(chars.next().unwrap());
-                            }
-                            color = MACRO_COLOR;
-                        }
-                        c if c.is_whitespace() => {
-                            while let Some(&ch) = chars.peek() {
-                                if !ch.is_whitespace() { break; }
-                                token.push(chars.next().unwrap());
-                            }
-                            color = IDENTIFIER_COLOR;
-                        }
-                        c if c.is_ascii_digit() => {
-                            while let Some(&ch) = chars.peek() {
-                                if !(ch.is_ascii_digit() || ch == '.' || ch == 'f' || ch == 'F' || ch == '-') { break; }
-                                token.push(chars.next().unwrap());
-                            }
-                            color = NUMBER_LITERAL_COLOR;
-                        }
-                        c if !c.is_alphanumeric() && c != '_' => {
-                            token.push(chars.next().unwrap());
-                            color = PUNCTUATION_COLOR;
-                        }
-                        _ => {
-                            while let Some(&ch) = chars.peek() {
-                                if !ch.is_alphanumeric() && ch != '_' { break; }
-                                token.push(chars.next().unwrap());
-                            }
-                            let clean = token.trim_matches(|c: char| !c.is_alphanumeric() && c != '_');
-                            color = gts.calibrate_string_color(clean, &elk);
-                        }
+                    None => {
+                        let visual = format!("{} ", line.replace("\t", TAB_PATTERN));
+                        gts.measure(&visual)
                     }
-                }
+                };
+
+                let (sx, sy) = camera.world_to_screen(x + from_w, y + text_y_offset);
+                draw_rectangle(sx, sy - gts.font_size as f32 + CURSOR_HEIGHT, to_w - from_w, gts.font_size as f32, pal.selection);
+            }
 
-                let width = measure_text(&token, Some(&gts.font), gts.font_size, 1.0).width;
+            // Search matches, matched live so edits don't leave stale highlights
+            let mut line_hits = search.regex.as_ref().map(|r| line_matches(r, line, line_index)).unwrap_or_default();
+            line_hits.extend(pending.filter(|m| m.line == line_index));
+
+            for m in line_hits {
+                let from_prefix = line[..char_to_byte(line, m.start)].replace("\t", TAB_PATTERN);
+                let to_prefix = line[..char_to_byte(line, m.end)].replace("\t", TAB_PATTERN);
+                let from_w = gts.measure(&from_prefix);
+                let to_w = gts.measure(&to_prefix);
+
+                let color = if current == Some(m) || pending == Some(m) { pal.search_current } else { pal.search_match };
+
+                let (sx, sy) = camera.world_to_screen(x + from_w, y + text_y_offset);
+                draw_rectangle(sx, sy - gts.font_size as f32 + CURSOR_HEIGHT, to_w - from_w, gts.font_size as f32, color);
+            }
+
+            // Byte offset of every char, tokens are char indexed
+            let offsets: Vec<usize> = line.char_indices().map(|(b, _)| b).chain([line.len()]).collect();
+
+            for t in lex_cache.tokens(line_index) {
+                // CRITICAL FIX: Replace tabs BEFORE drawing
+                let token = line[offsets[t.start]..offsets[t.end]].replace("\t", TAB_PATTERN);
+                let token = token.as_str();
+                let color = gts.token_color(t.kind, ops);
+
+                let width = gts.measure(token);
                 let (sx, sy) = camera.world_to_screen(x, y + text_y_offset);
                 
                 gts.color = color;
-                gts.draw(&token, sx, sy);
+                gts.draw(token, sx, sy);
                 
                 x += width;
             }
+
+            // Build and language server diagnostics, a squiggle under the line from its indentation on
+            if let Some((_, severity)) = diagnostics.iter().find(|(l, _)| *l == line_index) {
+                let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+                let from = start_x + line_start_fix + gts.measure(&indent.replace("\t", TAB_PATTERN));
+                let to = x.max(from + gts.measure(" "));
+
+                let color = if *severity == EditorSeverity::Error { pal.diagnostic_error } else { pal.diagnostic_warning };
+                let (sx, sy) = camera.world_to_screen(from, y + text_y_offset);
+                let (ex, _) = camera.world_to_screen(to, y + text_y_offset);
+
+                draw_squiggle(sx, ex, sy + CURSOR_HEIGHT + 1.0, color);
+            }
         }
     }
 
     // Sidebar
     let sidebar_width = start_x + line_start_fix - 5.0;
-    draw_rectangle(0.0, 0.0, sidebar_width, screen_height(), COMPOSITE_TYPE_COLOR);
-    draw_rectangle(0.0, 0.0, sidebar_width - 1.0, screen_height(), BACKGROUND_COLOR);
+    draw_rectangle(0.0, 0.0, sidebar_width, screen_height(), pal.composite_type);
+    draw_rectangle(0.0, 0.0, sidebar_width - 1.0, screen_height(), pal.background);
 
     // Line numbers
-    gts.color = CURSOR_COLOR;
+    gts.color = pal.cursor;
     for i in first_line..=last_line {
         let line_y_world = 1.1 * FILE_TEXT_X_MARGIN + FILE_LINE_NUMBER_Y_MARGIN + gts.font_size as f32 * i as f32 + text_y_offset;
         let screen_y = line_y_world - camera.offset_y;
+
+        // Lines with diagnostics have their number in the diagnostic's colour
+        gts.color = match diagnostics.iter().find(|(l, _)| *l == i) {
+            Some((_, EditorSeverity::Error)) => pal.diagnostic_error,
+            Some(_) => pal.diagnostic_warning,
+            None => pal.cursor,
+        };
+
         gts.draw(&i.to_string(), FILE_LINE_NUMBER_X_MARGIN, screen_y);
+        gts.color = pal.cursor;
+
+        // Changes since HEAD, a bar along the line or a notch under it for deleted lines
+        let marker_x = sidebar_width - FILE_CHANGE_MARKER_WIDTH - 1.0;
+
+        match changes.line_change(i) {
+            Some(EditorLineChange::Added) => {
+                draw_rectangle(marker_x, screen_y - gts.font_size as f32 + CURSOR_HEIGHT, FILE_CHANGE_MARKER_WIDTH, gts.font_size as f32, pal.git_added);
+            }
+
+            Some(EditorLineChange::Modified) => {
+                draw_rectangle(marker_x, screen_y - gts.font_size as f32 + CURSOR_HEIGHT, FILE_CHANGE_MARKER_WIDTH, gts.font_size as f32, pal.git_modified);
+            }
+
+            Some(EditorLineChange::Deleted) => {
+                draw_rectangle(marker_x - FILE_CHANGE_MARKER_WIDTH, screen_y + CURSOR_HEIGHT - FILE_CHANGE_MARKER_WIDTH, FILE_CHANGE_MARKER_WIDTH * 2.0, FILE_CHANGE_MARKER_WIDTH, pal.git_deleted);
+            }
+
+            None => {}
+        }
+
+        // Marked lines
+        if cursor.marks.iter().any(|m| m.1 == i) {
+            gts.color = pal.mark;
+            gts.draw(MARK_GLYPH, sidebar_width - FILE_MARK_X_MARGIN, screen_y);
+            gts.color = pal.cursor;
+        }
     }
 
     // Top bar
     let top_bar_height = MODE_Y_MARGIN + MODE_FONT_SIZE + text_y_offset;
-    draw_rectangle(0.0, 0.0, screen_width(), top_bar_height + 1.0, COMPOSITE_TYPE_COLOR);
-    draw_rectangle(0.0, 0.0, screen_width(), top_bar_height, BACKGROUND_COLOR);
+    draw_rectangle(0.0, 0.0, screen_width(), top_bar_height + 1.0, pal.composite_type);
+    draw_rectangle(0.0, 0.0, screen_width(), top_bar_height, pal.background);
 
-    // Draw cursor position
+    // Draw cursor position, and the search match counter
     if !console.mode {
-        let cursor_idx = format!("Ln {}, Col {}", cursor.xy.1, cursor.xy.0);
-        gts.color = CONSOLE_TEXT_COLOR;
+        let mut cursor_idx = format!("Ln {}, Col {}", cursor.xy.1, cursor.xy.0);
+
+        if search.active {
+            cursor_idx = format!("{}  Match {}", cursor_idx, search.counter());
+        }
+
+        gts.color = pal.console_text;
         let previous_size = gts.font_size;
         gts.font_size = 30; // Remains the same.
         gts.draw(&cursor_idx, MODE_Y_OFFSET, MODE_FONT_SIZE + MODE_Y_MARGIN + MODE_Y_OFFSET);
         gts.font_size = previous_size;
     }
 }
+
+/// A zigzag line from `x0` to `x1`, under diagnostics
+fn draw_squiggle(
+    x0: f32,
+    x1: f32,
+    y: f32,
+    color: Color
+) {
+    let mut x = x0;
+    let mut up = true;
+
+    while x < x1 {
+        let next = (x + FILE_SQUIGGLE_STEP).min(x1);
+        let (from_y, to_y) = if up { (y, y - FILE_SQUIGGLE_STEP / 2.0) } else { (y - FILE_SQUIGGLE_STEP / 2.0, y) };
+
+        draw_line(x, from_y, next, to_y, 1.0, color);
+
+        x = next;
+        up = !up;
+    }
+}
@@ -1,12 +1,19 @@
 // Stylizer for general text in the editor
+//
+// Text is drawn in runs, each with the first font having its glyphs,
+// the editor font then the fallback chain. Measure text with `measure`
+// so widths agree with what is drawn.
 
 use macroquad::prelude::*;
 
-use crate::text::editor_language_manager::*;
+use crate::options::editor_fonts::*;
+use crate::options::editor_options::EditorOptions;
+use crate::text::editor_lexer::*;
 use crate::options::editor_pallete::*;
 
 pub struct EditorGeneralTextStylizer {
-    pub font: Font,
+    pub font: EditorFont,
+    pub fallbacks: Vec<EditorFont>,
     pub font_size: u16,
     pub color: Color,
 }
@@ -14,34 +21,100 @@ pub struct EditorGeneralTextStylizer {
 impl EditorGeneralTextStylizer {
     pub async fn new() -> EditorGeneralTextStylizer {
         EditorGeneralTextStylizer {
-            font: load_ttf_font("assets/font/UbuntuMono-R.ttf").await.unwrap(),
+            font: load_font(DEFAULT_FONT).unwrap(),
+            fallbacks: Vec::new(),
             font_size: 18,
             color: WHITE,
         }
     }
 
-    /// Calibrate the color of a token
-    pub fn calibrate_string_color(
+    /// The font drawing `c`, the editor font if no font has it
+    fn font_for(
         &self,
-        string: &str,
-        elk: &EditorLanguageKeywords
+        c: char
+    ) -> &Font {
+        if c.is_whitespace() || self.font.has_glyph(c) {
+            return &self.font.font;
+        }
+
+        self.fallbacks.iter()
+            .find(|fallback| fallback.has_glyph(c))
+            .map_or(&self.font.font, |fallback| &fallback.font)
+    }
+
+    /// Split `text` into runs drawn with the same font
+    fn runs<'a>(
+        &'a self,
+        text: &'a str
+    ) -> Vec<(&'a Font, &'a str)> {
+        let mut runs: Vec<(&Font, &str)> = Vec::new();
+        let mut start = 0;
+        let mut current: Option<&Font> = None;
+
+        for (i, c) in text.char_indices() {
+            let font = self.font_for(c);
+
+            if let Some(previous) = current && !std::ptr::eq(previous, font) {
+                runs.push((previous, &text[start..i]));
+                start = i;
+            }
+
+            current = Some(font);
+        }
+
+        if let Some(font) = current {
+            runs.push((font, &text[start..]));
+        }
+
+        runs
+    }
+
+    /// Width of `text` as drawn by `draw`
+    pub fn measure(
+        &self,
+        text: &str
+    ) -> f32 {
+        self.runs(text)
+            .into_iter()
+            .map(|(font, run)| measure_text(run, Some(font), self.font_size, 1.0).width)
+            .sum()
+    }
+
+    /// Calibrate the color of a token, classes with highlighting off are drawn as identifiers
+    pub fn token_color(
+        &self,
+        kind: EditorTokenKind,
+        ops: &EditorOptions
     ) -> Color {
-        if elk.control_flow.contains(&string) {
-            return CONTROL_FLOW_COLOR;
-        } else if elk.type_qualifiers.contains(&string) {
-            return TYPE_QUALIFIER_COLOR;
-        } else if elk.composite_types.contains(&string) {
-            return COMPOSITE_TYPE_COLOR;
-        } else if elk.storage_class.contains(&string) {
-            return STORAGE_CLASS_COLOR;
-        } else if elk.misc.contains(&string) {
-            return MISC_COLOR;
-        } else if elk.data_types.contains(&string) {
-            return DATA_TYPE_COLOR;
-        } else if string.chars().all(|c| c.is_ascii_digit()) {
-            return NUMBER_LITERAL_COLOR;
-        } else {
-            return IDENTIFIER_COLOR;
+        let pal = pallete();
+
+        let class = match kind {
+            EditorTokenKind::Keyword(_) | EditorTokenKind::Lifetime => "keywords",
+            EditorTokenKind::Number => "numbers",
+            EditorTokenKind::String => "strings",
+            EditorTokenKind::Comment => "comments",
+            EditorTokenKind::Macro => "macros",
+            EditorTokenKind::Punctuation => "punctuation",
+            EditorTokenKind::Whitespace | EditorTokenKind::Identifier => return pal.identifier,
+        };
+
+        if !ops.is_highlighted(class) {
+            return pal.identifier;
+        }
+
+        match kind {
+            EditorTokenKind::Keyword(EditorKeywordClass::ControlFlow) => pal.control_flow,
+            EditorTokenKind::Keyword(EditorKeywordClass::TypeQualifier) => pal.type_qualifier,
+            EditorTokenKind::Keyword(EditorKeywordClass::CompositeType) => pal.composite_type,
+            EditorTokenKind::Keyword(EditorKeywordClass::StorageClass) => pal.storage_class,
+            EditorTokenKind::Keyword(EditorKeywordClass::Misc) => pal.misc,
+            EditorTokenKind::Keyword(EditorKeywordClass::DataType) => pal.data_type,
+            EditorTokenKind::Lifetime => pal.type_qualifier,
+            EditorTokenKind::Number => pal.number_literal,
+            EditorTokenKind::String => pal.string_literal,
+            EditorTokenKind::Comment => pal.comment,
+            EditorTokenKind::Macro => pal.macros,
+            _ => pal.punctuation,
         }
     }
 
@@ -51,7 +124,11 @@ impl EditorGeneralTextStylizer {
         x: f32,
         y: f32
     ){
-        draw_text_ex(text, x, y,
-            TextParams { font: Some(&self.font), font_size: self.font_size, color: self.color, ..Default::default() });
+        let mut x = x;
+
+        for (font, run) in self.runs(text) {
+            x += draw_text_ex(run, x, y,
+                TextParams { font: Some(font), font_size: self.font_size, color: self.color, ..Default::default() }).width;
+        }
     }
 }
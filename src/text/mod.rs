@@ -1,6 +1,16 @@
+pub mod editor_autocomplete;
+pub mod editor_buffer;
+pub mod editor_changes;
 pub mod editor_cursor;
+pub mod editor_history;
 pub mod editor_input;
 pub mod editor_text_stylizer;
 pub mod editor_text;
 pub mod editor_language_manager;
+pub mod editor_lexer;
+pub mod editor_marks;
+pub mod editor_outline;
+pub mod editor_search;
+pub mod editor_substitute;
 pub mod editor_clipboard;
+pub mod editor_selection;